@@ -0,0 +1,828 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::Encoding;
+use common::protocol::{FEATURE_NACK, PROTOCOL_VERSION};
+use common::schemas::{
+    Ack,
+    Cancel,
+    Cancellation,
+    Command,
+    Consistency,
+    ConsistencyKey,
+    ConsistencyValue,
+    DeliveryMode,
+    Event,
+    EventId,
+    EventLookup,
+    GetEvent,
+    Hello,
+    Message,
+    Metadata,
+    NewEvent,
+    NewEvents,
+    Predicate,
+    Query,
+    Receipt,
+    Register,
+    RegisterSchema,
+    Registration,
+    Reply,
+    Request,
+    SchemaRegistration,
+    Shutdown,
+};
+use failure::{Error, Fail, ResultExt};
+use futures::{Future, Poll, Sink, Stream, future};
+use futures::sync::{mpsc, oneshot};
+use serde_json::{Value, to_string_pretty};
+use tokio_core::reactor::Handle;
+use websocket::ClientBuilder;
+use websocket::async::{Client as WebsocketClient, TcpStream};
+use websocket::message::OwnedMessage;
+
+use consistency::ConsistencyTracker;
+use error::ErrorKind;
+use receipts::ReceiptTracker;
+
+/// PublishOptions contains the optional parts of a new event. The defaults use the next
+/// consistency value for the key and do not provide an idempotency key.
+#[derive(Clone, Debug, Default)]
+pub struct PublishOptions {
+    /// The id of the event that caused this event, usually the one being handled.
+    pub causation_id: Option<EventId>,
+    pub correlation_id: u64,
+    /// Free-form headers that are persisted and propagated with the event.
+    pub metadata: Metadata,
+    /// The version of the schema to validate the data against, the latest if not provided.
+    pub schema_version: Option<u32>,
+    /// An RFC 3339 timestamp that the event should not be propagated before.
+    pub deliver_at: Option<String>,
+    /// A number of seconds to wait before propagating the event.
+    pub delay: Option<u64>,
+    /// Let the event bus pick the next consistency value rather than this client.
+    pub implicit: bool,
+    /// Consistency values for other keys that must be current for the event to be accepted,
+    /// without advancing those keys.
+    pub expected_versions: Vec<Consistency>,
+    /// Events with the same idempotency key are only accepted once by the event bus, retries
+    /// receive a receipt with the `duplicate` status that refers to the original event.
+    pub idempotency_key: Option<String>,
+}
+
+/// RegisterOptions contains the optional parts of a registration. The defaults receive every
+/// event of the registered types, shared between the instances of the client type.
+#[derive(Clone, Debug, Default)]
+pub struct RegisterOptions {
+    /// Only events whose data satisfies every predicate are sent to this client type.
+    pub predicates: Vec<Predicate>,
+    /// Whether each event is sent to one instance of the client type or to all of them.
+    pub delivery_mode: DeliveryMode,
+}
+
+/// PendingReceipt is kept for each event that has been sent until the receipt for that event is
+/// received.
+#[derive(Debug)]
+struct PendingReceipt {
+    consistency: Consistency,
+    sender: oneshot::Sender<Receipt>,
+}
+
+/// State contains everything that is shared between the `BusClient` handles and the task that
+/// reads from the websocket connection.
+#[derive(Debug)]
+struct State {
+    consistency: ConsistencyTracker,
+    receipts: ReceiptTracker<PendingReceipt>,
+    /// Requests sent by this client are matched with their replies by `request_id`.
+    requests: ReceiptTracker<oneshot::Sender<Reply>>,
+    /// Registrations, queries, lookups, schema registrations and cancellations are matched with
+    /// their responses by the `message_id` that the event bus echoes.
+    registrations: ReceiptTracker<oneshot::Sender<Registration>>,
+    queries: ReceiptTracker<oneshot::Sender<Vec<Event>>>,
+    lookups: ReceiptTracker<oneshot::Sender<EventLookup>>,
+    schema_registrations: ReceiptTracker<oneshot::Sender<SchemaRegistration>>,
+    cancellations: ReceiptTracker<oneshot::Sender<Cancellation>>,
+    events: Option<mpsc::UnboundedSender<Event>>,
+    subscription: Option<mpsc::UnboundedReceiver<Event>>,
+    incoming_requests: Option<mpsc::UnboundedSender<Request>>,
+    request_subscription: Option<mpsc::UnboundedReceiver<Request>>,
+    /// The notice sent by the event bus if it is shutting down.
+    shutdown: Option<Shutdown>,
+    closed: bool,
+}
+
+impl State {
+    fn close(&mut self) {
+        self.closed = true;
+
+        // Dropping the senders fails any pending futures and ends the event stream.
+        self.events = None;
+        self.incoming_requests = None;
+        self.receipts.drain();
+        self.requests.drain();
+        self.registrations.drain();
+        self.queries.drain();
+        self.lookups.drain();
+        self.schema_registrations.drain();
+        self.cancellations.drain();
+    }
+}
+
+/// Events is the stream of events sent to this client by the event bus. Each event should be
+/// acknowledged with `BusClient::ack` once it has been processed.
+#[derive(Debug)]
+pub struct Events(mpsc::UnboundedReceiver<Event>);
+
+impl Stream for Events {
+    type Item = Event;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Event>, Error> {
+        self.0.poll().map_err(|_| Error::from(ErrorKind::ConnectionClosed))
+    }
+}
+
+/// Requests is the stream of requests sent to this client by other clients through the event
+/// bus. Each request should be answered with `BusClient::reply`.
+#[derive(Debug)]
+pub struct Requests(mpsc::UnboundedReceiver<Request>);
+
+impl Stream for Requests {
+    type Item = Request;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Request>, Error> {
+        self.0.poll().map_err(|_| Error::from(ErrorKind::ConnectionClosed))
+    }
+}
+
+/// BusClient is a handle to a connection to the event bus. It can be cheaply cloned and all
+/// clones share the same connection.
+#[derive(Clone, Debug)]
+pub struct BusClient {
+    state: Rc<RefCell<State>>,
+    outgoing: mpsc::UnboundedSender<OwnedMessage>,
+    encoding: Encoding,
+    features: Vec<String>,
+}
+
+impl BusClient {
+    /// Connect to the event bus and complete the handshake. The connection is driven by tasks
+    /// spawned on the provided handle.
+    pub fn connect(server_address: &str, handle: &Handle,
+                   preferred_encoding: Encoding) -> Box<Future<Item = Self, Error = Error>> {
+        let builder = match ClientBuilder::new(server_address) {
+            Ok(builder) => builder,
+            Err(e) => return Box::new(future::err(Error::from(
+                        e.context(ErrorKind::InvalidServerAddress)))),
+        };
+
+        let mut encodings = vec![String::from(preferred_encoding.name())];
+        if preferred_encoding != Encoding::Json {
+            encodings.push(String::from(Encoding::Json.name()));
+        }
+
+        let hello = Message::Hello(Hello {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: None,
+            encodings: encodings,
+            features: vec![String::from(FEATURE_NACK)],
+        });
+
+        // The hello is always sent as JSON, the negotiated encoding applies afterwards.
+        let frame = match Encoding::Json.encode(&hello) {
+            Ok(frame) => frame,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let connect = builder.async_connect_insecure(handle);
+        let handle = handle.clone();
+        Box::new(connect
+            .map_err(|e| Error::from(e.context(ErrorKind::WebsocketConnect)))
+            .and_then(move |(framed, _)| {
+                info!("connected to event bus, sending hello");
+                framed.send(frame).map_err(|e| Error::from(e.context(ErrorKind::WebsocketSend)))
+            })
+            .and_then(|framed| {
+                framed.into_future().map_err(|(e, _)| {
+                    Error::from(e.context(ErrorKind::WebsocketReceive))
+                })
+            })
+            .and_then(move |(frame, framed)| -> Result<Self, Error> {
+                let message: Message = match frame {
+                    Some(OwnedMessage::Text(m)) => Encoding::Json.decode(m.as_bytes()).context(
+                        ErrorKind::ParseMessageFromWebsockets)?,
+                    Some(_) => return Err(Error::from(ErrorKind::InvalidWebsocketMessageType)),
+                    None => return Err(Error::from(ErrorKind::ConnectionClosedDuringHandshake)),
+                };
+
+                let welcome = match message {
+                    Message::Welcome(welcome) => welcome,
+                    _ => return Err(Error::from(ErrorKind::InvalidWebsocketMessageType)),
+                };
+
+                if welcome.status != "accepted" {
+                    warn!("handshake refused by event bus: reason='{:?}'", welcome.reason);
+                    return Err(Error::from(ErrorKind::HandshakeRefused));
+                }
+
+                info!("handshake accepted by event bus: protocol_version='{}' encoding='{:?}' \
+                      features='{:?}'", welcome.protocol_version, welcome.encoding,
+                      welcome.features);
+                let encoding = welcome.encoding.as_ref()
+                    .and_then(|e| Encoding::from_name(e))
+                    .unwrap_or_default();
+                Ok(Self::start(framed, encoding, welcome.features, &handle))
+            }))
+    }
+
+    /// Create a client that sends its messages through `outgoing`, before any tasks are spawned
+    /// for the websocket connection.
+    fn new(outgoing: mpsc::UnboundedSender<OwnedMessage>, encoding: Encoding,
+           features: Vec<String>) -> Self {
+        let (events, subscription) = mpsc::unbounded();
+        let (incoming_requests, request_subscription) = mpsc::unbounded();
+
+        Self {
+            state: Rc::new(RefCell::new(State {
+                consistency: ConsistencyTracker::new(),
+                receipts: ReceiptTracker::new(),
+                requests: ReceiptTracker::new(),
+                registrations: ReceiptTracker::new(),
+                queries: ReceiptTracker::new(),
+                lookups: ReceiptTracker::new(),
+                schema_registrations: ReceiptTracker::new(),
+                cancellations: ReceiptTracker::new(),
+                events: Some(events),
+                subscription: Some(subscription),
+                incoming_requests: Some(incoming_requests),
+                request_subscription: Some(request_subscription),
+                shutdown: None,
+                closed: false,
+            })),
+            outgoing: outgoing,
+            encoding: encoding,
+            features: features,
+        }
+    }
+
+    /// Spawn the tasks that write to and read from the websocket connection once the handshake
+    /// is complete.
+    fn start(framed: WebsocketClient<TcpStream>, encoding: Encoding, features: Vec<String>,
+             handle: &Handle) -> Self {
+        let (sink, stream) = framed.split();
+        let (outgoing, outgoing_receiver) = mpsc::unbounded();
+        let client = Self::new(outgoing, encoding, features);
+
+
+        // Forward everything sent through the client handles to the websocket connection.
+        handle.spawn(sink
+            .sink_map_err(|e| Error::from(e.context(ErrorKind::WebsocketSend)))
+            .send_all(outgoing_receiver.map_err(|_| Error::from(ErrorKind::ConnectionClosed)))
+            .map(|_| ())
+            .map_err(|e| error!("sending message to event bus: error='{}'", e)));
+
+        let reader = client.clone();
+        handle.spawn(stream
+            .map_err(|e| Error::from(e.context(ErrorKind::WebsocketReceive)))
+            .for_each(move |frame| {
+                if let Err(e) = reader.process_frame(frame) {
+                    error!("processing message from event bus: error='{}'", e);
+                }
+                Ok(())
+            })
+            .then({
+                let state = client.state.clone();
+                move |result| -> Result<(), ()> {
+                    if let Err(e) = result {
+                        error!("receiving message from event bus: error='{}'", e);
+                    }
+
+                    info!("connection to event bus closed");
+                    state.borrow_mut().close();
+                    Ok(())
+                }
+            }));
+
+        client
+    }
+
+    /// Process an incoming message on the websocket connection.
+    fn process_frame(&self, frame: OwnedMessage) -> Result<(), Error> {
+        let message: Message = match frame {
+            OwnedMessage::Text(m) => Encoding::Json.decode(m.as_bytes()).context(
+                ErrorKind::ParseMessageFromWebsockets)?,
+            OwnedMessage::Binary(b) => self.encoding.decode(&b).context(
+                ErrorKind::ParseMessageFromWebsockets)?,
+            OwnedMessage::Ping(d) => return self.send_frame(OwnedMessage::Pong(d)),
+            OwnedMessage::Pong(_) | OwnedMessage::Close(_) => return Ok(()),
+        };
+
+        let message_type = message.message_type();
+        let mut state = self.state.borrow_mut();
+        match message {
+            Message::Event(event) => {
+                debug!("received event: event_type='{}'", event.event_type);
+                state.consistency.observe(event.consistency.key.clone(),
+                                          event.consistency.value.clone());
+
+                if let Some(ref events) = state.events {
+                    if events.unbounded_send(event).is_err() {
+                        warn!("event stream was dropped, event will not be acknowledged");
+                    }
+                }
+            },
+            Message::Receipts(receipts) => {
+                for receipt in receipts.receipts {
+                    let pending = resolve(&mut state.receipts, &receipt.message_id);
+
+                    match pending {
+                        Some(pending) => {
+                            debug!("matched receipt: message_id='{:?}' status='{}' \
+                                   event_id='{:?}'", receipt.message_id, receipt.status,
+                                   receipt.event_id);
+                            if receipt.status == "success" {
+                                // Prefer the consistency that the event bus assigned over what
+                                // we sent, they only differ for implicit consistency.
+                                let consistency = receipt.consistency.clone()
+                                    .unwrap_or(pending.consistency);
+                                state.consistency.observe(consistency.key, consistency.value);
+                            } else {
+                                // The event bus did not use the value we sent, so it is sent
+                                // again with the next event for the key.
+                                state.consistency.reject(pending.consistency.key,
+                                                         pending.consistency.value);
+                            }
+                            if pending.sender.send(receipt).is_err() {
+                                debug!("receipt future was dropped");
+                            }
+                        },
+                        None => warn!("receipt did not match a sent event: message_id='{:?}'",
+                                      receipt.message_id),
+                    }
+                }
+            },
+            Message::Registration(registration) => {
+                debug!("received registration: client_type='{}' message_id='{:?}'",
+                       registration.client_type, registration.message_id);
+                match resolve(&mut state.registrations, &registration.message_id) {
+                    Some(sender) => {
+                        if sender.send(registration).is_err() {
+                            debug!("registration future was dropped");
+                        }
+                    },
+                    None => warn!("registration did not match a sent register: \
+                                  message_id='{:?}'", registration.message_id),
+                }
+            },
+            Message::Rebuild(rebuild) => {
+                debug!("received rebuild: events='{}'", rebuild.events.len());
+                for event in rebuild.events.iter() {
+                    state.consistency.observe(event.consistency.key.clone(),
+                                              event.consistency.value.clone());
+                }
+
+                match resolve(&mut state.queries, &rebuild.message_id) {
+                    Some(sender) => {
+                        if sender.send(rebuild.events).is_err() {
+                            debug!("query future was dropped");
+                        }
+                    },
+                    None => warn!("rebuild did not match a sent query: message_id='{:?}'",
+                                  rebuild.message_id),
+                }
+            },
+            Message::EventLookup(lookup) => {
+                debug!("received event lookup: event_id='{}' found='{}'", lookup.event_id,
+                       lookup.event.is_some());
+                match resolve(&mut state.lookups, &lookup.message_id) {
+                    Some(sender) => {
+                        if sender.send(lookup).is_err() {
+                            debug!("event lookup future was dropped");
+                        }
+                    },
+                    None => warn!("event lookup did not match a sent lookup: event_id='{}' \
+                                  message_id='{:?}'", lookup.event_id, lookup.message_id),
+                }
+            },
+            Message::SchemaRegistration(registration) => {
+                debug!("received schema registration: event_type='{}' version='{}' \
+                       errors='{}'", registration.event_type, registration.version,
+                       registration.errors.len());
+                match resolve(&mut state.schema_registrations, &registration.message_id) {
+                    Some(sender) => {
+                        if sender.send(registration).is_err() {
+                            debug!("schema registration future was dropped");
+                        }
+                    },
+                    None => warn!("schema registration did not match a sent schema: \
+                                  message_id='{:?}'", registration.message_id),
+                }
+            },
+            Message::Cancellation(cancellation) => {
+                debug!("received cancellation: event_id='{}' cancelled='{}'",
+                       cancellation.event_id, cancellation.cancelled);
+                match resolve(&mut state.cancellations, &cancellation.message_id) {
+                    Some(sender) => {
+                        if sender.send(cancellation).is_err() {
+                            debug!("cancellation future was dropped");
+                        }
+                    },
+                    None => warn!("cancellation did not match a sent cancel: \
+                                  message_id='{:?}'", cancellation.message_id),
+                }
+            },
+            Message::Rejected(rejected) => {
+                // The event bus doesn't say which message was rejected, it closes the connection
+                // afterwards which fails any pending futures.
+                error!("message rejected by event bus: reason='{}' limit='{}' size='{}'",
+                       rejected.reason, rejected.limit, rejected.size);
+            },
+            Message::Shutdown(shutdown) => {
+                // The connection stays open until the deadline so that events that are being
+                // processed can still be acknowledged.
+                warn!("event bus is shutting down: reason='{}' deadline_ms='{}'",
+                      shutdown.reason, shutdown.deadline_ms);
+                state.shutdown = Some(shutdown);
+            },
+            Message::Request(request) => {
+                debug!("received request: request_id='{}' request_type='{}'",
+                       request.request_id, request.request_type);
+                if let Some(ref requests) = state.incoming_requests {
+                    if requests.unbounded_send(request).is_err() {
+                        debug!("request stream was dropped");
+                    }
+                }
+            },
+            Message::Reply(reply) => {
+                debug!("received reply: request_id='{}' error='{:?}'", reply.request_id,
+                       reply.error);
+                match state.requests.resolve(&reply.request_id) {
+                    Some(sender) => {
+                        if sender.send(reply).is_err() {
+                            debug!("reply future was dropped");
+                        }
+                    },
+                    None => warn!("received reply for unknown request: request_id='{}'",
+                                  reply.request_id),
+                }
+            },
+            // Any other messages are only ever sent from clients to the bus.
+            _ => {
+                warn!("received unexpected message from event bus: message_type='{}'",
+                      message_type);
+                return Err(Error::from(ErrorKind::InvalidWebsocketMessageType));
+            },
+        }
+
+        Ok(())
+    }
+
+    fn send_frame(&self, frame: OwnedMessage) -> Result<(), Error> {
+        self.outgoing.unbounded_send(frame).map_err(|_| Error::from(ErrorKind::ConnectionClosed))
+    }
+
+    fn send_message(&self, message: Message) -> Result<(), Error> {
+        if self.state.borrow().closed {
+            return Err(Error::from(ErrorKind::ConnectionClosed));
+        }
+
+        trace!("sending message: message_type='{}' message=\n{}", message.message_type(),
+               to_string_pretty(&message)?);
+        let frame = self.encoding.encode(&message)?;
+        self.send_frame(frame)
+    }
+
+    /// Check whether an optional protocol feature was negotiated during the handshake.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Get the notice sent by the event bus if it is shutting down. Once there is one, a new
+    /// connection should be made to another instance of the event bus.
+    pub fn shutdown_notice(&self) -> Option<Shutdown> {
+        self.state.borrow().shutdown.clone()
+    }
+
+    /// Get the latest consistency value seen for a key, either from an event, a receipt or a
+    /// rebuild.
+    pub fn consistency(&self, key: &ConsistencyKey) -> Option<ConsistencyValue> {
+        self.state.borrow().consistency.get(key).cloned()
+    }
+
+    /// Take the stream of events sent to this client. This can only be done once per connection,
+    /// events received before subscribing are buffered.
+    pub fn subscribe(&self) -> Result<Events, Error> {
+        self.state.borrow_mut().subscription.take()
+            .map(Events)
+            .ok_or(Error::from(ErrorKind::AlreadySubscribed))
+    }
+
+    /// Take the stream of requests sent to this client. This can only be done once per
+    /// connection, requests received before subscribing are buffered.
+    pub fn subscribe_requests(&self) -> Result<Requests, Error> {
+        self.state.borrow_mut().request_subscription.take()
+            .map(Requests)
+            .ok_or(Error::from(ErrorKind::AlreadySubscribed))
+    }
+
+    /// Register the type of this client and the event types that it is interested in. Use `*` to
+    /// receive all event types.
+    pub fn register(&self, client_type: &str,
+                    event_types: Vec<String>) -> Box<Future<Item = Registration, Error = Error>> {
+        self.register_with_options(client_type, event_types, RegisterOptions::default())
+    }
+
+    /// Register in the same way as `register`, with predicates on the events that are received
+    /// or a different delivery mode.
+    pub fn register_with_options(
+        &self, client_type: &str, event_types: Vec<String>, options: RegisterOptions
+    ) -> Box<Future<Item = Registration, Error = Error>> {
+        let message_id = self.state.borrow_mut().registrations.next_message_id();
+        let register = Message::Register(Register {
+            client_type: String::from(client_type),
+            event_types: event_types,
+            predicates: options.predicates,
+            delivery_mode: options.delivery_mode,
+            message_id: Some(message_id.clone()),
+        });
+
+        let registration = wait_for(self.send_message(register).map(|_| {
+            let (sender, receiver) = oneshot::channel();
+            self.state.borrow_mut().registrations.track(message_id, sender);
+            receiver
+        }));
+        Box::new(registration.and_then(|registration| {
+            match registration.error {
+                Some(error) => {
+                    warn!("registration refused: client_type='{}' error='{}'",
+                          registration.client_type, error);
+                    Err(Error::from(ErrorKind::RegistrationRefused))
+                },
+                None => Ok(registration),
+            }
+        }))
+    }
+
+    /// Publish a new event. Unless `options.implicit` is set, the consistency value is the next
+    /// value for the key as tracked by this client. The returned future resolves with the receipt
+    /// from the event bus.
+    pub fn publish(&self, event_type: &str, consistency_key: ConsistencyKey, data: Value,
+                   options: PublishOptions) -> Box<Future<Item = Receipt, Error = Error>> {
+        wait_for(self.send_new_event(None, event_type, consistency_key, data, options))
+    }
+
+    /// Publish a command that is only propagated to the given client type. Otherwise this
+    /// behaves the same as `publish`.
+    pub fn command(&self, client_type: &str, event_type: &str, consistency_key: ConsistencyKey,
+                   data: Value,
+                   options: PublishOptions) -> Box<Future<Item = Receipt, Error = Error>> {
+        wait_for(self.send_new_event(Some(client_type), event_type, consistency_key, data,
+                                     options))
+    }
+
+    fn send_new_event(&self, target: Option<&str>, event_type: &str,
+                      consistency_key: ConsistencyKey, data: Value,
+                      options: PublishOptions) -> Result<oneshot::Receiver<Receipt>, Error> {
+        let value = self.state.borrow_mut().consistency.next(consistency_key.clone(),
+                                                             options.implicit)?;
+        let consistency = Consistency { key: consistency_key, value: value };
+        let message_id = self.state.borrow_mut().receipts.next_message_id();
+
+        let event = NewEvent {
+            causation_id: options.causation_id,
+            consistency: consistency.clone(),
+            correlation_id: options.correlation_id,
+            data: data,
+            deliver_at: options.deliver_at,
+            delay: options.delay,
+            event_type: String::from(event_type),
+            metadata: options.metadata,
+            schema_version: options.schema_version,
+            message_id: Some(message_id.clone()),
+            expected_versions: options.expected_versions,
+            idempotency_key: options.idempotency_key,
+        };
+        let message = match target {
+            Some(client_type) => Message::Command(Command {
+                client_type: String::from(client_type),
+                command: event,
+            }),
+            None => Message::NewEvents(NewEvents {
+                events: vec![event],
+                atomic: false,
+            }),
+        };
+        self.send_message(message)?;
+
+        let (sender, receiver) = oneshot::channel();
+        self.state.borrow_mut().receipts.track(message_id, PendingReceipt {
+            consistency: consistency,
+            sender: sender,
+        });
+        Ok(receiver)
+    }
+
+    /// Acknowledge that an event has been processed so that it is not redelivered.
+    pub fn ack(&self, event: &Event) -> Result<(), Error> {
+        self.send_message(Message::Ack(Ack { event_id: event.id.clone() }))
+    }
+
+    /// Reject an event so that the event bus redelivers it. This requires the `nack` feature to
+    /// have been negotiated.
+    pub fn nack(&self, event: &Event) -> Result<(), Error> {
+        if !self.supports(FEATURE_NACK) {
+            return Err(Error::from(ErrorKind::NackNotSupported));
+        }
+
+        self.send_message(Message::Nack(Ack { event_id: event.id.clone() }))
+    }
+
+    /// Look up a single event by its id, resolving with `None` if there is no such event.
+    pub fn get_event(&self, event_id: &str) -> Box<Future<Item = Option<Event>, Error = Error>> {
+        let message_id = self.state.borrow_mut().lookups.next_message_id();
+        let get_event = Message::GetEvent(GetEvent {
+            event_id: String::from(event_id),
+            message_id: Some(message_id.clone()),
+        });
+
+        let lookup = wait_for(self.send_message(get_event).map(|_| {
+            let (sender, receiver) = oneshot::channel();
+            self.state.borrow_mut().lookups.track(message_id, sender);
+            receiver
+        }));
+        Box::new(lookup.and_then(|lookup| {
+            match lookup.error {
+                Some(error) => {
+                    warn!("event lookup failed: event_id='{}' error='{}'", lookup.event_id,
+                          error);
+                    Err(Error::from(ErrorKind::EventLookupFailed))
+                },
+                None => Ok(lookup.event),
+            }
+        }))
+    }
+
+    /// Query for previous events of the given types since an RFC3339 timestamp, or `*` for all
+    /// events.
+    pub fn query(&self, event_types: Vec<String>,
+                 since: &str) -> Box<Future<Item = Vec<Event>, Error = Error>> {
+        let message_id = self.state.borrow_mut().queries.next_message_id();
+        let query = Message::Query(Query {
+            event_types: event_types,
+            since: String::from(since),
+            message_id: Some(message_id.clone()),
+        });
+
+        wait_for(self.send_message(query).map(|_| {
+            let (sender, receiver) = oneshot::channel();
+            self.state.borrow_mut().queries.track(message_id, sender);
+            receiver
+        }))
+    }
+
+    /// Register a JSON Schema document that the data of new events with the given event type
+    /// and version is validated against. The registration contains any errors in the schema.
+    pub fn register_schema(
+        &self, event_type: &str, version: u32, schema: Value
+    ) -> Box<Future<Item = SchemaRegistration, Error = Error>> {
+        let message_id = self.state.borrow_mut().schema_registrations.next_message_id();
+        let register_schema = Message::RegisterSchema(RegisterSchema {
+            event_type: String::from(event_type),
+            version: version,
+            schema: schema,
+            message_id: Some(message_id.clone()),
+        });
+
+        wait_for(self.send_message(register_schema).map(|_| {
+            let (sender, receiver) = oneshot::channel();
+            self.state.borrow_mut().schema_registrations.track(message_id, sender);
+            receiver
+        }))
+    }
+
+    /// Send a request to one instance of another client type. Requests with the same key go to
+    /// the same instance while it is connected. The reply has its `error` set if the request
+    /// could not be answered, including when it times out.
+    pub fn request(
+        &self, client_type: &str, request_type: &str, data: Value, key: Option<ConsistencyKey>,
+        timeout_ms: Option<u64>
+    ) -> Box<Future<Item = Reply, Error = Error>> {
+        let request_id = self.state.borrow_mut().requests.next_message_id();
+        let request = Message::Request(Request {
+            request_id: request_id.clone(),
+            client_type: String::from(client_type),
+            request_type: String::from(request_type),
+            key: key,
+            data: data,
+            timeout_ms: timeout_ms,
+        });
+
+        wait_for(self.send_message(request).map(|_| {
+            let (sender, receiver) = oneshot::channel();
+            self.state.borrow_mut().requests.track(request_id, sender);
+            receiver
+        }))
+    }
+
+    /// Answer a request received from `subscribe_requests`.
+    pub fn reply(&self, request: &Request, data: Value,
+                 error: Option<String>) -> Result<(), Error> {
+        self.send_message(Message::Reply(Reply {
+            request_id: request.request_id.clone(),
+            data: data,
+            error: error,
+        }))
+    }
+
+    /// Cancel a scheduled event before it is delivered. The cancellation says whether the event
+    /// was still scheduled.
+    pub fn cancel(&self, event_id: &str) -> Box<Future<Item = Cancellation, Error = Error>> {
+        let message_id = self.state.borrow_mut().cancellations.next_message_id();
+        let cancel = Message::Cancel(Cancel {
+            event_id: String::from(event_id),
+            message_id: Some(message_id.clone()),
+        });
+
+        wait_for(self.send_message(cancel).map(|_| {
+            let (sender, receiver) = oneshot::channel();
+            self.state.borrow_mut().cancellations.track(message_id, sender);
+            receiver
+        }))
+    }
+}
+
+/// Take the pending response that matches the `message_id` echoed by the event bus, responses
+/// without one cannot be matched.
+fn resolve<T>(tracker: &mut ReceiptTracker<T>, message_id: &Option<String>) -> Option<T> {
+    match *message_id {
+        Some(ref message_id) => tracker.resolve(message_id),
+        None => None,
+    }
+}
+
+/// Turn the receiving half of a pending response into a future, failing if the connection closes
+/// before the response arrives.
+fn wait_for<T: 'static>(
+    receiver: Result<oneshot::Receiver<T>, Error>
+) -> Box<Future<Item = T, Error = Error>> {
+    match receiver {
+        Ok(receiver) => Box::new(receiver.map_err(|_| Error::from(ErrorKind::ConnectionClosed))),
+        Err(e) => Box::new(future::err(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> (BusClient, mpsc::UnboundedReceiver<OwnedMessage>) {
+        let (outgoing, receiver) = mpsc::unbounded();
+        (BusClient::new(outgoing, Encoding::Json, Vec::new()), receiver)
+    }
+
+    fn receipt(message_id: &str, status: &str) -> OwnedMessage {
+        OwnedMessage::Text(format!(r#"{{
+                                        "message_type": "receipt",
+                                        "receipts": [{{
+                                            "checksum": "",
+                                            "consistency": {{ "key": "account-1", "value": 0 }},
+                                            "message_id": "{}",
+                                            "status": "{}"
+                                        }}],
+                                        "sender": "",
+                                        "timestamp": ""
+                                   }}"#, message_id, status))
+    }
+
+    #[test]
+    fn shutdown_notice_is_recorded() {
+        let (client, _receiver) = client();
+        let frame = OwnedMessage::Text(String::from(r#"{
+                                                        "message_type": "shutdown",
+                                                        "reason": "terminated",
+                                                        "deadline_ms": 10000
+                                                   }"#));
+
+        assert!(client.process_frame(frame).is_ok());
+        let notice = client.shutdown_notice().expect("expected shutdown notice");
+        assert_eq!(notice.reason, "terminated");
+        assert_eq!(notice.deadline_ms, 10000);
+    }
+
+    #[test]
+    fn rejected_receipt_rolls_back_consistency() {
+        let (client, _receiver) = client();
+        let key = String::from("account-1");
+        let _ = client.publish("deposit", key.clone(), Value::Null, PublishOptions::default());
+        assert_eq!(client.consistency(&key), Some(ConsistencyValue::Explicit(0)));
+
+        assert!(client.process_frame(receipt("0", "invalid")).is_ok());
+        assert_eq!(client.consistency(&key), None);
+
+        let _ = client.publish("deposit", key.clone(), Value::Null, PublishOptions::default());
+        assert!(client.process_frame(receipt("1", "success")).is_ok());
+        assert_eq!(client.consistency(&key), Some(ConsistencyValue::Explicit(0)));
+    }
+}
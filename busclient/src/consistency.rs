@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use common::schemas::{ConsistencyKey, ConsistencyValue};
+use failure::Error;
+
+use error::ErrorKind;
+
+/// ConsistencyTracker keeps track of the latest consistency value seen for each consistency key so
+/// that new events can be sent with the value that the event bus expects next.
+#[derive(Clone, Debug, Default)]
+pub struct ConsistencyTracker {
+    values: HashMap<ConsistencyKey, ConsistencyValue>,
+}
+
+impl ConsistencyTracker {
+    pub fn new() -> Self { Self::default() }
+
+    /// Get the latest consistency value seen for a key.
+    pub fn get(&self, key: &ConsistencyKey) -> Option<&ConsistencyValue> { self.values.get(key) }
+
+    /// Get the consistency value that the next event sent for this key should use and record it
+    /// as sent. Implicit consistency is left for the event bus to assign.
+    pub fn next(&mut self, key: ConsistencyKey, implicit: bool) -> Result<ConsistencyValue, Error> {
+        if implicit {
+            debug!("implicit consistency");
+            return Ok(ConsistencyValue::Implicit);
+        }
+
+        match self.values.entry(key) {
+            Entry::Occupied(mut entry) => {
+                // We increment the consistency when we send events. As long as we never send an
+                // event with a consistency key higher than expected, this should be fine.
+                let next_value = match *entry.get() {
+                    ConsistencyValue::Explicit(v) => {
+                        debug!("existing consistency key: existing_value='{}' \
+                               sending_value='{}'", v, v + 1);
+                        v + 1
+                    },
+                    ConsistencyValue::Implicit => {
+                        return Err(Error::from(ErrorKind::ImplicitConsistencyInMap));
+                    },
+                };
+
+                let value = ConsistencyValue::Explicit(next_value);
+                entry.insert(value.clone());
+                Ok(value)
+            },
+            Entry::Vacant(entry) => {
+                let initial = ConsistencyValue::Explicit(0);
+                debug!("new consistency key: value='{}'", initial);
+                entry.insert(initial.clone());
+                Ok(initial)
+            },
+        }
+    }
+
+    /// Forget a consistency value that was sent with an event the event bus did not accept, so
+    /// that the next event for the key uses it again. Any values sent after it can't have been
+    /// accepted either, so the key goes back to the value before the rejected one.
+    pub fn reject(&mut self, key: ConsistencyKey, value: ConsistencyValue) {
+        let rejected = match value {
+            ConsistencyValue::Explicit(v) => v,
+            ConsistencyValue::Implicit => {
+                debug!("ignoring rejected implicit consistency");
+                return;
+            },
+        };
+
+        match self.values.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let is_sent = ConsistencyValue::Explicit(rejected) <= *entry.get();
+                debug!("rolling back rejected consistency: current='{}' rejected_value='{}' \
+                       is_sent='{}'", entry.get(), rejected, is_sent);
+
+                if !is_sent {
+                    return;
+                }
+                if rejected == 0 {
+                    entry.remove();
+                } else {
+                    entry.insert(ConsistencyValue::Explicit(rejected - 1));
+                }
+            },
+            Entry::Vacant(_) => debug!("rejected consistency key is not tracked"),
+        }
+    }
+
+    /// Record a consistency value that was seen in an event or receipt from the event bus. The
+    /// value is only kept if it is higher than what we have locally.
+    pub fn observe(&mut self, key: ConsistencyKey, value: ConsistencyValue) {
+        // We don't want to increment if the value was implicit, this can happen from receipts.
+        if let ConsistencyValue::Implicit = value {
+            debug!("ignoring implicit consistency increment");
+            return;
+        }
+
+        match self.values.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let is_higher = value > *entry.get();
+                debug!("checking consistency increment: current='{}' received_value='{}' \
+                       is_higher='{}'", entry.get(), value, is_higher);
+
+                if is_higher {
+                    entry.insert(value);
+                }
+            },
+            Entry::Vacant(entry) => {
+                debug!("new consistency key, inserting new value: value='{}'", value);
+                entry.insert(value);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_starts_at_zero_and_increments() {
+        let mut tracker = ConsistencyTracker::new();
+        let key = String::from("account-1");
+
+        assert_eq!(tracker.next(key.clone(), false).unwrap(), ConsistencyValue::Explicit(0));
+        assert_eq!(tracker.next(key.clone(), false).unwrap(), ConsistencyValue::Explicit(1));
+        assert_eq!(tracker.get(&key), Some(&ConsistencyValue::Explicit(1)));
+    }
+
+    #[test]
+    fn next_with_implicit_does_not_track() {
+        let mut tracker = ConsistencyTracker::new();
+        let key = String::from("account-1");
+
+        assert_eq!(tracker.next(key.clone(), true).unwrap(), ConsistencyValue::Implicit);
+        assert_eq!(tracker.get(&key), None);
+    }
+
+    #[test]
+    fn observe_only_keeps_higher_values() {
+        let mut tracker = ConsistencyTracker::new();
+        let key = String::from("account-1");
+
+        tracker.observe(key.clone(), ConsistencyValue::Explicit(4));
+        tracker.observe(key.clone(), ConsistencyValue::Explicit(2));
+        tracker.observe(key.clone(), ConsistencyValue::Implicit);
+        assert_eq!(tracker.get(&key), Some(&ConsistencyValue::Explicit(4)));
+
+        assert_eq!(tracker.next(key.clone(), false).unwrap(), ConsistencyValue::Explicit(5));
+    }
+
+    #[test]
+    fn reject_rolls_back_to_before_the_rejected_value() {
+        let mut tracker = ConsistencyTracker::new();
+        let key = String::from("account-1");
+
+        tracker.observe(key.clone(), ConsistencyValue::Explicit(4));
+        assert_eq!(tracker.next(key.clone(), false).unwrap(), ConsistencyValue::Explicit(5));
+        assert_eq!(tracker.next(key.clone(), false).unwrap(), ConsistencyValue::Explicit(6));
+
+        tracker.reject(key.clone(), ConsistencyValue::Explicit(5));
+        tracker.reject(key.clone(), ConsistencyValue::Explicit(6));
+        assert_eq!(tracker.get(&key), Some(&ConsistencyValue::Explicit(4)));
+        assert_eq!(tracker.next(key.clone(), false).unwrap(), ConsistencyValue::Explicit(5));
+    }
+
+    #[test]
+    fn reject_of_first_value_forgets_key() {
+        let mut tracker = ConsistencyTracker::new();
+        let key = String::from("account-1");
+
+        assert_eq!(tracker.next(key.clone(), false).unwrap(), ConsistencyValue::Explicit(0));
+        tracker.reject(key.clone(), ConsistencyValue::Explicit(0));
+        tracker.reject(key.clone(), ConsistencyValue::Implicit);
+
+        assert_eq!(tracker.get(&key), None);
+        assert_eq!(tracker.next(key.clone(), false).unwrap(), ConsistencyValue::Explicit(0));
+    }
+}
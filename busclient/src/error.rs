@@ -0,0 +1,67 @@
+use std::fmt;
+use std::fmt::Display;
+
+use failure::{Backtrace, Context, Fail};
+
+#[derive(Debug)]
+pub struct Error {
+    inner: Context<ErrorKind>,
+}
+
+/// Contains all of the different varieties of errors. By using the ErrorKind pattern with the
+/// failure library, we are able to have a one-to-many mapping with the underlying error types and
+/// the kind of error. These variants should not carry data.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "Invalid event bus address")]
+    InvalidServerAddress,
+    #[fail(display = "Failed to connect to the event bus")]
+    WebsocketConnect,
+    #[fail(display = "Failed to send message over websockets")]
+    WebsocketSend,
+    #[fail(display = "Failed to receive message over websockets")]
+    WebsocketReceive,
+    #[fail(display = "Invalid message received on websockets")]
+    ParseMessageFromWebsockets,
+    #[fail(display = "Received invalid message type over websockets")]
+    InvalidWebsocketMessageType,
+    #[fail(display = "Connection closed before the handshake was complete")]
+    ConnectionClosedDuringHandshake,
+    #[fail(display = "Handshake was refused by the event bus")]
+    HandshakeRefused,
+    #[fail(display = "Connection to the event bus was closed")]
+    ConnectionClosed,
+    #[fail(display = "Events or requests have already been subscribed to")]
+    AlreadySubscribed,
+    #[fail(display = "The event bus did not negotiate support for negative acknowledgements")]
+    NackNotSupported,
+    #[fail(display = "The event bus failed to look up the event")]
+    EventLookupFailed,
+    #[fail(display = "The event bus refused the registration")]
+    RegistrationRefused,
+
+    #[fail(display = "Found implicit consistency in state. This is a bug and should not happen")]
+    ImplicitConsistencyInMap,
+}
+
+impl Error {
+    #[allow(dead_code)]
+    pub fn kind(&self) -> ErrorKind { *self.inner.get_context() }
+}
+
+impl Fail for Error {
+    fn cause(&self) -> Option<&Fail> { self.inner.cause() }
+    fn backtrace(&self) -> Option<&Backtrace> { self.inner.backtrace() }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { Display::fmt(&self.inner, f) }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self { Self { inner: Context::new(kind) } }
+}
+
+impl From<Context<ErrorKind>> for Error {
+    fn from(inner: Context<ErrorKind>) -> Self { Self { inner: inner } }
+}
@@ -0,0 +1,22 @@
+#![deny(warnings)]
+#![deny(missing_debug_implementations)]
+
+//! A client library for the event bus. This handles the handshake, receipts and consistency
+//! tracking so that clients only need to deal with typed events.
+
+extern crate common;
+#[macro_use] extern crate failure;
+extern crate futures;
+#[macro_use] extern crate log;
+extern crate serde_json;
+extern crate tokio_core;
+extern crate websocket;
+
+mod client;
+mod consistency;
+mod error;
+mod receipts;
+
+pub use client::{BusClient, Events, PublishOptions, RegisterOptions, Requests};
+pub use consistency::ConsistencyTracker;
+pub use receipts::ReceiptTracker;
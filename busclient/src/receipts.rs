@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+/// ReceiptTracker matches receipts from the event bus with whatever was recorded when the
+/// corresponding event was sent. Each sent event is given a `message_id` that the event bus
+/// echoes in its receipt.
+#[derive(Debug)]
+pub struct ReceiptTracker<T> {
+    pending: HashMap<String, T>,
+    next_message_id: u64,
+}
+
+impl<T> ReceiptTracker<T> {
+    pub fn new() -> Self { Self { pending: HashMap::new(), next_message_id: 0 } }
+
+    /// Get a `message_id` that is unique for this tracker.
+    pub fn next_message_id(&mut self) -> String {
+        let message_id = self.next_message_id.to_string();
+        self.next_message_id += 1;
+        message_id
+    }
+
+    /// Record a sent event that is waiting on a receipt.
+    pub fn track(&mut self, message_id: String, value: T) {
+        self.pending.insert(message_id, value);
+    }
+
+    /// Take the pending event that matches the `message_id` of a receipt.
+    pub fn resolve(&mut self, message_id: &str) -> Option<T> {
+        self.pending.remove(message_id)
+    }
+
+    /// Remove every pending event, this is used when the connection is lost and no more receipts
+    /// will arrive.
+    pub fn drain(&mut self) -> Vec<T> {
+        self.pending.drain().map(|(_, value)| value).collect()
+    }
+
+    pub fn len(&self) -> usize { self.pending.len() }
+
+    pub fn is_empty(&self) -> bool { self.pending.is_empty() }
+}
+
+impl<T> Default for ReceiptTracker<T> {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_ids_are_unique() {
+        let mut tracker: ReceiptTracker<()> = ReceiptTracker::new();
+        let first = tracker.next_message_id();
+        let second = tracker.next_message_id();
+
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn resolve_matches_message_id() {
+        let mut tracker = ReceiptTracker::new();
+        let first = tracker.next_message_id();
+        let second = tracker.next_message_id();
+        tracker.track(first.clone(), 1);
+        tracker.track(second.clone(), 1);
+
+        assert_eq!(tracker.resolve(&second), Some(1));
+        assert_eq!(tracker.resolve(&second), None);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn drain_empties_tracker() {
+        let mut tracker = ReceiptTracker::new();
+        tracker.track(String::from("a"), 1);
+        tracker.track(String::from("b"), 2);
+
+        let mut drained = tracker.drain();
+        drained.sort();
+        assert_eq!(drained, vec![1, 2]);
+        assert!(tracker.is_empty());
+    }
+}
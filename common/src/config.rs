@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use failure::{Error, ResultExt};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::DeserializeOwned;
+use toml::{self, Value};
+
+use error::ErrorKind;
+
+/// Separates the keys of nested tables in the names of environment variables, such as
+/// `BUSD_KAFKA__BROKERS` for `brokers` in the `kafka` table.
+const ENV_SEPARATOR: &str = "__";
+
+/// Load a configuration by starting from its defaults, merging in the TOML file at `path` if
+/// there is one and then applying any environment variables that start with `env_prefix`.
+pub fn load_config<T, I>(path: Option<&Path>, env_prefix: &str, vars: I) -> Result<T, Error>
+    where T: Default + Serialize + DeserializeOwned, I: IntoIterator<Item=(String, String)>
+{
+    let mut config = Value::try_from(T::default()).context(ErrorKind::SerializeConfig)?;
+
+    if let Some(path) = path {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .context(ErrorKind::ReadConfigFile)?;
+        let file: Value = contents.parse::<Value>().context(ErrorKind::ParseConfigFile)?;
+        merge(&mut config, file);
+    }
+
+    for (name, value) in vars {
+        if name.starts_with(env_prefix) {
+            apply_override(&mut config, &name[env_prefix.len()..], &value)?;
+        }
+    }
+
+    Ok(config.try_into().context(ErrorKind::InvalidConfig)?)
+}
+
+/// Render a configuration as TOML, such as for printing the effective configuration.
+pub fn render_config<T: Serialize>(config: &T) -> Result<String, Error> {
+    Ok(toml::to_string_pretty(config).context(ErrorKind::SerializeConfig)?)
+}
+
+/// Deserialize a table of strings that can also be written as numbers or booleans, such as the
+/// settings that are passed through to Kafka.
+pub fn deserialize_string_map<'de, D>(deserializer: D)
+    -> Result<BTreeMap<String, String>, D::Error> where D: Deserializer<'de>
+{
+    let table = BTreeMap::<String, Value>::deserialize(deserializer)?;
+    Ok(table.into_iter().map(|(key, value)| {
+        match value {
+            Value::String(value) => (key, value),
+            value => (key, value.to_string()),
+        }
+    }).collect())
+}
+
+/// Merge the values of one table into another, replacing everything but nested tables.
+fn merge(base: &mut Value, overlay: Value) {
+    let overlay = match overlay {
+        Value::Table(overlay) => overlay,
+        overlay => {
+            *base = overlay;
+            return;
+        },
+    };
+
+    if let Value::Table(ref mut base) = *base {
+        for (key, value) in overlay {
+            match base.entry(key) {
+                Entry::Occupied(mut existing) => merge(existing.get_mut(), value),
+                Entry::Vacant(entry) => { entry.insert(value); },
+            }
+        }
+        return;
+    }
+
+    *base = Value::Table(overlay);
+}
+
+/// Normalize a key so that environment variable names can refer to it.
+fn normalize(key: &str) -> String {
+    key.to_lowercase().replace('.', "_")
+}
+
+/// Set the value at the path named by an environment variable, without its prefix. Each part of
+/// the path matches an existing key regardless of case and with `.` written as `_`, otherwise it
+/// becomes a new key in lower case. New keys that contain `.` must be written with it.
+fn apply_override(config: &mut Value, name: &str, raw: &str) -> Result<(), Error> {
+    let mut current = config;
+    let mut parts = name.split(ENV_SEPARATOR).peekable();
+
+    while let Some(part) = parts.next() {
+        let table = match *{ current } {
+            Value::Table(ref mut table) => table,
+            _ => return Err(Error::from(ErrorKind::InvalidEnvironmentOverride)),
+        };
+
+        let existing = table.keys().find(|key| normalize(key) == normalize(part)).cloned();
+        let key = existing.unwrap_or_else(|| part.to_lowercase());
+
+        if parts.peek().is_none() {
+            let value = match table.get(&key) {
+                Some(existing) => coerce(existing, raw),
+                None => infer(raw),
+            };
+            table.insert(key, value);
+            return Ok(());
+        }
+
+        current = table.entry(key).or_insert_with(|| Value::Table(Default::default()));
+    }
+
+    Err(Error::from(ErrorKind::InvalidEnvironmentOverride))
+}
+
+/// Parse a value from the environment as the same type as the value that it replaces.
+fn coerce(existing: &Value, raw: &str) -> Value {
+    match *existing {
+        Value::String(_) => Value::String(raw.to_owned()),
+        _ => infer(raw),
+    }
+}
+
+/// Parse a value from the environment as a TOML value if it is one, otherwise as a string.
+fn infer(raw: &str) -> Value {
+    format!("value = {}", raw).parse::<Value>().ok()
+        .and_then(|parsed| parsed.get("value").cloned())
+        .unwrap_or_else(|| Value::String(raw.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Config {
+        name: String,
+        retries: u32,
+        limit: Option<usize>,
+        #[serde(deserialize_with = "deserialize_string_map")]
+        passthrough: BTreeMap<String, String>,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            let mut passthrough = BTreeMap::new();
+            passthrough.insert(String::from("session.timeout.ms"), String::from("6000"));
+
+            Self {
+                name: String::from("bus"),
+                retries: 60,
+                limit: None,
+                passthrough: passthrough,
+            }
+        }
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|&(name, value)| (name.to_owned(), value.to_owned())).collect()
+    }
+
+    #[test]
+    fn defaults_are_used_without_overrides() {
+        let config: Config = load_config(None, "TEST_", vars(&[])).unwrap();
+        assert_eq!(config.name, "bus");
+        assert_eq!(config.retries, 60);
+        assert_eq!(config.limit, None);
+    }
+
+    #[test]
+    fn environment_overrides_existing_values() {
+        let config: Config = load_config(None, "TEST_", vars(&[
+            ("TEST_NAME", "1234"),
+            ("TEST_RETRIES", "5"),
+            ("TEST_PASSTHROUGH__SESSION_TIMEOUT_MS", "10000"),
+            ("OTHER_RETRIES", "10"),
+        ])).unwrap();
+        assert_eq!(config.name, "1234");
+        assert_eq!(config.retries, 5);
+        assert_eq!(config.passthrough["session.timeout.ms"], "10000");
+    }
+
+    #[test]
+    fn environment_adds_new_values() {
+        let config: Config = load_config(None, "TEST_", vars(&[
+            ("TEST_LIMIT", "1024"),
+            ("TEST_PASSTHROUGH__fetch.wait.max.ms", "50"),
+        ])).unwrap();
+        assert_eq!(config.limit, Some(1024));
+        assert_eq!(config.passthrough["fetch.wait.max.ms"], "50");
+    }
+
+    #[test]
+    fn environment_cannot_override_inside_a_value() {
+        let config: Result<Config, _> = load_config(None, "TEST_", vars(&[
+            ("TEST_NAME__INNER", "value"),
+        ]));
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn misspelled_keys_are_refused() {
+        let config: Result<Config, _> = load_config(None, "TEST_", vars(&[
+            ("TEST_RETRY", "5"),
+        ]));
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn file_values_are_merged_with_defaults() {
+        let mut config = Value::try_from(Config::default()).unwrap();
+        merge(&mut config, r#"
+            retries = 3
+
+            [passthrough]
+            "fetch.wait.max.ms" = 50
+        "#.parse().unwrap());
+
+        let config: Config = config.try_into().unwrap();
+        assert_eq!(config.name, "bus");
+        assert_eq!(config.retries, 3);
+        assert_eq!(config.passthrough["session.timeout.ms"], "6000");
+        assert_eq!(config.passthrough["fetch.wait.max.ms"], "50");
+    }
+}
@@ -0,0 +1,111 @@
+use failure::{Error, ResultExt};
+use rmp_serde::{from_slice as from_msgpack_slice, to_vec_named};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{from_slice, to_string};
+use websocket::message::OwnedMessage;
+
+use error::ErrorKind;
+use protocol::{ENCODING_JSON, ENCODING_MESSAGEPACK};
+
+/// Encoding represents the negotiated format that messages are sent in over websockets. JSON is
+/// sent as text frames and MessagePack is sent as binary frames.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            ENCODING_JSON => Some(Encoding::Json),
+            ENCODING_MESSAGEPACK => Some(Encoding::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Json => ENCODING_JSON,
+            Encoding::MessagePack => ENCODING_MESSAGEPACK,
+        }
+    }
+
+    /// Serialize a message into a websocket frame of the appropriate type for this encoding.
+    pub fn encode<T: Serialize>(&self, message: &T) -> Result<OwnedMessage, Error> {
+        match *self {
+            Encoding::Json => {
+                let serialized = to_string(message).context(ErrorKind::SerializeMessage)?;
+                Ok(OwnedMessage::Text(serialized))
+            },
+            Encoding::MessagePack => {
+                let serialized = to_vec_named(message).context(ErrorKind::SerializeMessage)?;
+                Ok(OwnedMessage::Binary(serialized))
+            },
+        }
+    }
+
+    /// Deserialize the payload of a binary frame. Text frames are always JSON and should be
+    /// decoded with `Encoding::Json` regardless of what was negotiated.
+    pub fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, Error> {
+        match *self {
+            Encoding::Json => Ok(from_slice(payload).context(ErrorKind::DeserializeMessage)?),
+            Encoding::MessagePack => {
+                Ok(from_msgpack_slice(payload).context(ErrorKind::DeserializeMessage)?)
+            },
+        }
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Self { Encoding::Json }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schemas::{Message, Query};
+
+    fn query() -> Message {
+        Message::Query(Query {
+            event_types: vec![String::from("deposit")],
+            since: String::from("*"),
+            message_id: None,
+        })
+    }
+
+    #[test]
+    fn encoding_names_round_trip() {
+        for encoding in &[Encoding::Json, Encoding::MessagePack] {
+            assert_eq!(Encoding::from_name(encoding.name()), Some(*encoding));
+        }
+        assert_eq!(Encoding::from_name("xml"), None);
+    }
+
+    #[test]
+    fn json_is_sent_as_text() {
+        match Encoding::Json.encode(&query()).unwrap() {
+            OwnedMessage::Text(text) => {
+                match Encoding::Json.decode(text.as_bytes()).unwrap() {
+                    Message::Query(decoded) => assert_eq!(decoded.event_types, vec!["deposit"]),
+                    _ => panic!("expected query message"),
+                }
+            },
+            _ => panic!("expected text frame"),
+        }
+    }
+
+    #[test]
+    fn messagepack_is_sent_as_binary() {
+        match Encoding::MessagePack.encode(&query()).unwrap() {
+            OwnedMessage::Binary(bytes) => {
+                match Encoding::MessagePack.decode(&bytes).unwrap() {
+                    Message::Query(decoded) => assert_eq!(decoded.since, "*"),
+                    _ => panic!("expected query message"),
+                }
+            },
+            _ => panic!("expected binary frame"),
+        }
+    }
+}
@@ -15,10 +15,28 @@ pub struct Error {
 pub enum ErrorKind {
     #[fail(display = "Failed to serialize value to json for hashing")]
     SerializeJsonForHashing,
+    #[fail(display = "Failed to serialize message in negotiated encoding")]
+    SerializeMessage,
+    #[fail(display = "Failed to deserialize message in negotiated encoding")]
+    DeserializeMessage,
     #[fail(display = "Invalid consistency value type recieved in incoming event message")]
     ParseConsistencyValue,
     #[fail(display = "Attempt to get value of implicit consistency value")]
     GetValueOfImplicitConsistency,
+    #[fail(display = "Failed to serialize configuration")]
+    SerializeConfig,
+    #[fail(display = "Failed to read configuration file")]
+    ReadConfigFile,
+    #[fail(display = "Failed to parse configuration file as TOML")]
+    ParseConfigFile,
+    #[fail(display = "Environment variable overrides a configuration value that is not a table")]
+    InvalidEnvironmentOverride,
+    #[fail(display = "Configuration is invalid")]
+    InvalidConfig,
+    #[fail(display = "Invalid log level in configuration")]
+    InvalidLogLevel,
+    #[fail(display = "Failed to configure logging")]
+    ConfigureLogging,
 }
 
 impl Error {
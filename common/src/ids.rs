@@ -0,0 +1,68 @@
+use chrono::Utc;
+use rand::{Rng, thread_rng};
+
+use schemas::EventId;
+
+/// Crockford's base32 alphabet, as used by ULIDs.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Generate a new ULID for an event. The first ten characters encode the current time in
+/// milliseconds so that ids sort in the order they were generated, the remaining sixteen
+/// characters are random.
+pub fn generate_event_id() -> EventId {
+    let now = Utc::now();
+    let millis = now.timestamp() as u64 * 1000 + now.timestamp_subsec_millis() as u64;
+
+    let mut random = [0u8; 10];
+    thread_rng().fill_bytes(&mut random);
+    encode_event_id(millis, &random)
+}
+
+fn encode_event_id(millis: u64, random: &[u8; 10]) -> EventId {
+    let mut id = String::with_capacity(26);
+    push_base32(&mut id, millis, 10);
+
+    // The 80 random bits are encoded as two 40 bit halves of eight characters each.
+    for half in random.chunks(5) {
+        let bits = half.iter().fold(0u64, |acc, &byte| (acc << 8) | byte as u64);
+        push_base32(&mut id, bits, 8);
+    }
+
+    id
+}
+
+fn push_base32(id: &mut String, bits: u64, characters: usize) {
+    for i in (0..characters).rev() {
+        id.push(ALPHABET[((bits >> (i * 5)) & 0x1f) as usize] as char);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_event_id_zero() {
+        assert_eq!(encode_event_id(0, &[0; 10]), "00000000000000000000000000");
+    }
+
+    #[test]
+    fn encode_event_id_max() {
+        assert_eq!(encode_event_id((1 << 48) - 1, &[0xff; 10]), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+    }
+
+    #[test]
+    fn event_ids_sort_by_time() {
+        let earlier = encode_event_id(1517443200000, &[0xff; 10]);
+        let later = encode_event_id(1517443200001, &[0; 10]);
+        assert!(earlier < later);
+    }
+
+    #[test]
+    fn generated_event_ids_are_unique() {
+        let first = generate_event_id();
+        let second = generate_event_id();
+        assert_eq!(first.len(), 26);
+        assert_ne!(first, second);
+    }
+}
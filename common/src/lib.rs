@@ -6,18 +6,27 @@ extern crate colored;
 #[macro_use] extern crate failure;
 extern crate fern;
 extern crate log;
+extern crate rand;
+extern crate rmp_serde;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate serde_json;
 extern crate sha1;
+extern crate toml;
 extern crate websocket;
 
+pub mod config;
+mod encoding;
 mod error;
 mod extensions;
 mod helpers;
+mod ids;
 mod logging;
+pub mod protocol;
 pub mod schemas;
 
+pub use encoding::Encoding;
 pub use extensions::VecDequeExt;
 pub use helpers::hash_json;
-pub use logging::configure_logging;
+pub use ids::generate_event_id;
+pub use logging::{LogContext, LogFormat, LoggingConfig, configure_logging};
@@ -1,13 +1,139 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::io::stdout;
 
 use chrono::Local;
 use colored::*;
+use failure::{Error, ResultExt};
 use fern::Dispatch;
 use log::{LogLevel, LogLevelFilter};
+use serde_json::{Map, Value};
 
-pub fn configure_logging(level: LogLevelFilter) {
-    Dispatch::new()
-        .format(|out, message, record| {
+use error::ErrorKind;
+
+thread_local! {
+    /// The fields of every `LogContext` that is alive on this thread, outermost first.
+    static CONTEXT: RefCell<Vec<(&'static str, String)>> = RefCell::new(Vec::new());
+}
+
+/// LogContext gives every JSON record that is logged on the current thread the fields that it
+/// was created with, such as the session and client type, until it is dropped. Actors handle one
+/// message at a time, so a context created at the start of a handler covers everything that is
+/// logged while handling that message. The fields of inner contexts take precedence.
+#[must_use]
+pub struct LogContext {
+    depth: usize,
+}
+
+impl LogContext {
+    pub fn new() -> Self {
+        let depth = CONTEXT.with(|context| context.borrow().len());
+        LogContext { depth: depth }
+    }
+
+    /// Add a field to the context.
+    pub fn field<T: Display>(self, name: &'static str, value: T) -> Self {
+        CONTEXT.with(|context| context.borrow_mut().push((name, value.to_string())));
+        self
+    }
+
+    /// Add a field to the context if it has a value.
+    pub fn optional_field<T: Display>(self, name: &'static str, value: Option<T>) -> Self {
+        match value {
+            Some(value) => self.field(name, value),
+            None => self,
+        }
+    }
+}
+
+impl Drop for LogContext {
+    fn drop(&mut self) {
+        let depth = self.depth;
+        CONTEXT.with(|context| context.borrow_mut().truncate(depth));
+    }
+}
+
+/// LogFormat is how each log record is written.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum LogFormat {
+    /// Coloured lines for people to read.
+    #[serde(rename = "text")]
+    Text,
+    /// One JSON object per line for log aggregation.
+    #[serde(rename = "json")]
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self { LogFormat::Text }
+}
+
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// LoggingConfig contains the logging section of the configuration of each binary.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// One of `off`, `trace`, `debug`, `info`, `warn` or `error`.
+    pub level: String,
+    pub format: LogFormat,
+    /// The levels of individual modules by their path, such as `server::consumer`, these
+    /// override `level`.
+    pub modules: BTreeMap<String, String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: String::from("info"),
+            format: LogFormat::Text,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn level_filter(&self) -> Result<LogLevelFilter, Error> {
+        parse_level(&self.level)
+    }
+
+    pub fn module_filters(&self) -> Result<Vec<(String, LogLevelFilter)>, Error> {
+        self.modules.iter()
+            .map(|(module, level)| Ok((module.clone(), parse_level(level)?)))
+            .collect()
+    }
+
+    /// Check that every level in the configuration is valid.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.level_filter()?;
+        self.module_filters()?;
+        Ok(())
+    }
+}
+
+fn parse_level(level: &str) -> Result<LogLevelFilter, Error> {
+    level.parse().map_err(|_| Error::from(ErrorKind::InvalidLogLevel))
+}
+
+pub fn configure_logging(config: &LoggingConfig) -> Result<(), Error> {
+    let mut dispatch = Dispatch::new()
+        .level(config.level_filter()?)
+        .level_for("tokio_core", LogLevelFilter::Info);
+    for (module, level) in config.module_filters()? {
+        dispatch = dispatch.level_for(module, level);
+    }
+
+    let dispatch = match config.format {
+        LogFormat::Text => dispatch.format(|out, message, record| {
             let now = Local::now();
 
             let level_colour = match record.level() {
@@ -27,9 +153,90 @@ pub fn configure_logging(level: LogLevelFilter) {
                 level,
                 message
             ))
-        })
-        .level(level)
-        .level_for("tokio_core", LogLevelFilter::Info)
-        .chain(stdout())
-        .apply().unwrap();
+        }),
+        LogFormat::Json => dispatch.format(|out, message, record| {
+            let line = CONTEXT.with(|context| {
+                json_record(&Local::now().to_rfc3339(), record.level(), record.target(),
+                            &message.to_string(), &context.borrow())
+            });
+            out.finish(format_args!("{}", line))
+        }),
+    };
+
+    dispatch.chain(stdout()).apply().context(ErrorKind::ConfigureLogging)?;
+    Ok(())
+}
+
+/// Format a log record as a single line of JSON, with the fields of the log contexts that it was
+/// logged in. Multi-line messages, such as those with pretty-printed payloads, are escaped rather
+/// than split.
+fn json_record(timestamp: &str, level: LogLevel, target: &str, message: &str,
+               context: &[(&'static str, String)]) -> String {
+    let mut record = Map::new();
+    record.insert(String::from("timestamp"), Value::from(timestamp));
+    record.insert(String::from("level"), Value::from(format!("{}", level).to_lowercase()));
+    record.insert(String::from("target"), Value::from(target));
+    for &(field, ref value) in context {
+        record.insert(field.to_owned(), Value::from(value.as_str()));
+    }
+    record.insert(String::from("message"), Value::from(message));
+
+    Value::Object(record).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::from_str;
+
+    #[test]
+    fn contexts_add_fields_until_dropped() {
+        let outer = LogContext::new()
+            .field("session", "127.0.0.1:5000")
+            .optional_field("client_type", Some("accounts"))
+            .optional_field::<&str>("correlation_id", None);
+        {
+            let _inner = LogContext::new().field("event_id", "abc").field("session", "other");
+            CONTEXT.with(|context| assert_eq!(*context.borrow(), vec![
+                ("session", String::from("127.0.0.1:5000")),
+                ("client_type", String::from("accounts")),
+                ("event_id", String::from("abc")),
+                ("session", String::from("other")),
+            ]));
+        }
+
+        CONTEXT.with(|context| assert_eq!(context.borrow().len(), 2));
+        drop(outer);
+        CONTEXT.with(|context| assert!(context.borrow().is_empty()));
+    }
+
+    #[test]
+    fn json_records_are_single_lines() {
+        let context = vec![
+            ("session", String::from("127.0.0.1:5000")),
+            ("event_id", String::from("abc")),
+            ("session", String::from("127.0.0.1:6000")),
+        ];
+        let line = json_record("2018-01-01T00:00:00+00:00", LogLevel::Trace, "server::bus",
+                               "saving event: event_id='abc' event=\n{\n  \"a\": 1\n}",
+                               &context);
+        assert!(!line.contains('\n'));
+
+        let record: Value = from_str(&line).unwrap();
+        assert_eq!(record["level"], "trace");
+        assert_eq!(record["target"], "server::bus");
+        assert_eq!(record["event_id"], "abc");
+        assert_eq!(record["session"], "127.0.0.1:6000");
+        assert_eq!(record["message"], "saving event: event_id='abc' event=\n{\n  \"a\": 1\n}");
+    }
+
+    #[test]
+    fn module_levels_are_validated() {
+        let mut config = LoggingConfig::default();
+        config.modules.insert(String::from("server::consumer"), String::from("debug"));
+        assert!(config.validate().is_ok());
+
+        config.modules.insert(String::from("server::bus"), String::from("loud"));
+        assert!(config.validate().is_err());
+    }
 }
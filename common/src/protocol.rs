@@ -0,0 +1,116 @@
+use std::cmp::{max, min};
+
+use schemas::{Hello, Welcome};
+
+/// The newest version of the wire protocol that this build understands. This should be bumped
+/// whenever a change is made to the schemas that older clients would not understand.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest version of the wire protocol that this build is still able to speak.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+pub const ENCODING_JSON: &str = "json";
+pub const ENCODING_MESSAGEPACK: &str = "msgpack";
+
+pub const FEATURE_NACK: &str = "nack";
+pub const FEATURE_CHUNKED_REBUILD: &str = "chunked_rebuild";
+pub const FEATURE_COMPRESSION: &str = "compression";
+
+/// Build the `Welcome` response to a `Hello` given the encodings and features that the responding
+/// side supports. Encodings are chosen in the order of the client's preference and features are
+/// the intersection of both sides.
+pub fn negotiate(hello: &Hello, encodings: &[&str], features: &[&str]) -> Welcome {
+    let client_min = hello.min_protocol_version.unwrap_or(hello.protocol_version);
+    let version = min(hello.protocol_version, PROTOCOL_VERSION);
+
+    if version < max(client_min, MIN_PROTOCOL_VERSION) {
+        return refuse(version, format!(
+                "no common protocol version: client='{}-{}' server='{}-{}'",
+                client_min, hello.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION));
+    }
+
+    let encoding = match hello.encodings.iter().find(|e| encodings.contains(&e.as_str())) {
+        Some(encoding) => encoding.clone(),
+        None => return refuse(version, format!(
+                "no common encoding: client='{:?}' server='{:?}'", hello.encodings, encodings)),
+    };
+
+    let features = hello.features.iter()
+        .filter(|f| features.contains(&f.as_str()))
+        .cloned()
+        .collect();
+
+    Welcome {
+        status: String::from("accepted"),
+        protocol_version: version,
+        encoding: Some(encoding),
+        features: features,
+        reason: None,
+    }
+}
+
+fn refuse(version: u32, reason: String) -> Welcome {
+    Welcome {
+        status: String::from("refused"),
+        protocol_version: version,
+        encoding: None,
+        features: Vec::new(),
+        reason: Some(reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(version: u32, min_version: Option<u32>, encodings: Vec<&str>,
+             features: Vec<&str>) -> Hello {
+        Hello {
+            protocol_version: version,
+            min_protocol_version: min_version,
+            encodings: encodings.into_iter().map(String::from).collect(),
+            features: features.into_iter().map(String::from).collect(),
+        }
+    }
+
+    #[test]
+    fn negotiate_accepts_compatible_client() {
+        let welcome = negotiate(&hello(PROTOCOL_VERSION, None, vec!["json"], vec![FEATURE_NACK]),
+                                &[ENCODING_JSON], &[FEATURE_NACK]);
+        assert_eq!(welcome.status, "accepted");
+        assert_eq!(welcome.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(welcome.encoding, Some(String::from("json")));
+        assert_eq!(welcome.features, vec![String::from(FEATURE_NACK)]);
+    }
+
+    #[test]
+    fn negotiate_downgrades_newer_client() {
+        let welcome = negotiate(&hello(PROTOCOL_VERSION + 1, Some(MIN_PROTOCOL_VERSION),
+                                       vec!["json"], vec![]),
+                                &[ENCODING_JSON], &[]);
+        assert_eq!(welcome.status, "accepted");
+        assert_eq!(welcome.protocol_version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn negotiate_refuses_incompatible_version() {
+        let welcome = negotiate(&hello(PROTOCOL_VERSION + 1, None, vec!["json"], vec![]),
+                                &[ENCODING_JSON], &[]);
+        assert_eq!(welcome.status, "refused");
+        assert!(welcome.reason.is_some());
+    }
+
+    #[test]
+    fn negotiate_refuses_without_common_encoding() {
+        let welcome = negotiate(&hello(PROTOCOL_VERSION, None, vec!["xml"], vec![]),
+                                &[ENCODING_JSON], &[]);
+        assert_eq!(welcome.status, "refused");
+    }
+
+    #[test]
+    fn negotiate_drops_unsupported_features() {
+        let welcome = negotiate(&hello(PROTOCOL_VERSION, None, vec!["json"],
+                                       vec![FEATURE_NACK, FEATURE_COMPRESSION]),
+                                &[ENCODING_JSON], &[FEATURE_NACK]);
+        assert_eq!(welcome.features, vec![String::from(FEATURE_NACK)]);
+    }
+}
@@ -0,0 +1,29 @@
+use schemas::event::EventId;
+
+/// Ack is sent by clients to acknowledge (or, as a `nack`, reject) the event with the given id.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ack {
+    pub event_id: EventId,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemas::Message;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_ack_message_type() {
+        let data = r#"{
+                        "message_type": "ack",
+                        "event_id": "01C3Z0XW8SK9Y4FJ6T2A0QNBVD"
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Ack(message)) = parsed {
+            assert_eq!(message.event_id, "01C3Z0XW8SK9Y4FJ6T2A0QNBVD");
+        } else {
+            panic!("expected ack message");
+        }
+    }
+}
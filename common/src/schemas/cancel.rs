@@ -0,0 +1,65 @@
+use schemas::event::EventId;
+
+/// Cancel is sent by clients to cancel a scheduled event before it is delivered.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cancel {
+    pub event_id: EventId,
+    /// This field contains an optional id chosen by the client that is echoed in the response to
+    /// this message.
+    #[serde(default)]
+    pub message_id: Option<String>,
+}
+
+/// Cancellation is the response to `Cancel`, `cancelled` is false if there was no scheduled event
+/// with that id, such as when it has already been delivered, or if it was published by another
+/// client type.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cancellation {
+    pub event_id: EventId,
+    pub cancelled: bool,
+    /// This field contains the `message_id` of the message this responds to, if one was provided.
+    #[serde(default)]
+    pub message_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemas::Message;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_cancel_message_type() {
+        let data = r#"{
+                        "message_type": "cancel",
+                        "event_id": "01C3Z0XW8SK9Y4FJ6T2A0QNBVD"
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Cancel(message)) = parsed {
+            assert_eq!(message.event_id, "01C3Z0XW8SK9Y4FJ6T2A0QNBVD");
+            assert_eq!(message.message_id, None);
+        } else {
+            panic!("expected cancel message");
+        }
+    }
+
+    #[test]
+    fn parse_cancellation_message_id() {
+        let data = r#"{
+                        "message_type": "cancellation",
+                        "event_id": "01C3Z0XW8SK9Y4FJ6T2A0QNBVD",
+                        "cancelled": true,
+                        "message_id": "4"
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Cancellation(message)) = parsed {
+            assert!(message.cancelled);
+            assert_eq!(message.message_id, Some(String::from("4")));
+        } else {
+            panic!("expected cancellation message");
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use schemas::new_event::NewEvent;
+
+/// Command is sent by clients to direct an event at a single client type. It is persisted like
+/// any other event and acknowledged in the same way, but it is only propagated to `client_type`.
+/// The event bus responds with receipts as it does for new events.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Command {
+    pub client_type: String,
+    pub command: NewEvent,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemas::Message;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_command_message_type() {
+        let data = r#"{
+                        "message_type": "command",
+                        "client_type": "accounts",
+                        "command": {
+                            "event_type": "close_account",
+                            "correlation_id": 94859829321,
+                            "message_id": "7",
+                            "data": {
+                                "account": 837
+                            },
+                            "consistency": {
+                                "key": "account-837",
+                                "value": "*"
+                            }
+                        }
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Command(message)) = parsed {
+            assert_eq!(message.client_type, "accounts");
+            assert_eq!(message.command.event_type, "close_account");
+            assert_eq!(message.command.data["account"], 837);
+        } else {
+            panic!("expected command message");
+        }
+    }
+}
@@ -114,7 +114,10 @@ pub struct Consistency {
     pub value: ConsistencyValue,
 }
 
+#[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn parse_consistency_from_str() {
         assert_eq!(ConsistencyValue::from_str("*").unwrap(), ConsistencyValue::Implicit);
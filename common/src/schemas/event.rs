@@ -1,34 +1,51 @@
-use std::hash::{Hash, Hasher};
+use std::collections::HashMap;
 
 use serde_json::Value;
 
 use schemas::consistency::Consistency;
 
+/// EventId uniquely identifies an event accepted by the event bus. These are ULIDs and so sort in
+/// the order that the events were accepted.
+pub type EventId = String;
+
+/// Metadata contains free-form headers that are carried alongside the data of an event.
+pub type Metadata = HashMap<String, String>;
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Event {
+    /// Events persisted before event ids were assigned will not have an id.
+    #[serde(default)]
+    pub id: EventId,
+    /// This field contains the id of the event that caused this event, if any.
+    #[serde(default)]
+    pub causation_id: Option<EventId>,
     pub consistency: Consistency,
-    pub correlation_id: u32,
+    pub correlation_id: u64,
+    /// This field contains the raw timestamp that the event is held until before being propagated
+    /// to clients, if it was scheduled.
+    #[serde(default)]
+    pub deliver_at: Option<i64>,
     pub data: Value,
     pub event_type: String,
-    // We don't want this field going to Kafka.
-    pub message_type: Option<String>,
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// This field contains the id of the document that the data of the event is stored in, if it
+    /// was too large to store with the event. The data is null except when the event is looked
+    /// up by its id.
+    #[serde(default)]
+    pub payload_ref: Option<String>,
+    /// This field contains the version of the schema for the event type that the data has.
+    /// Events persisted before schema versions were introduced are version one.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
     pub sender: String,
     pub session_id: Option<usize>,
+    /// This field contains the client type that this event is a command for, if it is one.
+    /// Commands are only propagated to that client type.
+    #[serde(default)]
+    pub target: Option<String>,
     pub timestamp: String,
     pub timestamp_raw: Option<i64>,
 }
 
-impl Eq for Event { }
-
-impl Hash for Event {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.consistency.hash(state);
-        self.correlation_id.hash(state);
-        self.event_type.hash(state);
-        self.message_type.hash(state);
-        self.sender.hash(state);
-        self.session_id.hash(state);
-        self.timestamp.hash(state);
-        self.timestamp_raw.hash(state);
-    }
-}
+fn default_schema_version() -> u32 { 1 }
@@ -0,0 +1,25 @@
+use schemas::event::{Event, EventId};
+
+/// GetEvent is sent by clients to look up a single event by its id.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetEvent {
+    pub event_id: EventId,
+    /// This field contains an optional id chosen by the client that is echoed in the response to
+    /// this message.
+    #[serde(default)]
+    pub message_id: Option<String>,
+}
+
+/// EventLookup is the response to `GetEvent`, `event` is `None` if no event has that id or if
+/// the lookup failed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EventLookup {
+    pub event_id: EventId,
+    pub event: Option<Event>,
+    /// This field contains why the event could not be looked up, if it could not.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// This field contains the `message_id` of the message this responds to, if one was provided.
+    #[serde(default)]
+    pub message_id: Option<String>,
+}
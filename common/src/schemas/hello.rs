@@ -0,0 +1,42 @@
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hello {
+    /// The newest protocol version that the client is able to speak.
+    pub protocol_version: u32,
+    /// The oldest protocol version that the client is able to speak, if this is not provided then
+    /// only `protocol_version` is acceptable.
+    #[serde(default)]
+    pub min_protocol_version: Option<u32>,
+    /// Encodings the client supports, in order of preference.
+    pub encodings: Vec<String>,
+    /// Optional features the client would like to use.
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemas::Message;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_hello_message_type() {
+        let data = r#"{
+                        "message_type": "hello",
+                        "protocol_version": 1,
+                        "encodings": [
+                            "json"
+                        ]
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Hello(message)) = parsed {
+            assert_eq!(message.protocol_version, 1);
+            assert_eq!(message.min_protocol_version, None);
+            assert_eq!(message.encodings[0], "json");
+            assert!(message.features.is_empty());
+        } else {
+            panic!("expected hello message");
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use schemas::{
+    Ack,
+    Cancel,
+    Cancellation,
+    Command,
+    Event,
+    EventLookup,
+    GetEvent,
+    Hello,
+    NewEvents,
+    Query,
+    Rebuild,
+    Receipts,
+    Register,
+    RegisterSchema,
+    Registration,
+    Rejected,
+    Reply,
+    Request,
+    SchemaRegistration,
+    Shutdown,
+    Welcome,
+};
+
+/// Message is the envelope for everything sent over websockets between the event bus and its
+/// clients. The `message_type` field of the incoming JSON selects the variant so that each
+/// message is only parsed once.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "message_type")]
+pub enum Message {
+    #[serde(rename = "hello")]
+    Hello(Hello),
+    #[serde(rename = "welcome")]
+    Welcome(Welcome),
+    #[serde(rename = "register")]
+    Register(Register),
+    #[serde(rename = "registration")]
+    Registration(Registration),
+    #[serde(rename = "new")]
+    NewEvents(NewEvents),
+    #[serde(rename = "receipt")]
+    Receipts(Receipts),
+    #[serde(rename = "event")]
+    Event(Event),
+    #[serde(rename = "ack")]
+    Ack(Ack),
+    #[serde(rename = "nack")]
+    Nack(Ack),
+    #[serde(rename = "get_event")]
+    GetEvent(GetEvent),
+    #[serde(rename = "event_lookup")]
+    EventLookup(EventLookup),
+    #[serde(rename = "query")]
+    Query(Query),
+    #[serde(rename = "rebuild")]
+    Rebuild(Rebuild),
+    #[serde(rename = "register_schema")]
+    RegisterSchema(RegisterSchema),
+    #[serde(rename = "schema_registration")]
+    SchemaRegistration(SchemaRegistration),
+    #[serde(rename = "cancel")]
+    Cancel(Cancel),
+    #[serde(rename = "cancellation")]
+    Cancellation(Cancellation),
+    #[serde(rename = "command")]
+    Command(Command),
+    #[serde(rename = "rejected")]
+    Rejected(Rejected),
+    #[serde(rename = "request")]
+    Request(Request),
+    #[serde(rename = "reply")]
+    Reply(Reply),
+    #[serde(rename = "shutdown")]
+    Shutdown(Shutdown),
+}
+
+impl Message {
+    /// The value of the `message_type` field for this message.
+    pub fn message_type(&self) -> &'static str {
+        match *self {
+            Message::Hello(_) => "hello",
+            Message::Welcome(_) => "welcome",
+            Message::Register(_) => "register",
+            Message::Registration(_) => "registration",
+            Message::NewEvents(_) => "new",
+            Message::Receipts(_) => "receipt",
+            Message::Event(_) => "event",
+            Message::Ack(_) => "ack",
+            Message::Nack(_) => "nack",
+            Message::GetEvent(_) => "get_event",
+            Message::EventLookup(_) => "event_lookup",
+            Message::Query(_) => "query",
+            Message::Rebuild(_) => "rebuild",
+            Message::RegisterSchema(_) => "register_schema",
+            Message::SchemaRegistration(_) => "schema_registration",
+            Message::Cancel(_) => "cancel",
+            Message::Cancellation(_) => "cancellation",
+            Message::Command(_) => "command",
+            Message::Rejected(_) => "rejected",
+            Message::Request(_) => "request",
+            Message::Reply(_) => "reply",
+            Message::Shutdown(_) => "shutdown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{from_str, to_value};
+
+    #[test]
+    fn serialize_includes_message_type() {
+        let message = Message::Query(Query {
+            event_types: vec![String::from("deposit")],
+            since: String::from("*"),
+            message_id: None,
+        });
+        let value = to_value(&message).unwrap();
+
+        assert_eq!(value["message_type"], message.message_type());
+        assert_eq!(value["event_types"][0], "deposit");
+    }
+
+    #[test]
+    fn parse_unknown_message_type() {
+        let data = r#"{
+                        "message_type": "unknown",
+                        "event_types": []
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn parse_missing_message_type() {
+        let data = r#"{
+                        "event_types": [],
+                        "since": "*"
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_err());
+    }
+}
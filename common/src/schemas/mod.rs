@@ -1,21 +1,45 @@
+pub mod ack;
+pub mod cancel;
+pub mod command;
 pub mod consistency;
 pub mod event;
+pub mod get_event;
+pub mod hello;
+pub mod message;
 pub mod new_event;
+pub mod predicate;
 pub mod query;
 pub mod rebuild;
 pub mod receipt;
 pub mod register;
+pub mod register_schema;
 pub mod registration;
+pub mod rejected;
+pub mod request;
+pub mod shutdown;
+pub mod welcome;
 
+pub use self::ack::Ack;
+pub use self::cancel::{Cancel, Cancellation};
+pub use self::command::Command;
 pub use self::consistency::{
     Consistency,
     ConsistencyKey,
     ConsistencyValue,
 };
-pub use self::event::Event;
+pub use self::event::{Event, EventId, Metadata};
+pub use self::get_event::{EventLookup, GetEvent};
+pub use self::hello::Hello;
+pub use self::message::Message;
 pub use self::new_event::{NewEvent, NewEvents};
+pub use self::predicate::{Predicate, matches_all};
 pub use self::query::Query;
 pub use self::rebuild::Rebuild;
 pub use self::receipt::{Receipt, Receipts};
-pub use self::register::Register;
+pub use self::register::{DeliveryMode, Register};
+pub use self::register_schema::{RegisterSchema, SchemaRegistration};
 pub use self::registration::Registration;
+pub use self::rejected::Rejected;
+pub use self::request::{Reply, Request};
+pub use self::shutdown::Shutdown;
+pub use self::welcome::Welcome;
@@ -1,22 +1,63 @@
 use serde_json::Value;
 
 use schemas::consistency::Consistency;
+use schemas::event::{EventId, Metadata};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NewEvents {
-    pub message_type: String,
     pub events: Vec<NewEvent>,
+    /// This field is set when either all or none of the events should be accepted. Every receipt
+    /// for an atomic batch has the same status. Atomicity covers storing the events, they are
+    /// sent to Kafka once they are all stored and a `success` receipt doesn't mean that Kafka has
+    /// acknowledged them yet.
+    #[serde(default)]
+    pub atomic: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NewEvent {
+    /// This field contains the id of the event that caused this event, if any.
+    #[serde(default)]
+    pub causation_id: Option<EventId>,
     pub consistency: Consistency,
-    pub correlation_id: u32,
+    pub correlation_id: u64,
     pub data: Value,
+    /// This field contains an RFC 3339 timestamp that the event should not be propagated before.
+    /// The event is accepted and persisted immediately and can be cancelled until it is due.
+    #[serde(default)]
+    pub deliver_at: Option<String>,
+    /// This field contains a number of seconds to wait before propagating the event, it is
+    /// ignored if `deliver_at` is provided.
+    #[serde(default)]
+    pub delay: Option<u64>,
     pub event_type: String,
+    /// This field contains free-form headers that are persisted and propagated with the event.
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// This field contains the version of the schema for the event type that the data should be
+    /// validated against, the latest registered version is used if this is not provided.
+    #[serde(default)]
+    pub schema_version: Option<u32>,
+    /// This field contains an optional id chosen by the client that is echoed in the receipt for
+    /// this event.
+    #[serde(default)]
+    pub message_id: Option<String>,
+    /// This field contains consistency values for other keys that must be current for this event
+    /// to be accepted. Like `consistency`, each value is the one the next event for that key would
+    /// take, but these keys are not advanced by this event.
+    #[serde(default)]
+    pub expected_versions: Vec<Consistency>,
+    /// This field contains an optional key that identifies this event to the event bus. Events
+    /// with the same key are only accepted once within the deduplication window.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
 }
 
+#[cfg(test)]
 mod tests {
+    use schemas::{ConsistencyValue, Message};
+    use serde_json::from_str;
+
     #[test]
     fn parse_new_message_type() {
         let data = r#"{
@@ -37,6 +78,18 @@ mod tests {
                             {
                                 "event_type": "withdrawal",
                                 "correlation_id": 94859829321,
+                                "message_id": "2",
+                                "causation_id": "01C0Q3ZNTHMRDM6ZPZ3RP8KQ6E",
+                                "metadata": {
+                                    "trace": "abc"
+                                },
+                                "idempotency_key": "withdrawal-2837",
+                                "expected_versions": [
+                                    {
+                                        "key": "user-12",
+                                        "value": 3
+                                    }
+                                ],
                                 "data": {
                                     "account": 2837,
                                     "amount": 5
@@ -48,21 +101,73 @@ mod tests {
                             }
                         ]
                    }"#;
-        let parsed: Result<NewEvents, _> = from_str(data);
+        let parsed: Result<Message, _> = from_str(data);
 
         assert!(parsed.is_ok());
-        if let Ok(message) = parsed {
-            assert_eq!(message.message_type, "new");
+        if let Ok(Message::NewEvents(message)) = parsed {
+            assert_eq!(message.atomic, false);
             assert_eq!(message.events[0].event_type, "deposit");
             assert_eq!(message.events[0].correlation_id, 94859829321);
             assert_eq!(message.events[0].data["account"], 837);
             assert_eq!(message.events[0].data["amount"], 3);
             assert_eq!(message.events[0].consistency.key, "testkey");
+            assert_eq!(message.events[0].message_id, None);
+            assert_eq!(message.events[0].idempotency_key, None);
+            assert!(message.events[0].expected_versions.is_empty());
+            assert_eq!(message.events[0].causation_id, None);
+            assert!(message.events[0].metadata.is_empty());
             assert_eq!(message.events[1].event_type, "withdrawal");
             assert_eq!(message.events[1].correlation_id, 94859829321);
             assert_eq!(message.events[1].data["account"], 2837);
             assert_eq!(message.events[1].data["amount"], 5);
             assert_eq!(message.events[1].consistency.key, "testkey");
+            assert_eq!(message.events[1].message_id, Some(String::from("2")));
+            assert_eq!(message.events[1].idempotency_key, Some(String::from("withdrawal-2837")));
+            assert_eq!(message.events[1].causation_id,
+                       Some(String::from("01C0Q3ZNTHMRDM6ZPZ3RP8KQ6E")));
+            assert_eq!(message.events[1].metadata["trace"], "abc");
+            assert_eq!(message.events[1].expected_versions[0].key, "user-12");
+            assert_eq!(message.events[1].expected_versions[0].value,
+                       ConsistencyValue::Explicit(3));
+        } else {
+            panic!("expected new message");
+        }
+    }
+
+    #[test]
+    fn parse_atomic_new_message_type() {
+        let data = r#"{
+                        "message_type": "new",
+                        "atomic": true,
+                        "events": [
+                            {
+                                "event_type": "withdrawal",
+                                "correlation_id": 1,
+                                "data": {},
+                                "consistency": {
+                                    "key": "account-1",
+                                    "value": 4
+                                }
+                            },
+                            {
+                                "event_type": "deposit",
+                                "correlation_id": 1,
+                                "data": {},
+                                "consistency": {
+                                    "key": "account-2",
+                                    "value": "*"
+                                }
+                            }
+                        ]
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::NewEvents(message)) = parsed {
+            assert_eq!(message.atomic, true);
+            assert_eq!(message.events.len(), 2);
+        } else {
+            panic!("expected new message");
         }
     }
 }
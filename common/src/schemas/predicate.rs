@@ -0,0 +1,131 @@
+use serde_json::Value;
+
+/// Predicate is a condition on the data of an event that clients can register with, so that they
+/// are only sent the events that they are interested in. Each predicate refers to a field of the
+/// data by a JSON pointer, such as `/account/id`, and doesn't match if that field is missing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum Predicate {
+    /// The field must be equal to `value`.
+    #[serde(rename = "eq")]
+    Eq { path: String, value: Value },
+    /// The field must be a number between `min` and `max`, inclusive. Either bound can be left
+    /// out.
+    #[serde(rename = "range")]
+    Range {
+        path: String,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    /// The field must be equal to one of `values`.
+    #[serde(rename = "in")]
+    In { path: String, values: Vec<Value> },
+}
+
+impl Predicate {
+    /// Check whether the data of an event satisfies this predicate.
+    pub fn matches(&self, data: &Value) -> bool {
+        match *self {
+            Predicate::Eq { ref path, ref value } => data.pointer(path) == Some(value),
+            Predicate::Range { ref path, min, max } => {
+                match data.pointer(path).and_then(Value::as_f64) {
+                    Some(field) => min.map_or(true, |min| field >= min) &&
+                        max.map_or(true, |max| field <= max),
+                    None => false,
+                }
+            },
+            Predicate::In { ref path, ref values } => {
+                match data.pointer(path) {
+                    Some(field) => values.contains(field),
+                    None => false,
+                }
+            },
+        }
+    }
+}
+
+/// Check whether the data of an event satisfies every predicate. This is true if there are no
+/// predicates.
+pub fn matches_all(predicates: &[Predicate], data: &Value) -> bool {
+    predicates.iter().all(|predicate| predicate.matches(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::from_str;
+
+    fn data() -> Value {
+        from_str(r#"{
+            "account": 837,
+            "amount": 35.5,
+            "details": {
+                "currency": "GBP"
+            }
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn parse_predicates() {
+        let data = r#"[
+                        { "op": "eq", "path": "/account", "value": 837 },
+                        { "op": "range", "path": "/amount", "min": 10 },
+                        { "op": "in", "path": "/details/currency", "values": ["GBP", "EUR"] }
+                   ]"#;
+        let parsed: Vec<Predicate> = from_str(data).unwrap();
+
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[1], Predicate::Range { path: String::from("/amount"), min: Some(10.0),
+                                                 max: None });
+    }
+
+    #[test]
+    fn eq_matches_equal_field() {
+        let predicate = Predicate::Eq { path: String::from("/account"), value: Value::from(837) };
+        assert!(predicate.matches(&data()));
+
+        let predicate = Predicate::Eq { path: String::from("/account"), value: Value::from(1) };
+        assert!(!predicate.matches(&data()));
+    }
+
+    #[test]
+    fn range_is_inclusive() {
+        let predicate = Predicate::Range { path: String::from("/amount"), min: Some(35.5),
+                                           max: Some(35.5) };
+        assert!(predicate.matches(&data()));
+
+        let predicate = Predicate::Range { path: String::from("/amount"), min: None,
+                                           max: Some(35.0) };
+        assert!(!predicate.matches(&data()));
+    }
+
+    #[test]
+    fn range_does_not_match_non_numbers() {
+        let predicate = Predicate::Range { path: String::from("/details/currency"),
+                                           min: None, max: None };
+        assert!(!predicate.matches(&data()));
+    }
+
+    #[test]
+    fn in_matches_nested_field() {
+        let predicate = Predicate::In {
+            path: String::from("/details/currency"),
+            values: vec![Value::from("EUR"), Value::from("GBP")],
+        };
+        assert!(predicate.matches(&data()));
+    }
+
+    #[test]
+    fn missing_field_does_not_match() {
+        let predicate = Predicate::In { path: String::from("/missing"),
+                                        values: vec![Value::Null] };
+        assert!(!predicate.matches(&data()));
+    }
+
+    #[test]
+    fn no_predicates_match_everything() {
+        assert!(matches_all(&[], &data()));
+    }
+}
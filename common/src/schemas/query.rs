@@ -1,13 +1,16 @@
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Query {
-    pub message_type: String,
     pub event_types: Vec<String>,
     pub since: String,
+    /// This field contains an optional id chosen by the client that is echoed in the response to
+    /// this message.
+    #[serde(default)]
+    pub message_id: Option<String>,
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use schemas::Message;
     use serde_json::from_str;
 
     #[test]
@@ -20,14 +23,15 @@ mod tests {
                         ],
                         "since": "2010-06-09T15:20:00-07:00"
                    }"#;
-        let parsed: Result<Query, _> = from_str(data);
+        let parsed: Result<Message, _> = from_str(data);
 
         assert!(parsed.is_ok());
-        if let Ok(message) = parsed {
-            assert_eq!(message.message_type, "query");
+        if let Ok(Message::Query(message)) = parsed {
             assert_eq!(message.event_types[0], "deposit");
             assert_eq!(message.event_types[1], "withdrawal");
             assert_eq!(message.since, "2010-06-09T15:20:00-07:00");
+        } else {
+            panic!("expected query message");
         }
     }
 }
@@ -2,6 +2,8 @@ use schemas::Event;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Rebuild {
-    pub message_type: String,
     pub events: Vec<Event>,
+    /// This field contains the `message_id` of the message this responds to, if one was provided.
+    #[serde(default)]
+    pub message_id: Option<String>,
 }
@@ -1,6 +1,8 @@
+use schemas::consistency::Consistency;
+use schemas::event::EventId;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Receipts {
-    pub message_type: String,
     pub receipts: Vec<Receipt>,
     pub sender: String,
     pub timestamp: String,
@@ -9,5 +11,25 @@ pub struct Receipts {
 #[derive(Clone, Debug, Hash, Serialize, Deserialize)]
 pub struct Receipt {
     pub checksum: String,
+    /// This field contains the consistency assigned to the event by the event bus.
+    #[serde(default)]
+    pub consistency: Option<Consistency>,
+    /// This field contains the reasons that an event with the `invalid` status was rejected.
+    #[serde(default)]
+    pub errors: Vec<String>,
+    /// This field contains the id assigned to the event, it is only present if the event was
+    /// accepted.
+    #[serde(default)]
+    pub event_id: Option<EventId>,
+    /// This field contains the `message_id` of the event this receipt is for, if one was provided.
+    #[serde(default)]
+    pub message_id: Option<String>,
+    /// This field contains the index of the event this receipt is for in the `new` message.
+    #[serde(default)]
+    pub position: usize,
+    /// This field contains how many milliseconds to wait before retrying an event with the
+    /// `rate_limited` status.
+    #[serde(default)]
+    pub retry_after_ms: Option<u64>,
     pub status: String,
 }
@@ -1,11 +1,42 @@
+use schemas::predicate::Predicate;
+
+/// DeliveryMode controls how events are shared between the connected instances of a client type.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeliveryMode {
+    /// Each event is sent to one instance, picked by sticky round robin.
+    #[serde(rename = "competing")]
+    Competing,
+    /// Each event is sent to every instance, which each acknowledge it.
+    #[serde(rename = "fanout")]
+    Fanout,
+}
+
+impl Default for DeliveryMode {
+    fn default() -> Self { DeliveryMode::Competing }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Register {
     pub client_type: String,
     pub event_types: Vec<String>,
-    pub message_type: String,
+    /// Only events whose data satisfies every predicate are sent to this client type, the rest
+    /// are acknowledged on its behalf.
+    #[serde(default)]
+    pub predicates: Vec<Predicate>,
+    #[serde(default)]
+    pub delivery_mode: DeliveryMode,
+    /// This field contains an optional id chosen by the client that is echoed in the response to
+    /// this message.
+    #[serde(default)]
+    pub message_id: Option<String>,
 }
 
+#[cfg(test)]
 mod tests {
+    use super::DeliveryMode;
+    use schemas::Message;
+    use serde_json::from_str;
+
     #[test]
     fn parse_register_message_type() {
         let data = r#"{
@@ -16,14 +47,55 @@ mod tests {
                         ],
                         "client_type": "transaction"
                    }"#;
-        let parsed: Result<Register, _> = from_str(data);
+        let parsed: Result<Message, _> = from_str(data);
 
         assert!(parsed.is_ok());
-        if let Ok(message) = parsed {
-            assert_eq!(message.message_type, "register");
+        if let Ok(Message::Register(message)) = parsed {
             assert_eq!(message.event_types[0], "deposit");
             assert_eq!(message.event_types[1], "withdrawal");
             assert_eq!(message.client_type, "transaction");
+            assert!(message.predicates.is_empty());
+            assert_eq!(message.delivery_mode, DeliveryMode::Competing);
+        } else {
+            panic!("expected register message");
+        }
+    }
+
+    #[test]
+    fn parse_register_fanout_message_type() {
+        let data = r#"{
+                        "message_type": "register",
+                        "event_types": ["account_updated"],
+                        "client_type": "replica",
+                        "delivery_mode": "fanout"
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Register(message)) = parsed {
+            assert_eq!(message.delivery_mode, DeliveryMode::Fanout);
+        } else {
+            panic!("expected register message");
+        }
+    }
+
+    #[test]
+    fn parse_register_with_predicates_message_type() {
+        let data = r#"{
+                        "message_type": "register",
+                        "event_types": ["*"],
+                        "client_type": "notifications",
+                        "predicates": [
+                            { "op": "in", "path": "/channel", "values": ["email", "sms"] }
+                        ]
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Register(message)) = parsed {
+            assert_eq!(message.predicates.len(), 1);
+        } else {
+            panic!("expected register message");
         }
     }
 }
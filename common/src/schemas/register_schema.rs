@@ -0,0 +1,56 @@
+use serde_json::Value;
+
+/// RegisterSchema is sent by clients to add a JSON Schema document that the data of new events
+/// with the given event type and version is validated against.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegisterSchema {
+    pub event_type: String,
+    pub version: u32,
+    pub schema: Value,
+    /// This field contains an optional id chosen by the client that is echoed in the response to
+    /// this message.
+    #[serde(default)]
+    pub message_id: Option<String>,
+}
+
+/// SchemaRegistration is the response to `RegisterSchema`, `errors` is empty if the schema was
+/// registered.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SchemaRegistration {
+    pub event_type: String,
+    pub version: u32,
+    #[serde(default)]
+    pub errors: Vec<String>,
+    /// This field contains the `message_id` of the message this responds to, if one was provided.
+    #[serde(default)]
+    pub message_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemas::Message;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_register_schema_message_type() {
+        let data = r#"{
+                        "message_type": "register_schema",
+                        "event_type": "deposit",
+                        "version": 2,
+                        "schema": {
+                            "type": "object",
+                            "required": ["account", "amount"]
+                        }
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::RegisterSchema(message)) = parsed {
+            assert_eq!(message.event_type, "deposit");
+            assert_eq!(message.version, 2);
+            assert_eq!(message.schema["type"], "object");
+        } else {
+            panic!("expected register schema message");
+        }
+    }
+}
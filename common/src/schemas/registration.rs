@@ -1,6 +1,13 @@
+/// Registration is the response to `Register`.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Registration {
     pub client_type: String,
     pub event_types: Vec<String>,
-    pub message_type: String,
+    /// This field contains why the registration was refused, if it was. Refused registrations
+    /// leave the client as it was.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// This field contains the `message_id` of the message this responds to, if one was provided.
+    #[serde(default)]
+    pub message_id: Option<String>,
 }
@@ -0,0 +1,35 @@
+/// Rejected is sent by the event bus when a frame is refused without being processed because it
+/// exceeds a configured limit, the connection is closed afterwards. `reason` says which limit,
+/// such as `frame_too_large`, `limit` is the limit and `size` is the actual size of the frame.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rejected {
+    pub reason: String,
+    pub limit: usize,
+    pub size: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemas::Message;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_rejected_message_type() {
+        let data = r#"{
+                        "message_type": "rejected",
+                        "reason": "frame_too_large",
+                        "limit": 1048576,
+                        "size": 3145728
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Rejected(message)) = parsed {
+            assert_eq!(message.reason, "frame_too_large");
+            assert_eq!(message.limit, 1048576);
+            assert_eq!(message.size, 3145728);
+        } else {
+            panic!("expected rejected message");
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use serde_json::Value;
+
+use schemas::consistency::ConsistencyKey;
+
+/// Request is sent by a client to ask a question of one instance of another client type. The
+/// event bus picks the instance in the same way as for events, using `key` for stickiness, and
+/// forwards the request with its own `request_id`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub request_id: String,
+    pub client_type: String,
+    pub request_type: String,
+    #[serde(default)]
+    pub key: Option<ConsistencyKey>,
+    pub data: Value,
+    /// This field contains how long to wait for a reply before the event bus replies with an
+    /// error on behalf of the other client.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Reply is sent in response to a `Request` and is routed back to the client that sent the
+/// request. `error` is set if the request could not be answered.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reply {
+    pub request_id: String,
+    #[serde(default)]
+    pub data: Value,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemas::Message;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_request_message_type() {
+        let data = r#"{
+                        "message_type": "request",
+                        "request_id": "1",
+                        "client_type": "accounts",
+                        "request_type": "balance",
+                        "data": {
+                            "account": 837
+                        }
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Request(message)) = parsed {
+            assert_eq!(message.client_type, "accounts");
+            assert_eq!(message.request_type, "balance");
+            assert_eq!(message.key, None);
+            assert_eq!(message.timeout_ms, None);
+            assert_eq!(message.data["account"], 837);
+        } else {
+            panic!("expected request message");
+        }
+    }
+
+    #[test]
+    fn parse_error_reply_message_type() {
+        let data = r#"{
+                        "message_type": "reply",
+                        "request_id": "1",
+                        "error": "timed out"
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Reply(message)) = parsed {
+            assert!(message.data.is_null());
+            assert_eq!(message.error, Some(String::from("timed out")));
+        } else {
+            panic!("expected reply message");
+        }
+    }
+}
@@ -0,0 +1,32 @@
+/// Shutdown is sent by the event bus to every client when it is stopping. Clients should
+/// reconnect to another instance of the event bus, the connection will be closed once
+/// `deadline_ms` milliseconds have passed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Shutdown {
+    pub reason: String,
+    pub deadline_ms: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemas::Message;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_shutdown_message_type() {
+        let data = r#"{
+                        "message_type": "shutdown",
+                        "reason": "terminated",
+                        "deadline_ms": 10000
+                   }"#;
+        let parsed: Result<Message, _> = from_str(data);
+
+        assert!(parsed.is_ok());
+        if let Ok(Message::Shutdown(message)) = parsed {
+            assert_eq!(message.reason, "terminated");
+            assert_eq!(message.deadline_ms, 10000);
+        } else {
+            panic!("expected shutdown message");
+        }
+    }
+}
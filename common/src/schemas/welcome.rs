@@ -0,0 +1,14 @@
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Welcome {
+    /// Either `"accepted"` or `"refused"`. A refused client will be disconnected after receiving
+    /// this message.
+    pub status: String,
+    /// The protocol version that will be used for the remainder of the connection.
+    pub protocol_version: u32,
+    /// The encoding that will be used for the remainder of the connection.
+    pub encoding: Option<String>,
+    /// The optional features that both the client and the event bus support.
+    pub features: Vec<String>,
+    /// Why the client was refused, if it was.
+    pub reason: Option<String>,
+}
@@ -1,19 +1,38 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
+use std::time::Duration;
 
-use actix::{Actor, Address, Context};
-use common::schemas::{ConsistencyKey, ConsistencyValue, Event};
+use actix::{Actor, Address, AsyncContext, Context, SyncAddress};
+use chrono::Local;
+use common::LogContext;
+use common::schemas::{
+    Consistency,
+    ConsistencyKey,
+    ConsistencyValue,
+    DeliveryMode,
+    Event,
+    EventId,
+    Predicate,
+};
 use couchbase::{Bucket, BinaryDocument};
 use failure::{Error, ResultExt};
 use rdkafka::client::EmptyContext;
-use rdkafka::config::ClientConfig;
 use rdkafka::producer::FutureProducer;
 use futures::Future;
+use serde::de::DeserializeOwned;
 use serde_json::{from_str};
 
+use config::{CouchbaseConfig, KafkaConfig};
 use error::ErrorKind;
+use lanes::{LaneSettings, Lanes};
+use limits::SizeLimits;
+use metrics::Metrics;
 use persistence::connect_to_bucket;
+use rate_limit::{RateLimits, TokenBucket};
+use registry::SchemaRegistry;
+use schedule::{ScheduledEvent, load_scheduled};
 use session::Session;
+use upcast::Upcasters;
 
 /// RegisteredTypes represents which types of events a given client is interested in,
 /// all events or a subset of events.
@@ -33,16 +52,66 @@ pub struct SessionDetails {
     pub address: Address<Session>,
     /// This field contains the registered types for this client.
     pub registered_types: RegisteredTypes,
+    /// This field contains the predicates that the data of an event must satisfy for it to be
+    /// sent to this client.
+    pub predicates: Vec<Predicate>,
     /// This field contains which consistency keys this session is handling through sticky
     /// round robin.
     pub consistency_keys: HashSet<(String, ConsistencyKey)>,
     /// This field contains the unacknowledged messages sent to this session that should be resent
     /// if this session disconnects and fails to acknowledge the finished processing of this event.
-    ///
-    /// We store the events as strings here since we are unable to implement `Hash` on the `Value`
-    /// type from `serde_json`. This should not contain the `message_type` field and should not be
-    /// pretty printed.
-    pub unacknowledged_events: HashSet<Event>,
+    /// Events are keyed by their id, which is what clients acknowledge.
+    pub unacknowledged_events: HashMap<EventId, Event>,
+    /// This field contains the events waiting to be sent to this session by priority, and the
+    /// in-flight budget that each priority has used. Queued events are also unacknowledged.
+    pub lanes: Lanes,
+    /// This field contains the bucket that limits how quickly this session can publish events,
+    /// if sessions are rate limited.
+    pub rate_limiter: Option<TokenBucket>,
+}
+
+/// IdempotencyRecord contains what is needed to answer a duplicate of an accepted event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IdempotencyRecord {
+    pub event_id: EventId,
+    pub consistency: Consistency,
+    /// This field contains the raw timestamp that the original event was accepted at.
+    pub accepted_at: i64,
+}
+
+/// PendingRequest is kept for each request that has been forwarded to a client until it is
+/// replied to or times out.
+#[derive(Clone, Debug)]
+pub struct PendingRequest {
+    /// This field contains the address of the client that sent the request.
+    pub origin: SocketAddr,
+    /// This field contains the id that the client that sent the request used for it.
+    pub request_id: String,
+    /// This field contains the address of the client that the request was forwarded to.
+    pub responder: SocketAddr,
+}
+
+/// BusSettings contains the tunable behaviour of the bus.
+#[derive(Clone, Debug)]
+pub struct BusSettings {
+    /// This field contains the length of the deduplication window in seconds.
+    pub dedup_window: i64,
+    pub lanes: LaneSettings,
+    pub rate_limits: RateLimits,
+    pub size_limits: SizeLimits,
+    /// This field contains how long the bus has to shut down in once it is signalled to stop.
+    pub shutdown_timeout: Duration,
+    /// This field contains how often to check whether sessions have settled their events while
+    /// shutting down.
+    pub drain_interval: Duration,
+    /// This field contains how often to check for scheduled events that are due.
+    pub schedule_interval: Duration,
+    /// This field contains how long a request waits for a reply if it doesn't have its own
+    /// timeout, in milliseconds.
+    pub request_timeout_ms: u64,
+    /// This field contains how long pending events are replayed to the instances of a fan-out
+    /// client type that register after the first one.
+    pub fanout_replay_window: Duration,
 }
 
 /// Bus maintains the state that pertains to all clients and allows clients to send messages
@@ -64,12 +133,76 @@ pub struct Bus {
     /// This field contains a mapping from each sequence key to the `SocketAddr` of the client
     /// that handles the events for that key. It is checked before the round robin state.
     pub sticky_consistency: HashMap<(String, ConsistencyKey), SocketAddr>,
+    /// This field contains how events are delivered to the instances of each client type, as
+    /// declared by the latest registration for that type.
+    pub delivery_modes: HashMap<String, DeliveryMode>,
     /// This field contains all messages that are not yet sent out to a client type and should be.
+    /// This is persisted when the bus shuts down, along with any unacknowledged events, and
+    /// updated when the events loaded from it at startup are requeued.
     pub pending_events: HashMap<String, Vec<Event>>,
+    /// This field contains the pending events of fan-out client types that are being replayed to
+    /// each instance as it registers, for a short time after the first instance registers.
+    pub fanout_replay: HashMap<String, Vec<Event>>,
     /// This field contains the topic that events should be sent to in Kafka.
     pub topic: String,
     /// This field contains the mapping of the sequence key to the last seen sequence value.
     pub consistency: HashMap<ConsistencyKey, ConsistencyValue>,
+    /// This field contains the events that have been accepted but should not be propagated
+    /// until their `deliver_at` time. Each is persisted in its own document so that it survives
+    /// restarts.
+    pub scheduled: HashMap<EventId, ScheduledEvent>,
+    /// This field contains the requests that have been forwarded to clients and are waiting for a
+    /// reply, by the id that the event bus assigned to them.
+    pub pending_requests: HashMap<String, PendingRequest>,
+    /// This field contains the idempotency keys of events accepted within the deduplication
+    /// window since the bus started. Each is also persisted in couchbase until the window has
+    /// passed, so that duplicates of events accepted before a restart are detected.
+    pub idempotency_keys: HashMap<String, IdempotencyRecord>,
+    /// This field contains the idempotency keys in the order they were accepted so that they can
+    /// be expired once they fall outside of the deduplication window.
+    pub idempotency_expiry: VecDeque<(i64, String)>,
+    /// This field contains the length of the deduplication window in seconds.
+    pub dedup_window: i64,
+    /// This field contains when the bus was created as a unix timestamp. Once the deduplication
+    /// window has passed since then, every idempotency key within the window is in memory.
+    pub created_at: i64,
+    /// This field contains the schemas that the data of new events is validated against.
+    pub schemas: SchemaRegistry,
+    /// This field contains the upcasters that are applied to events before they are sent to
+    /// clients.
+    pub upcasters: Upcasters,
+    /// This field contains the priority of each event type and the in-flight budget of each
+    /// priority.
+    pub lanes: LaneSettings,
+    /// This field contains the limits on how quickly sessions and client types can publish.
+    pub rate_limits: RateLimits,
+    /// This field contains the buckets that limit how quickly each client type can publish
+    /// events, shared between the sessions of that client type.
+    pub client_type_rate_limiters: HashMap<String, TokenBucket>,
+    /// This field contains the limits on the size of batches and the data of events.
+    pub size_limits: SizeLimits,
+    /// This field contains the counters, gauges and histograms that are exported by the metrics
+    /// endpoint.
+    pub metrics: Metrics,
+    /// This field contains the address of the bus itself, for results that arrive after the
+    /// handler that caused them has returned. It is set once the bus has started.
+    pub address: Option<Address<Bus>>,
+    /// This field contains how long the bus has to shut down in once it is signalled to stop.
+    pub shutdown_timeout: Duration,
+    /// This field contains how often to check whether sessions have settled their events while
+    /// shutting down, this is also how long is left for the final messages to reach clients
+    /// before exiting.
+    pub drain_interval: Duration,
+    /// This field contains how often to check for scheduled events that are due.
+    pub schedule_interval: Duration,
+    /// This field contains how long a request waits for a reply if it doesn't have its own
+    /// timeout, in milliseconds.
+    pub request_timeout_ms: u64,
+    /// This field contains how long pending events are replayed to the instances of a fan-out
+    /// client type that register after the first one.
+    pub fanout_replay_window: Duration,
+    /// This field is set once the bus has started shutting down.
+    pub shutting_down: bool,
     /// This field contains the producer that will be used when sending messages to Kafka.
     pub producer: FutureProducer<EmptyContext>,
     /// This field contains the couchbase bucket that will be used when persisting events to
@@ -81,51 +214,116 @@ pub struct Bus {
 }
 
 impl Bus {
-    pub fn launch(brokers: &str, topic: &str, couchbase_host: &str) -> Result<Address<Self>, Error> {
-        let producer = ClientConfig::new()
-            .set("bootstrap.servers", brokers)
-            .set("produce.offset.report", "true")
+    pub fn launch(kafka: &KafkaConfig, topic: &str, couchbase: &CouchbaseConfig,
+                  settings: BusSettings, schemas: SchemaRegistry,
+                  upcasters: Upcasters) -> Result<(Address<Self>, SyncAddress<Self>), Error> {
+        let producer = kafka.producer_config()
             .create::<FutureProducer<_>>()
             .context(ErrorKind::KafkaProducerCreation)?;
 
-        let event_bucket = connect_to_bucket(couchbase_host, "events")?;
-        let consistency_bucket = connect_to_bucket(couchbase_host, "consistency")?;
-
-        let consistency = match consistency_bucket.get::<BinaryDocument, _>("consistency").wait() {
-            Ok(doc) => {
-                let content = doc.content_as_str()?;
-                match content {
-                    Some(text) => {
-                        info!("found existing hashmap in consistency bucket, using that");
-                        let map: HashMap<ConsistencyKey, ConsistencyValue> = from_str(text)?;
-                        map
-                    },
-                    None => {
-                        info!("empty consistency map found in couchbase, creating new map");
-                        HashMap::new()
-                    },
-                }
-            },
-            Err(e) => {
-                info!("hashmap does not exist, creating new map: error='{:?}'", e);
-                HashMap::new()
-            },
-        };
+        let event_bucket = connect_to_bucket(couchbase, "events")?;
+        let consistency_bucket = connect_to_bucket(couchbase, "consistency")?;
 
-        Ok(Self {
+        let consistency = load_map(&consistency_bucket, "consistency")?;
+        let scheduled = load_scheduled(&event_bucket)?;
+        let legacy_scheduled = load_map(&consistency_bucket, "scheduled")?;
+        // The pending events document is kept until the events in it have been requeued, it is
+        // updated as the first client of each type registers.
+        let pending_events = load_map(&consistency_bucket, "pending")?;
+
+        let mut bus = Self {
             sessions: HashMap::new(),
             round_robin_state: HashMap::new(),
             sticky_consistency: HashMap::new(),
-            pending_events: HashMap::new(),
+            delivery_modes: HashMap::new(),
+            pending_events: pending_events,
+            fanout_replay: HashMap::new(),
             topic: topic.to_owned(),
             consistency: consistency,
+            scheduled: scheduled,
+            pending_requests: HashMap::new(),
+            idempotency_keys: HashMap::new(),
+            idempotency_expiry: VecDeque::new(),
+            dedup_window: settings.dedup_window,
+            created_at: Local::now().timestamp(),
+            schemas: schemas,
+            upcasters: upcasters,
+            lanes: settings.lanes,
+            rate_limits: settings.rate_limits,
+            client_type_rate_limiters: HashMap::new(),
+            size_limits: settings.size_limits,
+            metrics: Metrics::new(),
+            address: None,
+            shutdown_timeout: settings.shutdown_timeout,
+            drain_interval: settings.drain_interval,
+            schedule_interval: settings.schedule_interval,
+            request_timeout_ms: settings.request_timeout_ms,
+            fanout_replay_window: settings.fanout_replay_window,
+            shutting_down: false,
             producer: producer,
             event_bucket: event_bucket,
             consistency_bucket: consistency_bucket,
-        }.start())
+        };
+        bus.migrate_scheduled(legacy_scheduled);
+
+        Ok(bus.start())
+    }
+
+    /// Create a log context for handling a message from a session, with the client type of the
+    /// session once it has registered.
+    pub fn session_log_context(&self, addr: &SocketAddr) -> LogContext {
+        let client_type = self.sessions.get(addr).and_then(|details| details.client_type.as_ref());
+        LogContext::new().field("session", addr).optional_field("client_type", client_type)
+    }
+
+    /// Create a log context for handling an acknowledgement of an event from a session, with the
+    /// correlation id of the event if it was sent to the session.
+    pub fn acknowledgement_log_context(&self, addr: &SocketAddr,
+                                       event_id: &EventId) -> LogContext {
+        let correlation_id = self.sessions.get(addr)
+            .and_then(|details| details.unacknowledged_events.get(event_id))
+            .map(|event| event.correlation_id);
+        self.session_log_context(addr)
+            .field("event_id", event_id)
+            .optional_field("correlation_id", correlation_id)
+    }
+}
+
+/// Load a map that was persisted in a couchbase bucket, creating a new map if there isn't one.
+fn load_map<T: DeserializeOwned + Default>(bucket: &Bucket,
+                                           document_id: &str) -> Result<T, Error> {
+    match bucket.get::<BinaryDocument, _>(document_id).wait() {
+        Ok(doc) => {
+            let content = doc.content_as_str()?;
+            match content {
+                Some(text) => {
+                    info!("found existing map in couchbase, using that: document_id='{}'",
+                          document_id);
+                    let map: T = from_str(text)?;
+                    Ok(map)
+                },
+                None => {
+                    info!("empty map found in couchbase, creating new map: document_id='{}'",
+                          document_id);
+                    Ok(T::default())
+                },
+            }
+        },
+        Err(e) => {
+            info!("map does not exist, creating new map: document_id='{}' error='{:?}'",
+                  document_id, e);
+            Ok(T::default())
+        },
     }
 }
 
 impl Actor for Bus {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        info!("bus started: scheduled='{}'", self.scheduled.len());
+        self.address = Some(ctx.address());
+        self.deliver_scheduled_events_later(ctx);
+        self.subscribe_to_signals(ctx);
+    }
 }
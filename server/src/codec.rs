@@ -0,0 +1,262 @@
+use std::cmp;
+
+use bytes::BytesMut;
+use tokio_io::codec::{Decoder, Encoder};
+use websocket::WebSocketError;
+use websocket::async::{MessageCodec, MsgCodecCtx};
+use websocket::message::OwnedMessage;
+
+/// Frame is what is read from a client's connection, either a complete message or a notice that
+/// a message was too large and has been discarded.
+#[derive(Debug)]
+pub enum Frame {
+    Message(OwnedMessage),
+    /// The message had at least this many bytes of payload, which was over the limit.
+    TooLarge(usize),
+}
+
+/// LimitedMessageCodec wraps the websocket message codec and checks the length in the header of
+/// each data frame before its payload is read, so that messages over the limit are skipped as
+/// they arrive rather than being buffered in full. The payloads of fragmented messages count
+/// towards the same limit.
+pub struct LimitedMessageCodec {
+    inner: MessageCodec<OwnedMessage>,
+    max_frame_bytes: Option<usize>,
+    /// This field contains the size of the fragments of the current message that have been read.
+    fragment_bytes: usize,
+    /// This field contains how many more bytes of a discarded frame are still to be skipped.
+    skip_bytes: usize,
+    /// This field is set while the remaining fragments of a discarded message are skipped.
+    discarding: bool,
+}
+
+/// FrameHeader contains the parts of a websocket frame header needed to check its size.
+struct FrameHeader {
+    finished: bool,
+    opcode: u8,
+    header_len: usize,
+    payload_len: usize,
+}
+
+impl LimitedMessageCodec {
+    pub fn new(max_frame_bytes: Option<usize>) -> Self {
+        Self {
+            inner: MessageCodec::default(MsgCodecCtx::Server),
+            max_frame_bytes,
+            fragment_bytes: 0,
+            skip_bytes: 0,
+            discarding: false,
+        }
+    }
+
+    /// Drop as much of a discarded frame as has arrived, returning true once all of it has.
+    fn skip(&mut self, src: &mut BytesMut) -> bool {
+        let count = cmp::min(self.skip_bytes, src.len());
+        src.split_to(count);
+        self.skip_bytes -= count;
+        self.skip_bytes == 0
+    }
+
+    /// Start skipping the frame at the front of the buffer.
+    fn discard(&mut self, header: &FrameHeader) {
+        self.skip_bytes = header.header_len + header.payload_len;
+        self.discarding = !header.finished;
+        self.fragment_bytes = 0;
+        // Any earlier fragments of the message are buffered by the inner codec.
+        self.inner = MessageCodec::default(MsgCodecCtx::Server);
+    }
+}
+
+/// Read the header of the frame at the front of the buffer, if all of it has arrived.
+fn read_header(src: &BytesMut) -> Option<FrameHeader> {
+    if src.len() < 2 {
+        return None;
+    }
+
+    let extended_len = match src[1] & 0x7F {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    let mask_len = if src[1] & 0x80 != 0 { 4 } else { 0 };
+    let header_len = 2 + extended_len + mask_len;
+    if src.len() < header_len {
+        return None;
+    }
+
+    let payload_len = match extended_len {
+        0 => u64::from(src[1] & 0x7F),
+        _ => src[2..2 + extended_len].iter().fold(0, |len, byte| (len << 8) | u64::from(*byte)),
+    };
+
+    Some(FrameHeader {
+        finished: src[0] & 0x80 != 0,
+        opcode: src[0] & 0x0F,
+        header_len,
+        payload_len: cmp::min(payload_len, usize::max_value() as u64) as usize,
+    })
+}
+
+impl Decoder for LimitedMessageCodec {
+    type Item = Frame;
+    type Error = WebSocketError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, WebSocketError> {
+        let limit = match self.max_frame_bytes {
+            Some(limit) => limit,
+            None => return Ok(self.inner.decode(src)?.map(Frame::Message)),
+        };
+
+        loop {
+            if self.skip_bytes > 0 && !self.skip(src) {
+                return Ok(None);
+            }
+
+            let header = match read_header(src) {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+
+            // Control frames can be sent between the fragments of a message, they are limited
+            // to 125 bytes by the protocol.
+            let is_control = header.opcode >= 8;
+            if !is_control {
+                if self.discarding && header.opcode == 0 {
+                    self.skip_bytes = header.header_len + header.payload_len;
+                    self.discarding = !header.finished;
+                    continue;
+                }
+                self.discarding = false;
+
+                let size = if header.opcode == 0 {
+                    self.fragment_bytes.saturating_add(header.payload_len)
+                } else {
+                    header.payload_len
+                };
+                if size > limit {
+                    self.discard(&header);
+                    return Ok(Some(Frame::TooLarge(size)));
+                }
+            }
+
+            // Only a single frame is given to the inner codec at a time, so that each is checked
+            // before it is read.
+            let frame_len = header.header_len + header.payload_len;
+            if src.len() < frame_len {
+                return Ok(None);
+            }
+            let mut frame = src.split_to(frame_len);
+
+            if !is_control {
+                self.fragment_bytes = if header.finished {
+                    0
+                } else {
+                    self.fragment_bytes + header.payload_len
+                };
+            }
+
+            if let Some(message) = self.inner.decode(&mut frame)? {
+                return Ok(Some(Frame::Message(message)));
+            }
+        }
+    }
+}
+
+impl Encoder for LimitedMessageCodec {
+    type Item = OwnedMessage;
+    type Error = WebSocketError;
+
+    fn encode(&mut self, item: OwnedMessage, dst: &mut BytesMut) -> Result<(), WebSocketError> {
+        self.inner.encode(item, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_io::codec::Decoder;
+    use websocket::message::OwnedMessage;
+    use websocket::ws::Message;
+
+    use super::{Frame, LimitedMessageCodec};
+
+    fn frame(opcode: u8, finished: bool, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![if finished { 0x80 | opcode } else { opcode }];
+        if payload.len() < 126 {
+            bytes.push(0x80 | payload.len() as u8);
+        } else {
+            bytes.push(0x80 | 126);
+            bytes.push((payload.len() >> 8) as u8);
+            bytes.push(payload.len() as u8);
+        }
+        // A mask of zeroes leaves the payload as it is.
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn text(frame: Option<Frame>) -> String {
+        match frame {
+            Some(Frame::Message(OwnedMessage::Text(text))) => text,
+            other => panic!("expected text message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_frame_within_limit() {
+        let mut codec = LimitedMessageCodec::new(Some(16));
+        let mut src = BytesMut::from(frame(1, true, b"hello"));
+
+        assert_eq!(text(codec.decode(&mut src).unwrap()), "hello");
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn reject_frame_over_limit_before_payload_arrives() {
+        let mut codec = LimitedMessageCodec::new(Some(16));
+        let whole = frame(1, true, &[b'a'; 200]);
+        let mut src = BytesMut::from(&whole[..8]);
+
+        match codec.decode(&mut src).unwrap() {
+            Some(Frame::TooLarge(size)) => assert_eq!(size, 200),
+            other => panic!("expected too large, got {:?}", other),
+        }
+
+        // The rest of the frame is skipped as it arrives, and the next frame is read as normal.
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&whole[8..]);
+        src.extend_from_slice(&frame(1, true, b"next"));
+        assert_eq!(text(codec.decode(&mut src).unwrap()), "next");
+    }
+
+    #[test]
+    fn reject_fragmented_message_over_limit() {
+        let mut codec = LimitedMessageCodec::new(Some(16));
+        let mut src = BytesMut::from(frame(1, false, &[b'a'; 10]));
+        src.extend_from_slice(&frame(0, false, &[b'b'; 10]));
+        src.extend_from_slice(&frame(9, true, b""));
+        src.extend_from_slice(&frame(0, true, &[b'c'; 10]));
+        src.extend_from_slice(&frame(1, true, b"next"));
+
+        match codec.decode(&mut src).unwrap() {
+            Some(Frame::TooLarge(size)) => assert_eq!(size, 20),
+            other => panic!("expected too large, got {:?}", other),
+        }
+        match codec.decode(&mut src).unwrap() {
+            Some(Frame::Message(OwnedMessage::Ping(_))) => { },
+            other => panic!("expected ping, got {:?}", other),
+        }
+        assert_eq!(text(codec.decode(&mut src).unwrap()), "next");
+    }
+
+    #[test]
+    fn decode_without_limit() {
+        let mut codec = LimitedMessageCodec::new(None);
+        let message = OwnedMessage::Text(String::from("unlimited"));
+        let mut bytes = Vec::new();
+        message.serialize(&mut bytes, true).unwrap();
+        let mut src = BytesMut::from(bytes);
+
+        assert_eq!(text(codec.decode(&mut src).unwrap()), "unlimited");
+    }
+}
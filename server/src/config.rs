@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use common::LoggingConfig;
+use common::config::{deserialize_string_map, load_config};
+use failure::Error;
+use rdkafka::config::ClientConfig;
+
+use bus::BusSettings;
+use error::ErrorKind;
+use lanes::LaneSettings;
+use limits::SizeLimits;
+use rate_limit::{RateLimit, RateLimits};
+
+/// Environment variables starting with this prefix override the configuration file.
+pub const ENV_PREFIX: &str = "BUSD_";
+
+/// Config contains every tunable of the event bus daemon. It is loaded from the defaults, then the
+/// configuration file, then environment variables and then command line flags, each overriding
+/// the last.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub logging: LoggingConfig,
+    pub server: ServerConfig,
+    pub kafka: KafkaConfig,
+    pub couchbase: CouchbaseConfig,
+    pub lanes: LaneSettings,
+    pub rate_limits: RateLimits,
+    pub limits: SizeLimits,
+}
+
+/// ServerConfig contains the settings of the websocket server and the bus itself.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// This field contains the host and port to bind the websocket server to.
+    pub bind: String,
+    /// This field contains the host and port to bind the metrics http server to, it is not
+    /// started if this is not provided.
+    pub metrics_bind: Option<String>,
+    /// This field contains the host and port to bind the admin http server to, it is not started
+    /// if this is not provided.
+    pub admin_bind: Option<String>,
+    /// This field contains the bearer token that requests to the admin API must provide, it is
+    /// required if the admin http server is started.
+    pub admin_token: Option<String>,
+    /// This field contains the topic to send and receive events on.
+    pub topic: String,
+    /// This field contains the seconds that idempotency keys are remembered for.
+    pub dedup_window_secs: i64,
+    /// This field contains the seconds that the daemon has to shut down in once it is signalled
+    /// to stop.
+    pub shutdown_timeout_secs: u64,
+    /// This field contains how often to check whether sessions have settled their events while
+    /// shutting down, in milliseconds. This is also how long is left for the final messages to
+    /// reach clients before exiting.
+    pub drain_interval_ms: u64,
+    /// This field contains how often to check for scheduled events that are due, in milliseconds.
+    pub schedule_interval_ms: u64,
+    /// This field contains how long a request waits for a reply if it doesn't have its own
+    /// timeout, in milliseconds.
+    pub request_timeout_ms: u64,
+    /// This field contains how long pending events are replayed to the instances of a fan-out
+    /// client type that register after the first one, in milliseconds.
+    pub fanout_replay_ms: u64,
+    /// This field contains the directory of JSON schemas to validate events against.
+    pub schema_dir: Option<String>,
+    /// This field contains the directory of JSON patches to upgrade old events with.
+    pub upcaster_dir: Option<String>,
+}
+
+impl ServerConfig {
+    /// The bearer token of the admin API, if one that isn't empty is provided.
+    pub fn admin_token(&self) -> Option<&String> {
+        self.admin_token.as_ref().filter(|token| !token.is_empty())
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: String::from("localhost:8081"),
+            metrics_bind: None,
+            admin_bind: None,
+            admin_token: None,
+            topic: String::from("sed-instance-1"),
+            dedup_window_secs: 600,
+            shutdown_timeout_secs: 10,
+            drain_interval_ms: 500,
+            schedule_interval_ms: 1000,
+            request_timeout_ms: 30000,
+            fanout_replay_ms: 30000,
+            schema_dir: None,
+            upcaster_dir: None,
+        }
+    }
+}
+
+/// KafkaConfig contains the settings of the Kafka producer and consumer. The `producer` and
+/// `consumer` tables are passed through to librdkafka as they are.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    /// This field contains the broker list in Kafka format.
+    pub brokers: String,
+    /// This field contains the consumer group name.
+    pub group: String,
+    /// This field contains how many recently propagated event ids are remembered so that events
+    /// redelivered by Kafka are not propagated twice.
+    pub recent_event_ids: usize,
+    #[serde(deserialize_with = "deserialize_string_map")]
+    pub producer: BTreeMap<String, String>,
+    #[serde(deserialize_with = "deserialize_string_map")]
+    pub consumer: BTreeMap<String, String>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        let mut producer = BTreeMap::new();
+        producer.insert(String::from("produce.offset.report"), String::from("true"));
+
+        let mut consumer = BTreeMap::new();
+        consumer.insert(String::from("enable.partition.eof"), String::from("false"));
+        consumer.insert(String::from("session.timeout.ms"), String::from("6000"));
+
+        Self {
+            brokers: String::from("localhost:9092"),
+            group: String::from(crate_name!()),
+            recent_event_ids: 10000,
+            producer: producer,
+            consumer: consumer,
+        }
+    }
+}
+
+impl KafkaConfig {
+    /// Create the client configuration for the producer, with the passthrough settings applied.
+    pub fn producer_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers);
+        for (key, value) in &self.producer {
+            config.set(key, value);
+        }
+        config
+    }
+
+    /// Create the client configuration for the consumer, with the passthrough settings applied.
+    pub fn consumer_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers)
+            .set("group.id", &self.group)
+            .set("enable.auto.commit", "false");
+        for (key, value) in &self.consumer {
+            config.set(key, value);
+        }
+        config
+    }
+}
+
+/// CouchbaseConfig contains the settings used to connect to Couchbase.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CouchbaseConfig {
+    pub host: String,
+    pub username: String,
+    pub password: String,
+    /// This field contains how many times opening a bucket is retried before giving up.
+    pub max_retries: u32,
+    pub retry_interval_ms: u64,
+}
+
+impl Default for CouchbaseConfig {
+    fn default() -> Self {
+        Self {
+            host: String::from("couchbase.db"),
+            username: String::from("connect"),
+            password: String::from("connect"),
+            max_retries: 60,
+            retry_interval_ms: 1000,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the file named by the `config` argument or the `BUSD_CONFIG`
+    /// environment variable, if either is provided, and from the environment.
+    pub fn load(arguments: &ArgMatches) -> Result<Self, Error> {
+        let path_var = format!("{}CONFIG", ENV_PREFIX);
+        let path = arguments.value_of("config").map(String::from)
+            .or_else(|| env::var(&path_var).ok());
+        let vars = env::vars().filter(|&(ref name, _)| *name != path_var);
+
+        load_config(path.as_ref().map(Path::new), ENV_PREFIX, vars)
+    }
+
+    /// Override the configuration with the flags of the `server` subcommand that were provided.
+    pub fn apply_arguments(&mut self, arguments: &ArgMatches) -> Result<(), Error> {
+        if let Some(bind) = arguments.value_of("bind") {
+            self.server.bind = bind.to_owned();
+        }
+        if let Some(metrics_bind) = arguments.value_of("metrics_bind") {
+            self.server.metrics_bind = Some(metrics_bind.to_owned());
+        }
+        if let Some(admin_bind) = arguments.value_of("admin_bind") {
+            self.server.admin_bind = Some(admin_bind.to_owned());
+        }
+        if let Some(topic) = arguments.value_of("topic") {
+            self.server.topic = topic.to_owned();
+        }
+        if let Some(brokers) = arguments.value_of("brokers") {
+            self.kafka.brokers = brokers.to_owned();
+        }
+        if let Some(group) = arguments.value_of("group") {
+            self.kafka.group = group.to_owned();
+        }
+        if let Some(couchbase_host) = arguments.value_of("couchbase_host") {
+            self.couchbase.host = couchbase_host.to_owned();
+        }
+        if arguments.is_present("dedup_window") {
+            self.server.dedup_window_secs = value_t!(arguments, "dedup_window", i64).ok()
+                .and_then(|window| if window >= 0 { Some(window) } else { None })
+                .ok_or(ErrorKind::InvalidDedupWindowArgument)?;
+        }
+        if let Some(schema_dir) = arguments.value_of("schema_dir") {
+            self.server.schema_dir = Some(schema_dir.to_owned());
+        }
+        if let Some(upcaster_dir) = arguments.value_of("upcaster_dir") {
+            self.server.upcaster_dir = Some(upcaster_dir.to_owned());
+        }
+        if arguments.is_present("shutdown_timeout") {
+            self.server.shutdown_timeout_secs = value_t!(arguments, "shutdown_timeout", u64)
+                .map_err(|_| ErrorKind::InvalidShutdownTimeoutArgument)?;
+        }
+
+        if let Some(priorities) = arguments.values_of("priority") {
+            for priority in priorities {
+                self.lanes.add_priority(priority)?;
+            }
+        }
+        if arguments.is_present("prefetch") {
+            self.lanes.prefetch = value_t!(arguments, "prefetch", usize).ok()
+                .and_then(|prefetch| if prefetch > 0 { Some(prefetch) } else { None })
+                .ok_or(ErrorKind::InvalidPrefetchArgument)?;
+        }
+        if let Some(lane_prefetches) = arguments.values_of("lane_prefetch") {
+            for lane_prefetch in lane_prefetches {
+                self.lanes.add_lane_prefetch(lane_prefetch)?;
+            }
+        }
+
+        if let Some(limit) = arguments.value_of("session_rate_limit") {
+            self.rate_limits.session = Some(RateLimit::parse(limit)?);
+        }
+        if let Some(limits) = arguments.values_of("client_type_rate_limit") {
+            for limit in limits {
+                self.rate_limits.add_client_type(limit)?;
+            }
+        }
+
+        if let Some(limit) = optional_limit(arguments, "max_frame_bytes")? {
+            self.limits.max_frame_bytes = Some(limit);
+        }
+        if let Some(limit) = optional_limit(arguments, "max_batch_events")? {
+            self.limits.max_batch_events = Some(limit);
+        }
+        if let Some(limit) = optional_limit(arguments, "max_data_bytes")? {
+            self.limits.max_data_bytes = Some(limit);
+        }
+        if arguments.is_present("offload_payloads") {
+            self.limits.offload_payloads = true;
+        }
+
+        Ok(())
+    }
+
+    /// Check the parts of the configuration that are not checked when it is loaded.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.logging.validate()?;
+        if self.server.admin_bind.is_some() && self.server.admin_token().is_none() {
+            return Err(Error::from(ErrorKind::MissingAdminToken));
+        }
+
+        check_value("server.dedup_window_secs", self.server.dedup_window_secs >= 0)?;
+        check_value("server.shutdown_timeout_secs", self.server.shutdown_timeout_secs > 0)?;
+        check_value("server.drain_interval_ms", self.server.drain_interval_ms > 0)?;
+        check_value("server.schedule_interval_ms", self.server.schedule_interval_ms > 0)?;
+        check_value("server.request_timeout_ms", self.server.request_timeout_ms > 0)?;
+        check_value("kafka.recent_event_ids", self.kafka.recent_event_ids > 0)?;
+        check_value("couchbase.retry_interval_ms", self.couchbase.retry_interval_ms > 0)?;
+
+        check_value("lanes.prefetch", self.lanes.prefetch > 0)?;
+        for lane in &self.lanes.lane_prefetch {
+            check_value("lanes.lane_prefetch", lane.prefetch > 0)?;
+        }
+
+        let rate_limits = self.rate_limits.session.iter()
+            .chain(self.rate_limits.client_types.values());
+        for limit in rate_limits {
+            check_value("rate_limits", limit.is_valid())?;
+        }
+
+        check_value("limits.max_frame_bytes", self.limits.max_frame_bytes != Some(0))?;
+        check_value("limits.max_batch_events", self.limits.max_batch_events != Some(0))?;
+        check_value("limits.max_data_bytes", self.limits.max_data_bytes != Some(0))?;
+        Ok(())
+    }
+
+    /// The configuration with secrets hidden, for printing.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        config.couchbase.password = String::from("<redacted>");
+        if config.server.admin_token.is_some() {
+            config.server.admin_token = Some(String::from("<redacted>"));
+        }
+        config
+    }
+
+    pub fn bus_settings(&self) -> BusSettings {
+        BusSettings {
+            dedup_window: self.server.dedup_window_secs,
+            lanes: self.lanes.clone(),
+            rate_limits: self.rate_limits.clone(),
+            size_limits: self.limits.clone(),
+            shutdown_timeout: Duration::from_secs(self.server.shutdown_timeout_secs),
+            drain_interval: Duration::from_millis(self.server.drain_interval_ms),
+            schedule_interval: Duration::from_millis(self.server.schedule_interval_ms),
+            request_timeout_ms: self.server.request_timeout_ms,
+            fanout_replay_window: Duration::from_millis(self.server.fanout_replay_ms),
+        }
+    }
+}
+
+/// Check that a numeric value of the configuration is in range, logging the key if it isn't.
+fn check_value(key: &str, valid: bool) -> Result<(), Error> {
+    if valid {
+        return Ok(());
+    }
+
+    error!("configuration value is out of range: key='{}'", key);
+    Err(Error::from(ErrorKind::InvalidConfigValue))
+}
+
+/// Parse a size limit argument, there is no limit if it was not provided.
+fn optional_limit(arguments: &ArgMatches, name: &str) -> Result<Option<usize>, Error> {
+    if !arguments.is_present(name) {
+        return Ok(None);
+    }
+
+    let limit = value_t!(arguments, name, usize).map_err(|_| {
+        ErrorKind::InvalidSizeLimitArgument
+    })?;
+    Ok(Some(limit))
+}
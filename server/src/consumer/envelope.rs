@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use common::schemas::Event;
+use failure::{Error, ResultExt};
+use serde_json::from_str;
+
+use error::ErrorKind;
+
+/// The prefix of the headers that carry the metadata of an event.
+const METADATA_PREFIX: &str = "metadata.";
+
+/// Envelope is what an event is written to Kafka as. The version of rdkafka in use has no support
+/// for message headers, so the headers are carried in the payload alongside the event.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Envelope {
+    /// This field contains the ids that trace the event and its metadata, so that they can be read
+    /// without parsing the event.
+    pub headers: BTreeMap<String, String>,
+    pub event: Event,
+}
+
+impl Envelope {
+    /// Wrap an event with headers for its correlation and causation ids and its metadata.
+    pub fn new(event: Event) -> Self {
+        let mut headers = BTreeMap::new();
+        headers.insert(String::from("event_id"), event.id.clone());
+        headers.insert(String::from("correlation_id"), event.correlation_id.to_string());
+        if let Some(ref causation_id) = event.causation_id {
+            headers.insert(String::from("causation_id"), causation_id.clone());
+        }
+        for (name, value) in event.metadata.iter() {
+            headers.insert(format!("{}{}", METADATA_PREFIX, name), value.clone());
+        }
+
+        Self { headers, event }
+    }
+
+    /// Parse a message from Kafka. Events written before envelopes were introduced are not
+    /// wrapped, they are given the headers that they would have had.
+    pub fn parse(payload: &str) -> Result<Self, Error> {
+        match from_str::<Envelope>(payload) {
+            Ok(envelope) => Ok(envelope),
+            Err(_) => {
+                let event: Event = from_str(payload).context(ErrorKind::ParseJsonFromKafka)?;
+                Ok(Envelope::new(event))
+            },
+        }
+    }
+
+    pub fn header(&self, name: &str) -> &str {
+        self.headers.get(name).map_or("", String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::to_string;
+
+    use super::Envelope;
+
+    const EVENT: &str = r#"{
+        "id": "0001",
+        "causation_id": "0000",
+        "consistency": { "key": "acc-1", "value": 1 },
+        "correlation_id": 18446744073709551615,
+        "data": {},
+        "event_type": "AccountCreated",
+        "metadata": { "tenant": "bank-1" },
+        "sender": "127.0.0.1:4000",
+        "session_id": 1,
+        "timestamp": "",
+        "timestamp_raw": 0
+    }"#;
+
+    #[test]
+    fn parse_envelope() {
+        let envelope = Envelope::parse(EVENT).unwrap();
+        let parsed = Envelope::parse(&to_string(&envelope).unwrap()).unwrap();
+
+        assert_eq!(parsed.header("correlation_id"), "18446744073709551615");
+        assert_eq!(parsed.header("causation_id"), "0000");
+        assert_eq!(parsed.header("event_id"), "0001");
+        assert_eq!(parsed.header("metadata.tenant"), "bank-1");
+        assert_eq!(parsed.event, envelope.event);
+    }
+
+    #[test]
+    fn parse_event_without_envelope() {
+        let envelope = Envelope::parse(EVENT).unwrap();
+
+        assert_eq!(envelope.event.id, "0001");
+        assert_eq!(envelope.header("correlation_id"), "18446744073709551615");
+    }
+}
@@ -1,22 +1,27 @@
+mod envelope;
 mod stream;
 
+use std::collections::{HashSet, VecDeque};
 use std::str::from_utf8;
 
 use actix::{Actor, Address, Context, ResponseType, StreamHandler};
-use common::schemas::Event;
+use common::LogContext;
+use common::schemas::EventId;
 use failure::{Error, ResultExt};
 use futures::stream::Stream;
 use rdkafka::Message;
-use rdkafka::config::ClientConfig;
 use rdkafka::consumer::Consumer as ConsumerTrait;
 use rdkafka::message::OwnedMessage;
-use serde_json::{from_str, to_string_pretty};
+use serde_json::to_string_pretty;
 
 use bus::Bus;
+use config::KafkaConfig;
 use consumer::stream::StreamConsumer;
 use error::ErrorKind;
 use signals;
 
+pub use self::envelope::Envelope;
+
 /// `KafkaMessage` is a wrapper type that allows us to implement `ResponseType` for Kafka's
 /// OwnedMessage. It is created by the `Codec` encoder/decoder.
 #[derive(Debug)]
@@ -30,25 +35,26 @@ impl ResponseType for KafkaMessage {
 /// The consumer actor handles incoming messages from Kafka and forwards them using the correct
 /// message on the Bus.
 pub struct Consumer {
-    bus: Address<Bus>
+    bus: Address<Bus>,
+    /// This field contains the ids of recently propagated events, `recent_event_order` contains
+    /// the same ids in the order they were seen so that the oldest can be forgotten.
+    recent_event_ids: HashSet<EventId>,
+    recent_event_order: VecDeque<EventId>,
+    /// This field contains how many recently propagated event ids are remembered.
+    recent_event_limit: usize,
 }
 
 impl Consumer {
-    /// Start the Kafka listener given the arguments for the `server` subcommand.
-    pub fn launch(brokers: &str, group: &str, topic: &str,
-                 bus: Address<Bus>) -> Result<(), Error> {
-        info!("starting kafka listener: brokers='{}' group='{}'", brokers, group);
-        let consumer = ClientConfig::new()
-            .set("bootstrap.servers", brokers)
-            .set("group.id", group)
-            .set("enable.partition.eof", "false")
-            .set("session.timeout.ms", "6000")
-            .set("enable.auto.commit", "false")
+    /// Start the Kafka listener given the Kafka configuration.
+    pub fn launch(kafka: &KafkaConfig, topic: &str, bus: Address<Bus>) -> Result<(), Error> {
+        info!("starting kafka listener: brokers='{}' group='{}'", kafka.brokers, kafka.group);
+        let consumer = kafka.consumer_config()
             .create::<StreamConsumer<_>>()
             .context(ErrorKind::KafkaConsumerCreation)?;
         info!("subscribing to topic on kafka listener: topic='{}'", topic);
         consumer.subscribe(&[topic]).context(ErrorKind::KafkaConsumerSubscription)?;
 
+        let recent_event_limit = kafka.recent_event_ids;
         let _: () = Self::create(move |ctx| {
             Self::add_stream(consumer.start()
                 .filter_map(|result| {
@@ -65,7 +71,12 @@ impl Consumer {
                     Error::from(ErrorKind::KafkaErrorReceived)
                 }), ctx);
 
-            Self { bus: bus }
+            Self {
+                bus: bus,
+                recent_event_ids: HashSet::new(),
+                recent_event_order: VecDeque::new(),
+                recent_event_limit: recent_event_limit,
+            }
         });
 
         Ok(())
@@ -75,21 +86,49 @@ impl Consumer {
         debug!("starting processing message from kafka");
         let contents = self.get_message_contents(message)?;
 
-        let parsed: Event = from_str(&contents).context(ErrorKind::ParseJsonFromKafka)?;
+        let envelope = Envelope::parse(&contents)?;
         debug!("parsed message from kafka");
-        info!("received message on kafka: message=\n{}",
-              to_string_pretty(&parsed).context(ErrorKind::SerializeJsonForSending)?);
-
-        let message = Event {
-            message_type: Some("event".to_owned()),
-            ..parsed.clone()
-        };
+        let _log = LogContext::new()
+            .field("event_id", envelope.header("event_id"))
+            .field("correlation_id", envelope.header("correlation_id"));
+        info!("received message on kafka: event_id='{}' correlation_id='{}' causation_id='{}' \
+              event_type='{}'", envelope.header("event_id"), envelope.header("correlation_id"),
+              envelope.header("causation_id"), envelope.event.event_type);
+        let parsed = envelope.event;
+        trace!("received message on kafka: message=\n{}",
+               to_string_pretty(&parsed).context(ErrorKind::SerializeJsonForSending)?);
+
+        if self.is_duplicate(&parsed.id) {
+            info!("ignoring redelivered message on kafka: event_id='{}'", parsed.id);
+            return Ok(());
+        }
 
-        self.bus.send(signals::PropagateEvent { event: message });
+        self.bus.send(signals::PropagateEvent { event: parsed });
         debug!("finished processing message from kafka");
         Ok(())
     }
 
+    /// Check whether an event with this id has been propagated recently, remembering the id if
+    /// not. Events persisted before ids were assigned are never considered duplicates.
+    fn is_duplicate(&mut self, event_id: &EventId) -> bool {
+        if event_id.is_empty() {
+            return false;
+        }
+
+        if !self.recent_event_ids.insert(event_id.clone()) {
+            return true;
+        }
+
+        self.recent_event_order.push_back(event_id.clone());
+        if self.recent_event_order.len() > self.recent_event_limit {
+            if let Some(oldest) = self.recent_event_order.pop_front() {
+                self.recent_event_ids.remove(&oldest);
+            }
+        }
+
+        false
+    }
+
     fn get_message_contents(&mut self, message: KafkaMessage) -> Result<String, Error> {
         let message = message.0;
 
@@ -25,28 +25,38 @@ pub enum ErrorKind {
     UnableToBindWebsocketServer,
     #[fail(display = "Invalid websocket connection accepted")]
     InvalidWebsocketConnection,
-
-    #[fail(display = "No bind argument was provided. This is a bug, there should be a default")]
-    MissingBindArgument,
-    #[fail(display = "No brokers argument was provided. This is a bug, there should be a default")]
-    MissingBrokersArgument,
-    #[fail(display = "No group argument was provided. This is a bug, there should be a default")]
-    MissingGroupArgument,
-    #[fail(display = "No topic argument was provided. This is a bug, there should be a default")]
-    MissingTopicArgument,
-    #[fail(display = "No couchbase_host argument was provided. This is a bug, there should be a default")]
-    MissingCouchbaseHostArgument,
+    #[fail(display = "Failure when binding http server to port")]
+    UnableToBindHttpServer,
+    #[fail(display = "The admin http server can't be started without an admin token")]
+    MissingAdminToken,
+
+    #[fail(display = "Invalid dedup window argument, it should be zero or more seconds")]
+    InvalidDedupWindowArgument,
+    #[fail(display = "Invalid priority argument, it should be <event_type>=<priority>")]
+    InvalidPriorityArgument,
+    #[fail(display = "Invalid prefetch argument, it should be a number of events above zero")]
+    InvalidPrefetchArgument,
+    #[fail(display = "Invalid rate limit argument, it should be <rate>[:<burst>] events per second")]
+    InvalidRateLimitArgument,
+    #[fail(display = "Invalid size limit argument, it should be a number")]
+    InvalidSizeLimitArgument,
+    #[fail(display = "Invalid shutdown timeout argument, it should be a number of seconds")]
+    InvalidShutdownTimeoutArgument,
+    #[fail(display = "Invalid configuration value, it is out of range")]
+    InvalidConfigValue,
 
     #[fail(display = "Failed to parse bytes as UTF8 string")]
     ParseBytesAsUtf8,
     #[fail(display = "Received invalid message type over websockets")]
     InvalidWebsocketMessageType,
-    #[fail(display = "Invalid JSON received on websockets")]
-    ParseJsonFromWebsockets,
+    #[fail(display = "Invalid message received on websockets")]
+    ParseMessageFromWebsockets,
     #[fail(display = "Invalid JSON received on Kafka")]
     ParseJsonFromKafka,
-    #[fail(display = "No message type in JSON from websockets")]
-    NoMessageTypeFromWebsockets,
+    #[fail(display = "Received a hello message that was not the first message")]
+    UnexpectedHello,
+    #[fail(display = "Received a message for a feature that was not negotiated")]
+    FeatureNotNegotiated,
 
     #[fail(display = "Failed to serialize value to json for sending")]
     SerializeJsonForSending,
@@ -57,11 +67,6 @@ pub enum ErrorKind {
     #[fail(display = "Message from Kafka with no payload")]
     KafkaMessageWithNoPayload,
 
-    #[fail(display = "Invalid JSON received in new event message")]
-    ParseNewEventMessage,
-    #[fail(display = "Invalid JSON received in acknowledgement message")]
-    ParseAcknowledgement,
-
     #[fail(display = "Invalid data received in query message")]
     ParseQueryMessage,
 
@@ -72,6 +77,8 @@ pub enum ErrorKind {
     CouchbaseFailedGetQueryResult,
     #[fail(display = "Failed to deserialize result of query")]
     CouchbaseDeserialize,
+    #[fail(display = "Failed to get event by id")]
+    CouchbaseFailedGetEvent,
     #[fail(display = "Failed to create GSI")]
     CouchbaseCreateGSIFailed,
     #[fail(display = "Got a row when we weren't expecting one")]
@@ -86,6 +93,28 @@ pub enum ErrorKind {
 
     #[fail(display = "Attempt to resend unacknowledged events with unregistered clients")]
     UnacknowledgedEventResendWithoutClientType,
+
+    // schema registry errors
+    #[fail(display = "Failed to read schema directory")]
+    ReadSchemaDirectory,
+    #[fail(display = "Schema file name is not a version number")]
+    InvalidSchemaFileName,
+    #[fail(display = "Failed to read schema file")]
+    ReadSchemaFile,
+    #[fail(display = "Failed to parse schema as JSON")]
+    ParseSchema,
+    #[fail(display = "Schema is not a valid JSON Schema document")]
+    InvalidSchema,
+
+    // upcaster errors
+    #[fail(display = "Failed to read upcaster directory")]
+    ReadUpcasterDirectory,
+    #[fail(display = "Upcaster file name is not a version number")]
+    InvalidUpcasterFileName,
+    #[fail(display = "Failed to read upcaster file")]
+    ReadUpcasterFile,
+    #[fail(display = "Failed to parse upcaster as a JSON patch")]
+    ParseUpcaster,
 }
 
 impl Error {
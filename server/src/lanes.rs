@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+
+use common::schemas::{ConsistencyKey, Event, EventId};
+use failure::Error;
+
+use error::ErrorKind;
+
+/// The priority of event types that have not been assigned one.
+pub const DEFAULT_PRIORITY: u32 = 0;
+
+/// The in-flight budget of each lane if it is not configured.
+pub const DEFAULT_PREFETCH: usize = 100;
+
+/// LaneSettings contains the priority of each event type and how many events of each priority
+/// can be sent to a client before it has acknowledged them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaneSettings {
+    /// This field contains the in-flight budget of each lane that doesn't have its own.
+    pub prefetch: usize,
+    /// This field contains the in-flight budgets of lanes that differ from `prefetch`.
+    pub lane_prefetch: Vec<LanePrefetch>,
+    pub priorities: BTreeMap<String, u32>,
+}
+
+/// LanePrefetch is the in-flight budget of the lane for a single priority.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanePrefetch {
+    pub priority: u32,
+    pub prefetch: usize,
+}
+
+impl Default for LaneSettings {
+    fn default() -> Self {
+        Self {
+            prefetch: DEFAULT_PREFETCH,
+            lane_prefetch: Vec::new(),
+            priorities: BTreeMap::new(),
+        }
+    }
+}
+
+impl LaneSettings {
+    /// Parse a priority in the form `<event_type>=<priority>`.
+    pub fn add_priority(&mut self, argument: &str) -> Result<(), Error> {
+        let mut parts = argument.splitn(2, '=');
+        let event_type = parts.next().ok_or(ErrorKind::InvalidPriorityArgument)?;
+        let priority = parts.next()
+            .and_then(|priority| priority.parse::<u32>().ok())
+            .ok_or(ErrorKind::InvalidPriorityArgument)?;
+
+        info!("assigned priority: event_type='{}' priority='{}'", event_type, priority);
+        self.priorities.insert(event_type.to_owned(), priority);
+        Ok(())
+    }
+
+    /// Parse the in-flight budget of a lane in the form `<priority>=<prefetch>`.
+    pub fn add_lane_prefetch(&mut self, argument: &str) -> Result<(), Error> {
+        let mut parts = argument.splitn(2, '=');
+        let priority = parts.next()
+            .and_then(|priority| priority.parse::<u32>().ok())
+            .ok_or(ErrorKind::InvalidPrefetchArgument)?;
+        let prefetch = parts.next()
+            .and_then(|prefetch| prefetch.parse::<usize>().ok())
+            .and_then(|prefetch| if prefetch > 0 { Some(prefetch) } else { None })
+            .ok_or(ErrorKind::InvalidPrefetchArgument)?;
+
+        info!("assigned lane prefetch: priority='{}' prefetch='{}'", priority, prefetch);
+        self.lane_prefetch.retain(|lane| lane.priority != priority);
+        self.lane_prefetch.push(LanePrefetch { priority, prefetch });
+        Ok(())
+    }
+
+    pub fn priority(&self, event_type: &str) -> u32 {
+        self.priorities.get(event_type).cloned().unwrap_or(DEFAULT_PRIORITY)
+    }
+
+    /// Find the in-flight budget of the lane for a priority.
+    pub fn prefetch(&self, priority: u32) -> usize {
+        self.lane_prefetch.iter()
+            .find(|lane| lane.priority == priority)
+            .map_or(self.prefetch, |lane| lane.prefetch)
+    }
+}
+
+/// Lane contains the events of one priority that can be sent to a client, in the order that they
+/// were queued, and how many have been sent but not acknowledged.
+#[derive(Clone, Debug, Default)]
+struct Lane {
+    /// Only the earliest queued event of each consistency key is ready, events are keyed by the
+    /// order that they were queued in across all lanes.
+    ready: BTreeMap<u64, Event>,
+    in_flight: usize,
+}
+
+/// Lanes queues the events for a single client by priority. Events from higher priority lanes
+/// are sent first, but never ahead of an earlier event with the same consistency key.
+#[derive(Clone, Debug, Default)]
+pub struct Lanes {
+    lanes: BTreeMap<u32, Lane>,
+    /// This field contains an entry for each consistency key with a ready event, with the events
+    /// for that key that are queued behind it along with their order and priority.
+    blocked: HashMap<ConsistencyKey, VecDeque<(u64, u32, Event)>>,
+    /// This field contains the lane of each event that has been sent but not acknowledged.
+    sent: HashMap<EventId, u32>,
+    queued: usize,
+    next_sequence: u64,
+}
+
+impl Lanes {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Queue an event to be sent in the lane for its priority.
+    pub fn push(&mut self, priority: u32, event: Event) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.queued += 1;
+
+        match self.blocked.entry(event.consistency.key.clone()) {
+            Entry::Occupied(mut entry) => entry.get_mut().push_back((sequence, priority, event)),
+            Entry::Vacant(entry) => {
+                entry.insert(VecDeque::new());
+                self.lanes.entry(priority)
+                    .or_insert_with(Lane::default)
+                    .ready.insert(sequence, event);
+            },
+        }
+    }
+
+    /// Count the events that are waiting to be sent across all lanes.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// Check whether any event with a consistency key is waiting to be sent.
+    pub fn has_queued(&self, key: &ConsistencyKey) -> bool {
+        self.blocked.contains_key(key)
+    }
+
+    /// Free the budget used by an event once it has been acknowledged or rejected.
+    pub fn complete(&mut self, event_id: &EventId) {
+        if let Some(priority) = self.sent.remove(event_id) {
+            if let Some(lane) = self.lanes.get_mut(&priority) {
+                lane.in_flight = lane.in_flight.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Take every event that can be sent now, highest priority first.
+    pub fn drain_ready(&mut self, settings: &LaneSettings) -> Vec<Event> {
+        let mut ready = Vec::new();
+
+        // Sending an event from a lower lane can unblock an event with the same consistency key
+        // in a higher lane, so keep going until nothing else can be sent.
+        loop {
+            let mut progress = false;
+            let priorities: Vec<u32> = self.lanes.keys().rev().cloned().collect();
+            for priority in priorities {
+                let prefetch = settings.prefetch(priority);
+                while let Some(event) = self.pop_ready(priority, prefetch) {
+                    ready.push(event);
+                    progress = true;
+                }
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        ready
+    }
+
+    /// Take the earliest ready event from a lane if the lane has budget left, and make the next
+    /// event with the same consistency key ready.
+    fn pop_ready(&mut self, priority: u32, prefetch: usize) -> Option<Event> {
+        let event = {
+            let lane = self.lanes.get_mut(&priority)?;
+            if lane.in_flight >= prefetch {
+                return None;
+            }
+
+            let sequence = lane.ready.keys().next().cloned()?;
+            let event = lane.ready.remove(&sequence)?;
+            lane.in_flight += 1;
+            event
+        };
+        self.queued -= 1;
+        self.sent.insert(event.id.clone(), priority);
+
+        let next = match self.blocked.entry(event.consistency.key.clone()) {
+            Entry::Occupied(mut entry) => {
+                let next = entry.get_mut().pop_front();
+                if next.is_none() {
+                    entry.remove();
+                }
+                next
+            },
+            Entry::Vacant(_) => None,
+        };
+        if let Some((sequence, next_priority, next_event)) = next {
+            self.lanes.entry(next_priority)
+                .or_insert_with(Lane::default)
+                .ready.insert(sequence, next_event);
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::schemas::Event;
+    use serde_json::from_value;
+
+    use super::{LanePrefetch, LaneSettings, Lanes};
+
+    fn event(id: &str, key: &str) -> Event {
+        from_value(json!({
+            "id": id,
+            "consistency": { "key": key, "value": 1 },
+            "correlation_id": 0,
+            "data": {},
+            "event_type": "test",
+            "sender": "test",
+            "session_id": null,
+            "timestamp": "",
+            "timestamp_raw": null,
+        })).unwrap()
+    }
+
+    fn ids(events: Vec<Event>) -> Vec<String> {
+        events.into_iter().map(|event| event.id).collect()
+    }
+
+    #[test]
+    fn default_prefetch_limits_each_lane() {
+        let settings = LaneSettings::default();
+        let mut lanes = Lanes::new();
+        for i in 0..settings.prefetch + 1 {
+            lanes.push(0, event(&i.to_string(), &i.to_string()));
+        }
+
+        assert_eq!(lanes.drain_ready(&settings).len(), settings.prefetch);
+        assert_eq!(lanes.queued(), 1);
+    }
+
+    #[test]
+    fn higher_priorities_are_sent_first() {
+        let settings = LaneSettings::default();
+        let mut lanes = Lanes::new();
+        lanes.push(0, event("low", "a"));
+        lanes.push(5, event("high", "b"));
+        lanes.push(1, event("middle", "c"));
+
+        assert_eq!(ids(lanes.drain_ready(&settings)), vec!["high", "middle", "low"]);
+    }
+
+    #[test]
+    fn events_are_not_sent_ahead_of_earlier_events_with_the_same_key() {
+        let mut settings = LaneSettings::default();
+        settings.lane_prefetch.push(LanePrefetch { priority: 0, prefetch: 1 });
+        let mut lanes = Lanes::new();
+        lanes.push(0, event("first", "a"));
+        lanes.push(0, event("second", "b"));
+        lanes.push(5, event("third", "b"));
+        lanes.push(5, event("fourth", "c"));
+
+        // The high priority event for key `b` waits for the low priority event before it, but
+        // doesn't hold back the event for key `c` behind it.
+        assert_eq!(ids(lanes.drain_ready(&settings)), vec!["fourth", "first"]);
+        assert_eq!(lanes.queued(), 2);
+
+        lanes.complete(&String::from("first"));
+        assert!(lanes.has_queued(&String::from("b")));
+        assert_eq!(ids(lanes.drain_ready(&settings)), vec!["second", "third"]);
+        assert_eq!(lanes.queued(), 0);
+        assert!(!lanes.has_queued(&String::from("b")));
+    }
+
+    #[test]
+    fn prefetch_is_per_lane() {
+        let mut settings = LaneSettings::default();
+        settings.prefetch = 1;
+        settings.lane_prefetch.push(LanePrefetch { priority: 5, prefetch: 2 });
+        let mut lanes = Lanes::new();
+        for i in 0..3 {
+            lanes.push(0, event(&format!("low-{}", i), &format!("low-{}", i)));
+            lanes.push(5, event(&format!("high-{}", i), &format!("high-{}", i)));
+        }
+
+        assert_eq!(ids(lanes.drain_ready(&settings)), vec!["high-0", "high-1", "low-0"]);
+        assert!(lanes.drain_ready(&settings).is_empty());
+
+        lanes.complete(&String::from("low-0"));
+        assert_eq!(ids(lanes.drain_ready(&settings)), vec!["low-1"]);
+    }
+
+    #[test]
+    fn parse_lane_prefetch() {
+        let mut settings = LaneSettings::default();
+        settings.add_lane_prefetch("5=10").unwrap();
+        settings.add_lane_prefetch("5=20").unwrap();
+
+        assert_eq!(settings.prefetch(5), 20);
+        assert_eq!(settings.prefetch(0), settings.prefetch);
+        assert!(settings.add_lane_prefetch("5=0").is_err());
+        assert!(settings.add_lane_prefetch("high=1").is_err());
+    }
+}
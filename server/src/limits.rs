@@ -0,0 +1,22 @@
+/// SizeLimits contains the limits on the size of incoming messages and events. There is no limit
+/// for any that are `None`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SizeLimits {
+    /// This field contains the largest websocket frame that a client can send, in bytes.
+    pub max_frame_bytes: Option<usize>,
+    /// This field contains the most events that can be sent in a single `new` message.
+    pub max_batch_events: Option<usize>,
+    /// This field contains the largest serialized `data` of an event, in bytes.
+    pub max_data_bytes: Option<usize>,
+    /// If this is set, events with data larger than `max_data_bytes` are accepted and their data
+    /// is stored in a separate document instead of with the event.
+    pub offload_payloads: bool,
+}
+
+impl SizeLimits {
+    /// Check whether data of the given size is over the limit.
+    pub fn is_data_too_large(&self, size: usize) -> bool {
+        self.max_data_bytes.map_or(false, |limit| size > limit)
+    }
+}
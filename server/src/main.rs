@@ -1,38 +1,63 @@
 #![deny(warnings)]
 
 extern crate actix;
+extern crate actix_web;
+extern crate bytes;
 extern crate chrono;
 #[macro_use] extern crate clap;
 extern crate common;
 extern crate couchbase;
 #[macro_use] extern crate failure;
 extern crate futures;
+extern crate json_patch;
 #[macro_use] extern crate log;
 extern crate rand;
 extern crate rdkafka;
 extern crate serde;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate serde_derive;
+extern crate tokio_io;
+extern crate url;
+extern crate valico;
 extern crate websocket;
 
 mod bus;
+mod codec;
+mod config;
 mod consumer;
 mod error;
+mod lanes;
+mod limits;
+mod metrics;
 mod persistence;
+mod rate_limit;
+mod registry;
+mod schedule;
 mod server;
 mod session;
+mod shutdown;
 mod signals;
+mod upcast;
+mod versioned;
+mod web;
 
-use actix::{Address, System};
-use clap::{Arg, ArgMatches, App, AppSettings, SubCommand};
-use common::configure_logging;
+use std::path::Path;
+use std::process::exit;
+
+use actix::{Address, SyncAddress, System};
+use clap::{Arg, App, AppSettings, SubCommand};
+use common::{LogFormat, LoggingConfig, configure_logging};
+use common::config::render_config;
 use failure::Error;
-use log::LogLevelFilter;
 
 use bus::Bus;
+use config::Config;
 use consumer::Consumer;
 use error::ErrorKind;
+use registry::SchemaRegistry;
 use server::Server;
+use upcast::Upcasters;
+use web::{start_admin_server, start_webserver};
 
 fn main() {
     let matches = App::new(crate_name!())
@@ -43,10 +68,25 @@ fn main() {
         .arg(Arg::with_name("log-level")
              .short("l")
              .long("log-level")
-             .help("Log level")
-             .default_value("info")
+             .help("Log level, overrides the configuration file")
              .possible_values(&["off", "trace", "debug", "info", "warn", "error"])
              .takes_value(true))
+        .arg(Arg::with_name("log-format")
+             .long("log-format")
+             .help("Log format, overrides the configuration file")
+             .possible_values(&["text", "json"])
+             .takes_value(true))
+        .arg(Arg::with_name("config")
+             .short("c")
+             .long("config")
+             .help("TOML configuration file, settings in it can be overridden by environment \
+                   variables such as BUSD_KAFKA__BROKERS and by flags")
+             .takes_value(true))
+        .subcommand(SubCommand::with_name("config")
+                    .about("Inspect the configuration of the event bus daemon")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("check")
+                                .about("Validate and print the effective configuration")))
         .subcommand(SubCommand::with_name("server")
                     .about("Start the event bus daemon")
                     .version(crate_version!())
@@ -55,64 +95,193 @@ fn main() {
                          .short("t")
                          .long("topic")
                          .help("Topic to send and receive messages on")
-                         .default_value("sed-instance-1")
                          .takes_value(true))
                     .arg(Arg::with_name("bind")
                          .short("b")
                          .long("bind")
                          .help("Host and port to bind websocket server to")
-                         .default_value("localhost:8081")
+                         .takes_value(true))
+                    .arg(Arg::with_name("metrics_bind")
+                         .long("metrics-bind")
+                         .help("Host and port to bind the metrics http server to, it is not \
+                               started if not provided")
+                         .takes_value(true))
+                    .arg(Arg::with_name("admin_bind")
+                         .long("admin-bind")
+                         .help("Host and port to bind the admin http server to, it is not \
+                               started if not provided")
                          .takes_value(true))
                     .arg(Arg::with_name("brokers")
                          .long("broker")
                          .help("Broker list in Kafka format")
-                         .default_value("localhost:9092")
                          .takes_value(true))
                     .arg(Arg::with_name("group")
                          .short("g")
                          .long("group")
                          .help("Consumer group name")
-                         .default_value(crate_name!())
                          .takes_value(true))
                     .arg(Arg::with_name("couchbase_host")
                         .long("couchbase-host")
                         .help("The hostname for the couchbase DB.")
-                        .default_value("couchbase.db")
                         .takes_value(true))
+                    .arg(Arg::with_name("dedup_window")
+                         .long("dedup-window")
+                         .help("Seconds that idempotency keys are remembered for")
+                         .takes_value(true))
+                    .arg(Arg::with_name("schema_dir")
+                         .long("schema-dir")
+                         .help("Directory of JSON schemas to validate events against")
+                         .takes_value(true))
+                    .arg(Arg::with_name("upcaster_dir")
+                         .long("upcaster-dir")
+                         .help("Directory of JSON patches to upgrade old events with")
+                         .takes_value(true))
+                    .arg(Arg::with_name("priority")
+                         .long("priority")
+                         .help("Priority of an event type as <event_type>=<priority>, higher \
+                               priorities are sent to clients first")
+                         .multiple(true)
+                         .number_of_values(1)
+                         .takes_value(true))
+                    .arg(Arg::with_name("prefetch")
+                         .long("prefetch")
+                         .help("Unacknowledged events of each priority that a client can have, \
+                               100 if not provided")
+                         .takes_value(true))
+                    .arg(Arg::with_name("lane_prefetch")
+                         .long("lane-prefetch")
+                         .help("Unacknowledged events of one priority that a client can have as \
+                               <priority>=<prefetch>, overriding --prefetch")
+                         .multiple(true)
+                         .number_of_values(1)
+                         .takes_value(true))
+                    .arg(Arg::with_name("session_rate_limit")
+                         .long("session-rate-limit")
+                         .help("Events per second that each session can publish as \
+                               <rate>[:<burst>], unlimited if not provided")
+                         .takes_value(true))
+                    .arg(Arg::with_name("client_type_rate_limit")
+                         .long("client-type-rate-limit")
+                         .help("Events per second that all sessions of a client type can publish \
+                               as <client_type>=<rate>[:<burst>]")
+                         .multiple(true)
+                         .number_of_values(1)
+                         .takes_value(true))
+                    .arg(Arg::with_name("max_frame_bytes")
+                         .long("max-frame-bytes")
+                         .help("Largest websocket frame that clients can send, in bytes")
+                         .takes_value(true))
+                    .arg(Arg::with_name("max_batch_events")
+                         .long("max-batch-events")
+                         .help("Most events that clients can send in a single message")
+                         .takes_value(true))
+                    .arg(Arg::with_name("max_data_bytes")
+                         .long("max-data-bytes")
+                         .help("Largest data of an event, in bytes")
+                         .takes_value(true))
+                    .arg(Arg::with_name("offload_payloads")
+                         .long("offload-payloads")
+                         .help("Store data larger than --max-data-bytes separately from the \
+                               event instead of rejecting it"))
+                    .arg(Arg::with_name("shutdown_timeout")
+                         .long("shutdown-timeout")
+                         .help("Seconds that the daemon has to shut down in once it is \
+                               signalled to stop")
+                         .takes_value(true))
         ).get_matches();
 
-    let level = value_t!(matches, "log-level", LogLevelFilter).unwrap_or(LogLevelFilter::Trace);
-    configure_logging(level);
+    let config = Config::load(&matches);
+
+    // Flags override the logging configuration, which is used even if the rest of the
+    // configuration didn't load so that the error can be logged.
+    let mut logging = config.as_ref().map(|config| config.logging.clone()).unwrap_or_default();
+    if let Some(level) = matches.value_of("log-level") {
+        logging.level = level.to_owned();
+    }
+    if let Some(format) = matches.value_of("log-format").and_then(LogFormat::from_name) {
+        logging.format = format;
+    }
+    if let Err(e) = configure_logging(&logging) {
+        // Log the error with the default configuration, which is always valid.
+        if configure_logging(&LoggingConfig::default()).is_ok() {
+            error!("failed to configure logging: error='{}'", e);
+        }
+        exit(1);
+    }
+
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("failed to load configuration: error='{}'", e);
+            exit(1);
+        },
+    };
 
     match matches.subcommand() {
         ("server", Some(arguments)) => {
-            if let Err(e) = start_server(&arguments) {
+            if let Err(e) = config.apply_arguments(&arguments)
+                    .and_then(|_| start_server(config)) {
                 error!("failed to start server: error='{}'", e);
+                exit(1);
+            }
+        },
+        ("config", Some(arguments)) => {
+            if let ("check", Some(_)) = arguments.subcommand() {
+                if let Err(e) = check_config(&config) {
+                    error!("configuration is invalid: error='{}'", e);
+                    exit(1);
+                }
             }
         },
         _ => { }
     };
 }
 
-fn start_server(arguments: &ArgMatches) -> Result<(), Error> {
+/// Validate the configuration and print the effective configuration.
+fn check_config(config: &Config) -> Result<(), Error> {
+    config.validate()?;
+    print!("{}", render_config(&config.redacted())?);
+    Ok(())
+}
+
+fn start_server(config: Config) -> Result<(), Error> {
+    config.validate()?;
     let system = System::new(crate_name!());
 
+    let mut schemas = SchemaRegistry::new();
+    if let Some(ref schema_dir) = config.server.schema_dir {
+        info!("loading schemas: directory='{}'", schema_dir);
+        schemas.load_directory(Path::new(schema_dir))?;
+    }
+
+    let mut upcasters = Upcasters::new();
+    if let Some(ref upcaster_dir) = config.server.upcaster_dir {
+        info!("loading upcasters: directory='{}'", upcaster_dir);
+        upcasters.load_directory(Path::new(upcaster_dir))?;
+    }
+
     // Create the event bus actor, we'll pass this to the websocket server actor
     // and the consumer actor so that they can send it things.
-    let brokers = arguments.value_of("brokers").ok_or(ErrorKind::MissingBrokersArgument)?;
-    let topic = arguments.value_of("topic").ok_or(ErrorKind::MissingTopicArgument)?;
-    let couchbase_host = arguments.value_of("couchbase_host").ok_or(ErrorKind::MissingCouchbaseHostArgument)?;
-
-    let bus: Address<_> = Bus::launch(brokers, topic, couchbase_host)?;
+    let (bus, sync_bus): (Address<_>, SyncAddress<_>) = Bus::launch(
+        &config.kafka, &config.server.topic, &config.couchbase, config.bus_settings(), schemas,
+        upcasters)?;
 
     // Start WebSocket server.
-    let addr = arguments.value_of("bind").ok_or(ErrorKind::MissingBindArgument)?;
-    Server::launch(addr, bus.clone())?;
+    Server::launch(&config.server.bind, bus.clone(), config.limits.max_frame_bytes)?;
+
+    Consumer::launch(&config.kafka, &config.server.topic, bus.clone())?;
 
-    let group = arguments.value_of("group").ok_or(ErrorKind::MissingGroupArgument)?;
-    Consumer::launch(brokers, group, topic, bus.clone())?;
+    // Start the metrics and admin servers, they need an address of the bus that can be sent to
+    // from the threads of their workers.
+    if let Some(ref metrics_bind) = config.server.metrics_bind {
+        start_webserver(metrics_bind.clone(), sync_bus.clone())?;
+    }
+
+    if let Some(ref admin_bind) = config.server.admin_bind {
+        let admin_token = config.server.admin_token().ok_or(ErrorKind::MissingAdminToken)?;
+        start_admin_server(admin_bind.clone(), admin_token.clone(), sync_bus)?;
+    }
 
     system.run();
     Ok(())
 }
-
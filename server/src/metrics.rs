@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The prefix of every metric when it is exported.
+const PREFIX: &str = "busd_";
+
+/// The upper bounds of the histogram buckets, in seconds.
+const BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The most values that a label chosen by clients, such as the event type, can take. Further
+/// values are counted as `other` so that clients can't create an unbounded number of series.
+const MAX_LABEL_VALUES: usize = 200;
+
+/// The value that a bounded label takes once it has reached its limit.
+const OTHER_LABEL_VALUE: &str = "other";
+
+/// Labels distinguish the counters that share a name, such as by client type.
+pub type Labels = Vec<(&'static str, String)>;
+
+/// Histogram counts how many observations fell into each bucket, along with their sum.
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; BUCKETS.len()];
+        }
+
+        // Buckets are cumulative when rendered, so each observation is only counted once here.
+        if let Some(index) = BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Metrics contains counters of what the bus has done since it started, gauges of its current
+/// state and histograms of how long things take. They are rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: BTreeMap<(&'static str, Labels), u64>,
+    gauges: BTreeMap<(&'static str, Labels), f64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+    /// This field contains the values that each bounded label has taken so far.
+    label_values: BTreeMap<&'static str, BTreeSet<String>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add to the counter with the given name and labels.
+    pub fn increment(&mut self, name: &'static str, labels: Labels, amount: u64) {
+        *self.counters.entry((name, labels)).or_insert(0) += amount;
+    }
+
+    /// Set the gauge with the given name and labels.
+    pub fn set_gauge(&mut self, name: &'static str, labels: Labels, value: f64) {
+        self.gauges.insert((name, labels), value);
+    }
+
+    /// Add to the gauge with the given name and labels.
+    pub fn add_to_gauge(&mut self, name: &'static str, labels: Labels, amount: f64) {
+        *self.gauges.entry((name, labels)).or_insert(0.0) += amount;
+    }
+
+    /// Forget every gauge, so that gauges for things that no longer exist, such as client types
+    /// with no sessions, are not rendered once the gauges are set again.
+    pub fn reset_gauges(&mut self) {
+        self.gauges.clear();
+    }
+
+    /// Record how long something took in the histogram with the given name and labels.
+    pub fn observe(&mut self, name: &'static str, labels: Labels, duration: Duration) {
+        let seconds = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+        self.histograms.entry((name, labels)).or_insert_with(Histogram::default).observe(seconds);
+    }
+
+    /// Find the value to use for a label that is chosen by clients. The value is used as it is
+    /// until the label has taken too many values, after which new values are counted as `other`.
+    pub fn bounded_label(&mut self, label: &'static str, value: &str) -> String {
+        let values = self.label_values.entry(label).or_insert_with(BTreeSet::new);
+        if values.contains(value) || values.len() < MAX_LABEL_VALUES {
+            values.insert(value.to_owned());
+            value.to_owned()
+        } else {
+            String::from(OTHER_LABEL_VALUE)
+        }
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        let mut previous = None;
+        for (&(name, ref labels), value) in &self.counters {
+            write_type(&mut output, &mut previous, name, "counter");
+            write_sample(&mut output, name, "", labels, None, &value.to_string());
+        }
+
+        previous = None;
+        for (&(name, ref labels), value) in &self.gauges {
+            write_type(&mut output, &mut previous, name, "gauge");
+            write_sample(&mut output, name, "", labels, None, &value.to_string());
+        }
+
+        previous = None;
+        for (&(name, ref labels), histogram) in &self.histograms {
+            write_type(&mut output, &mut previous, name, "histogram");
+
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                write_sample(&mut output, name, "_bucket", labels, Some(&bound.to_string()),
+                             &cumulative.to_string());
+            }
+            write_sample(&mut output, name, "_bucket", labels, Some("+Inf"),
+                         &histogram.count.to_string());
+            write_sample(&mut output, name, "_sum", labels, None, &histogram.sum.to_string());
+            write_sample(&mut output, name, "_count", labels, None, &histogram.count.to_string());
+        }
+
+        output
+    }
+}
+
+/// Write the type of a metric before its first sample. Metrics are sorted by name, so all of the
+/// samples of a metric follow each other.
+fn write_type(output: &mut String, previous: &mut Option<&'static str>, name: &'static str,
+              kind: &str) {
+    if *previous != Some(name) {
+        let _ = writeln!(output, "# TYPE {}{} {}", PREFIX, name, kind);
+        *previous = Some(name);
+    }
+}
+
+fn write_sample(output: &mut String, name: &str, suffix: &str, labels: &Labels,
+                le: Option<&str>, value: &str) {
+    let mut rendered: Vec<String> = labels.iter()
+        .map(|&(key, ref value)| format!("{}=\"{}\"", key, escape(value)))
+        .collect();
+    if let Some(le) = le {
+        rendered.push(format!("le=\"{}\"", le));
+    }
+
+    if rendered.is_empty() {
+        let _ = writeln!(output, "{}{}{} {}", PREFIX, name, suffix, value);
+    } else {
+        let _ = writeln!(output, "{}{}{}{{{}}} {}", PREFIX, name, suffix, rendered.join(","),
+                         value);
+    }
+}
+
+/// Escape a label value as the text format requires.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{MAX_LABEL_VALUES, Metrics};
+
+    #[test]
+    fn renders_counters_and_gauges() {
+        let mut metrics = Metrics::new();
+        metrics.increment("events_total", vec![("status", String::from("success"))], 2);
+        metrics.increment("events_total", vec![("status", String::from("invalid"))], 1);
+        metrics.increment("events_total", vec![("status", String::from("success"))], 1);
+        metrics.set_gauge("sessions", vec![("client_type", String::from("a \"b\""))], 3.0);
+        metrics.set_gauge("uptime", Vec::new(), 1.5);
+
+        assert_eq!(metrics.render(), "\
+# TYPE busd_events_total counter
+busd_events_total{status=\"invalid\"} 1
+busd_events_total{status=\"success\"} 3
+# TYPE busd_sessions gauge
+busd_sessions{client_type=\"a \\\"b\\\"\"} 3
+# TYPE busd_uptime gauge
+busd_uptime 1.5
+");
+    }
+
+    #[test]
+    fn renders_cumulative_histogram_buckets() {
+        let mut metrics = Metrics::new();
+        let labels = vec![("status", String::from("success"))];
+        metrics.observe("duration_seconds", labels.clone(), Duration::from_millis(3));
+        metrics.observe("duration_seconds", labels.clone(), Duration::from_millis(40));
+        metrics.observe("duration_seconds", labels.clone(), Duration::from_millis(50));
+        metrics.observe("duration_seconds", labels, Duration::from_secs(20));
+
+        let rendered = metrics.render();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "# TYPE busd_duration_seconds histogram");
+        assert_eq!(lines[1], "busd_duration_seconds_bucket{status=\"success\",le=\"0.001\"} 0");
+        assert_eq!(lines[2], "busd_duration_seconds_bucket{status=\"success\",le=\"0.005\"} 1");
+        assert_eq!(lines[5], "busd_duration_seconds_bucket{status=\"success\",le=\"0.05\"} 3");
+        assert_eq!(lines[12], "busd_duration_seconds_bucket{status=\"success\",le=\"10\"} 3");
+        assert_eq!(lines[13], "busd_duration_seconds_bucket{status=\"success\",le=\"+Inf\"} 4");
+        assert_eq!(lines[14], "busd_duration_seconds_sum{status=\"success\"} 20.093");
+        assert_eq!(lines[15], "busd_duration_seconds_count{status=\"success\"} 4");
+        assert_eq!(lines.len(), 16);
+    }
+
+    #[test]
+    fn bounds_label_values() {
+        let mut metrics = Metrics::new();
+        for i in 0..MAX_LABEL_VALUES {
+            assert_eq!(metrics.bounded_label("event_type", &i.to_string()), i.to_string());
+        }
+
+        assert_eq!(metrics.bounded_label("event_type", "new"), "other");
+        assert_eq!(metrics.bounded_label("event_type", "0"), "0");
+        assert_eq!(metrics.bounded_label("client_type", "new"), "new");
+    }
+}
@@ -4,11 +4,10 @@ use failure::{Error, Fail};
 use futures::{Stream};
 use couchbase::{Bucket, Cluster, CouchbaseError, N1qlResult};
 
+use config::CouchbaseConfig;
 use error::ErrorKind;
 
 const BUCKET_NAME: &str = "events";
-const MAX_RETRIES: u8 = 60;
-const RETRY_INTERVAL_MILLIS: u64 = 1000;
 
 fn create_gsi(bucket: &Bucket, name: &str) -> Result<(), Error> {
     let query = format!("CREATE INDEX {0} ON {1} ({0}) USING GSI", name, BUCKET_NAME);
@@ -62,14 +61,14 @@ fn create_primary_index(bucket: &Bucket) -> Result<(), Error> {
     Ok(())
 }
 
-pub fn connect_to_bucket(couchbase_host: &str, bucket_name: &str) -> Result<Bucket, Error> {
+pub fn connect_to_bucket(config: &CouchbaseConfig, bucket_name: &str) -> Result<Bucket, Error> {
     // This is simply a state object, it doesn't actually initiate connections.
-    let mut cluster = Cluster::new(couchbase_host)?;
-    cluster.authenticate("connect", "connect");
+    let mut cluster = Cluster::new(config.host.as_str())?;
+    cluster.authenticate(config.username.as_str(), config.password.as_str());
     let cluster = cluster;
 
     // Retry logic on opening bucket.
-    let mut retries = MAX_RETRIES;
+    let mut retries = config.max_retries;
     let mut bucket = cluster.open_bucket(bucket_name, None);
 
     while bucket.is_err() && retries > 0 {
@@ -85,13 +84,13 @@ pub fn connect_to_bucket(couchbase_host: &str, bucket_name: &str) -> Result<Buck
             Err(err) => {
                 error!("failed to connect to couchbase: bucket='{}' host='{}' \
                        error='{}' retries_remaining='{}'",
-                       bucket_name, couchbase_host, err, retries);
+                       bucket_name, config.host, err, retries);
             },
         }
 
         retries -= 1;
         bucket = cluster.open_bucket(bucket_name, None);
-        thread::sleep(time::Duration::from_millis(RETRY_INTERVAL_MILLIS));
+        thread::sleep(time::Duration::from_millis(config.retry_interval_ms));
     }
 
     let bucket = match bucket {
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Instant;
+
+use failure::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, Visitor};
+
+use error::ErrorKind;
+
+/// RateLimit is the number of events per second that can be published, and how many can be
+/// published at once after a quiet period.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// Parse a rate limit in the form `<rate>` or `<rate>:<burst>`. The burst is the same as the
+    /// rate if it is not provided.
+    pub fn parse(argument: &str) -> Result<Self, Error> {
+        let mut parts = argument.splitn(2, ':');
+        let rate = parts.next()
+            .and_then(|rate| rate.parse::<f64>().ok())
+            .ok_or(ErrorKind::InvalidRateLimitArgument)?;
+        let burst = match parts.next() {
+            Some(burst) => burst.parse::<f64>().map_err(|_| ErrorKind::InvalidRateLimitArgument)?,
+            None => rate,
+        };
+
+        let limit = Self { rate: rate, burst: burst };
+        if !limit.is_valid() {
+            return Err(Error::from(ErrorKind::InvalidRateLimitArgument));
+        }
+
+        Ok(limit)
+    }
+
+    /// Check that the rate is positive and the burst is at least one event. `NaN` and infinite
+    /// values parse as numbers but are refused, they would turn the limit off.
+    pub fn is_valid(&self) -> bool {
+        self.rate.is_finite() && self.burst.is_finite() && self.rate > 0.0 && self.burst >= 1.0
+    }
+}
+
+impl Serialize for RateLimit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{}:{}", self.rate, self.burst))
+    }
+}
+
+/// RateLimitVisitor parses rate limits in configuration files as they are written on the command
+/// line, a rate on its own can also be written as a number.
+struct RateLimitVisitor;
+
+impl<'de> Visitor<'de> for RateLimitVisitor {
+    type Value = RateLimit;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a rate limit in the form <rate>[:<burst>]")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<RateLimit, E> {
+        RateLimit::parse(value).map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<RateLimit, E> {
+        self.visit_str(&value.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<RateLimit, E> {
+        self.visit_str(&value.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<RateLimit, E> {
+        self.visit_str(&value.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for RateLimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(RateLimitVisitor)
+    }
+}
+
+/// RateLimits contains the limits on publishing for each session and for each client type. There
+/// are no limits for sessions or client types without one.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub session: Option<RateLimit>,
+    pub client_types: BTreeMap<String, RateLimit>,
+}
+
+impl RateLimits {
+    /// Parse a rate limit for a client type in the form `<client_type>=<rate>[:<burst>]`.
+    pub fn add_client_type(&mut self, argument: &str) -> Result<(), Error> {
+        let mut parts = argument.splitn(2, '=');
+        let client_type = parts.next().ok_or(ErrorKind::InvalidRateLimitArgument)?;
+        let limit = RateLimit::parse(parts.next().ok_or(ErrorKind::InvalidRateLimitArgument)?)?;
+
+        info!("assigned rate limit: client_type='{}' rate='{}' burst='{}'",
+              client_type, limit.rate, limit.burst);
+        self.client_types.insert(client_type.to_owned(), limit);
+        Ok(())
+    }
+}
+
+/// TokenBucket tracks how many events can be published under a rate limit. Tokens are refilled
+/// continuously at the rate of the limit, up to the burst.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self { limit: limit, tokens: limit.burst, refilled_at: Instant::now() }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at);
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.tokens = (self.tokens + seconds * self.limit.rate).min(self.limit.burst);
+        self.refilled_at = now;
+    }
+
+    /// Find how many whole tokens are available now.
+    pub fn available(&mut self, now: Instant) -> usize {
+        self.refill(now);
+        self.tokens.floor() as usize
+    }
+
+    /// Remove tokens that were found to be available.
+    pub fn take(&mut self, count: usize) {
+        self.tokens -= count as f64;
+    }
+
+    /// Find how many milliseconds until the given number of tokens will be available.
+    pub fn retry_after_millis(&self, count: usize) -> u64 {
+        let missing = (count as f64 - self.tokens).max(0.0);
+        (missing / self.limit.rate * 1000.0).ceil() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimit;
+
+    #[test]
+    fn parse_rate_and_burst() {
+        assert_eq!(RateLimit::parse("10").unwrap(), RateLimit { rate: 10.0, burst: 10.0 });
+        assert_eq!(RateLimit::parse("0.5:5").unwrap(), RateLimit { rate: 0.5, burst: 5.0 });
+    }
+
+    #[test]
+    fn parse_refuses_invalid_limits() {
+        for argument in &["0", "-1", "10:0.5", "NaN", "inf", "10:NaN", "10:inf", "fast"] {
+            assert!(RateLimit::parse(argument).is_err(), "accepted '{}'", argument);
+        }
+    }
+}
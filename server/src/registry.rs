@@ -0,0 +1,164 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use failure::{Error, ResultExt};
+use serde_json::{Value, from_str};
+use url::Url;
+use valico::json_schema::Scope;
+
+use error::ErrorKind;
+use versioned::{VersionedErrors, read_versioned_directory};
+
+/// SchemaRegistry contains the JSON Schema documents that the data of new events is validated
+/// against, by event type and version. Event types without a schema are not validated.
+#[derive(Debug, Default)]
+pub struct SchemaRegistry {
+    schemas: HashMap<String, BTreeMap<u32, CompiledSchema>>,
+}
+
+/// CompiledSchema is a schema that has been compiled by valico, so that it is compiled once when
+/// it is registered rather than for every event. Each schema has its own scope so that schemas
+/// with the same id don't conflict.
+#[derive(Debug)]
+struct CompiledSchema {
+    scope: Scope,
+    id: Url,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load every schema from a directory laid out as `<event_type>/<version>.json`.
+    pub fn load_directory(&mut self, path: &Path) -> Result<(), Error> {
+        let errors = VersionedErrors {
+            read_directory: ErrorKind::ReadSchemaDirectory,
+            invalid_file_name: ErrorKind::InvalidSchemaFileName,
+            read_file: ErrorKind::ReadSchemaFile,
+        };
+
+        for file in read_versioned_directory(path, &errors)? {
+            let schema: Value = from_str(&file.contents).context(ErrorKind::ParseSchema)?;
+
+            info!("loaded schema: event_type='{}' version='{}'", file.event_type, file.version);
+            self.register(&file.event_type, file.version, schema)
+                .map_err(|_| ErrorKind::InvalidSchema)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a schema, replacing any existing schema for the same event type and version. If the
+    /// schema can't be compiled then the reason is returned.
+    pub fn register(&mut self, event_type: &str, version: u32,
+                    schema: Value) -> Result<(), String> {
+        let mut scope = Scope::new();
+        let id = match scope.compile(schema, false) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("invalid schema: event_type='{}' version='{}' error='{:?}'",
+                      event_type, version, e);
+                return Err(format!("{}: {:?}", ErrorKind::InvalidSchema, e));
+            },
+        };
+
+        let versions = self.schemas.entry(event_type.to_owned()).or_insert_with(BTreeMap::new);
+        if let Some(_) = versions.insert(version, CompiledSchema { scope: scope, id: id }) {
+            info!("schema replaced: event_type='{}' version='{}'", event_type, version);
+        }
+
+        Ok(())
+    }
+
+    /// Find the latest version of the schema for an event type, if there are any.
+    pub fn latest_version(&self, event_type: &str) -> Option<u32> {
+        self.schemas.get(event_type).and_then(|versions| versions.keys().next_back().cloned())
+    }
+
+    /// Validate the data of an event against the schema for its event type. If no version is
+    /// given then the latest is used. The returned errors are empty if the data is valid.
+    pub fn validate(&self, event_type: &str, version: Option<u32>, data: &Value) -> Vec<String> {
+        let versions = match self.schemas.get(event_type) {
+            Some(versions) => versions,
+            None => return Vec::new(),
+        };
+
+        let schema = match version {
+            Some(version) => versions.get(&version),
+            None => versions.values().next_back(),
+        };
+        let schema = match schema {
+            Some(schema) => schema,
+            None => return vec![format!("no schema registered for version {:?}", version)],
+        };
+
+        let compiled = match schema.scope.resolve(&schema.id) {
+            Some(compiled) => compiled,
+            None => return vec![format!("schema could not be resolved: {}", schema.id)],
+        };
+
+        compiled.validate(data).errors.iter()
+            .map(|e| format!("{}: {}", e.get_path(), e.get_title()))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::SchemaRegistry;
+
+    fn registry() -> SchemaRegistry {
+        let mut registry = SchemaRegistry::new();
+        registry.register("deposit", 1, json!({
+            "type": "object",
+            "required": ["account"],
+        })).unwrap();
+        registry.register("deposit", 2, json!({
+            "type": "object",
+            "required": ["account", "amount"],
+            "properties": {
+                "amount": { "type": "number" },
+            },
+        })).unwrap();
+        registry
+    }
+
+    #[test]
+    fn validate_accepts_valid_data() {
+        let registry = registry();
+
+        assert!(registry.validate("deposit", None,
+                                  &json!({ "account": "a", "amount": 5 })).is_empty());
+        assert!(registry.validate("deposit", Some(1), &json!({ "account": "a" })).is_empty());
+    }
+
+    #[test]
+    fn validate_rejects_invalid_data() {
+        let registry = registry();
+
+        // The latest version is used if none is given.
+        assert_eq!(registry.validate("deposit", None, &json!({ "account": "a" })).len(), 1);
+        assert!(!registry.validate("deposit", Some(2),
+                                   &json!({ "account": "a", "amount": "five" })).is_empty());
+        assert!(!registry.validate("deposit", Some(3), &json!({ "account": "a" })).is_empty());
+    }
+
+    #[test]
+    fn validate_accepts_event_types_without_schemas() {
+        let registry = registry();
+
+        assert!(registry.validate("withdrawal", Some(1), &json!(null)).is_empty());
+    }
+
+    #[test]
+    fn register_returns_why_schema_is_invalid() {
+        let mut registry = SchemaRegistry::new();
+        let error = registry.register("deposit", 1, json!({ "type": 5 })).unwrap_err();
+
+        assert!(error.starts_with("Schema is not a valid JSON Schema document: "));
+        assert!(error.len() > "Schema is not a valid JSON Schema document: ".len());
+        assert_eq!(registry.latest_version("deposit"), None);
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use actix::{AsyncContext, Context};
+use chrono::Local;
+use common::schemas::{Event, EventId};
+use couchbase::{Bucket, Document, BinaryDocument, N1qlResult};
+use failure::{Error, Fail, ResultExt};
+use futures::{Future, Stream};
+use serde_json::{from_str, to_string};
+
+use bus::Bus;
+use error::ErrorKind;
+
+/// ScheduledEvent is the document that an event is held in until it is due, each scheduled event
+/// has its own document. The event is nested so that it can't be mistaken for an event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScheduledEvent {
+    pub scheduled: Event,
+    /// This field contains the client type that published the event, only sessions of that
+    /// client type can cancel it. If the publisher had no client type then only the session that
+    /// published the event can cancel it.
+    #[serde(default)]
+    pub client_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CouchbaseStoredScheduledEvent {
+    events: ScheduledEvent,
+}
+
+/// Find the id of the document that a scheduled event is held in.
+fn document_id(event_id: &str) -> String {
+    format!("scheduled-{}", event_id)
+}
+
+/// Load every scheduled event from the event bucket.
+pub fn load_scheduled(bucket: &Bucket) -> Result<HashMap<EventId, ScheduledEvent>, Error> {
+    let query = String::from("SELECT * FROM events WHERE scheduled IS VALUED");
+    let mut scheduled = HashMap::new();
+    for row in bucket.query_n1ql(query).wait() {
+        match row {
+            Ok(N1qlResult::Meta(meta)) => {
+                debug!("raw meta received: meta='{:?}'", meta)
+            },
+            Ok(N1qlResult::Row(row)) => {
+                let parsed: CouchbaseStoredScheduledEvent = from_str(&row.as_ref()).context(
+                    ErrorKind::CouchbaseDeserialize)?;
+                scheduled.insert(parsed.events.scheduled.id.clone(), parsed.events);
+            },
+            Err(e) => return Err(Error::from(e.context(
+                        ErrorKind::CouchbaseFailedGetQueryResult))),
+        }
+    }
+
+    info!("loaded scheduled events: scheduled='{}'", scheduled.len());
+    Ok(scheduled)
+}
+
+impl Bus {
+    fn persist_scheduled_event(&mut self, scheduled: &ScheduledEvent) -> Result<(), Error> {
+        let serialized = to_string(scheduled).context(ErrorKind::SerializeJsonForSending)?;
+        let document_id = document_id(&scheduled.scheduled.id);
+        let document = BinaryDocument::create(document_id.as_str(), None,
+                                              Some(serialized.as_bytes().to_owned()), None);
+
+        debug!("persisting scheduled event to couchbase: document_id='{}'", document_id);
+        self.event_bucket.upsert(document).wait()?;
+        Ok(())
+    }
+
+    fn remove_scheduled_event(&mut self, event_id: &str) {
+        let document_id = document_id(event_id);
+        if let Err(e) = self.event_bucket.remove(document_id.as_str()).wait() {
+            warn!("failed to remove scheduled event from couchbase: document_id='{}' \
+                  error='{}'", document_id, e);
+        }
+    }
+
+    /// Store the scheduled events that earlier versions of the bus kept in a single document in
+    /// documents of their own.
+    pub fn migrate_scheduled(&mut self, legacy: HashMap<EventId, Event>) {
+        if legacy.is_empty() {
+            return;
+        }
+
+        info!("migrating scheduled events to their own documents: scheduled='{}'",
+              legacy.len());
+        for (event_id, event) in legacy {
+            let scheduled = ScheduledEvent { scheduled: event, client_type: None };
+            if let Err(e) = self.persist_scheduled_event(&scheduled) {
+                error!("failed to migrate scheduled event: event_id='{}' error='{}'",
+                       event_id, e);
+                return;
+            }
+            self.scheduled.insert(event_id, scheduled);
+        }
+
+        if let Err(e) = self.consistency_bucket.remove("scheduled").wait() {
+            warn!("failed to remove migrated scheduled events: error='{}'", e);
+        }
+    }
+
+    /// Hold an accepted event until its `deliver_at` time rather than sending it to Kafka.
+    pub fn schedule_event(&mut self, event: Event,
+                          client_type: Option<&String>) -> Result<(), Error> {
+        info!("scheduling event: event_id='{}' deliver_at='{:?}'", event.id, event.deliver_at);
+        let scheduled = ScheduledEvent { scheduled: event, client_type: client_type.cloned() };
+        self.persist_scheduled_event(&scheduled)?;
+        self.scheduled.insert(scheduled.scheduled.id.clone(), scheduled);
+        Ok(())
+    }
+
+    /// Check whether the session with the given address published a scheduled event.
+    fn owns_scheduled_event(&self, scheduled: &ScheduledEvent, addr: &SocketAddr) -> bool {
+        match scheduled.client_type {
+            Some(ref owner) => self.sessions.get(addr)
+                .and_then(|details| details.client_type.as_ref())
+                .map_or(false, |client_type| client_type == owner),
+            None => scheduled.scheduled.sender == format!("{:?}", addr),
+        }
+    }
+
+    /// Cancel a scheduled event that is not yet due, removing it from the event store. Returns
+    /// whether there was a scheduled event with that id that the session was allowed to cancel.
+    pub fn cancel_scheduled_event(&mut self, event_id: &EventId,
+                                  addr: &SocketAddr) -> Result<bool, Error> {
+        let owned = match self.scheduled.get(event_id) {
+            Some(scheduled) => self.owns_scheduled_event(scheduled, addr),
+            None => {
+                info!("no scheduled event to cancel: event_id='{}'", event_id);
+                return Ok(false);
+            },
+        };
+        if !owned {
+            warn!("refusing to cancel scheduled event of another client: event_id='{}' \
+                  client='{}'", event_id, addr);
+            return Ok(false);
+        }
+
+        let event = match self.scheduled.remove(event_id) {
+            Some(scheduled) => scheduled.scheduled,
+            None => return Ok(false),
+        };
+
+        info!("cancelled scheduled event: event_id='{}'", event_id);
+        self.remove_scheduled_event(event_id);
+        if let Err(e) = self.event_bucket.remove(event_id.as_str()).wait() {
+            warn!("failed to remove cancelled event from couchbase: event_id='{}' error='{}'",
+                  event_id, e);
+        }
+        if let Some(payload_ref) = event.payload_ref {
+            if let Err(e) = self.event_bucket.remove(payload_ref.as_str()).wait() {
+                warn!("failed to remove cancelled payload from couchbase: payload_ref='{}' \
+                      error='{}'", payload_ref, e);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Send every scheduled event that is due to Kafka, in the order they were accepted. Events
+    /// that can't be sent stay scheduled and are tried again later.
+    fn deliver_scheduled_events(&mut self) {
+        let now = Local::now().timestamp();
+        let mut due: Vec<EventId> = self.scheduled.values()
+            .map(|scheduled| &scheduled.scheduled)
+            .filter(|event| event.deliver_at.map(|deliver_at| deliver_at <= now).unwrap_or(true))
+            .map(|event| event.id.clone())
+            .collect();
+
+        // Event ids sort in the order that they were accepted.
+        due.sort();
+        for event_id in due {
+            let event = match self.scheduled.get(&event_id) {
+                Some(scheduled) => scheduled.scheduled.clone(),
+                None => continue,
+            };
+
+            info!("delivering scheduled event: event_id='{}'", event_id);
+            match self.send_to_kafka(&event, &event.event_type) {
+                Ok(()) => {
+                    self.scheduled.remove(&event_id);
+                    self.remove_scheduled_event(&event_id);
+                },
+                Err(e) => error!("failed to deliver scheduled event: event_id='{}' error='{}'",
+                                 event_id, e),
+            }
+        }
+    }
+
+    /// Check for scheduled events that are due after an interval, and keep doing so for as long
+    /// as the bus is running.
+    pub fn deliver_scheduled_events_later(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_later(self.schedule_interval, |bus, ctx| {
+            bus.deliver_scheduled_events();
+            bus.deliver_scheduled_events_later(ctx);
+        });
+    }
+}
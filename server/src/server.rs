@@ -3,21 +3,28 @@ use std::net::SocketAddr;
 
 use actix::{
     Actor,
+    ActorContext,
     Address,
     Arbiter,
+    AsyncContext,
     Context,
     FramedReader,
+    Handler,
     ResponseType,
-    StreamHandler
+    StreamHandler,
+    SyncAddress
 };
+use actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
 use failure::{Error, Fail, ResultExt};
 use rand::{self, Rng, ThreadRng};
 use websocket::async::{Server as WebsocketServer, TcpStream};
 use websocket::async::futures::{Future, Stream};
 use websocket::server::InvalidConnection;
+use websocket::async::client::Framed;
 use websocket::server::upgrade::async::Upgrade;
 
 use bus::Bus;
+use codec::LimitedMessageCodec;
 use error::ErrorKind;
 use session::Session;
 
@@ -38,11 +45,14 @@ impl ResponseType for Connection {
 pub struct Server {
     bus: Address<Bus>,
     rng: RefCell<ThreadRng>,
+    /// This field contains the largest frame that clients can send, in bytes.
+    max_frame_bytes: Option<usize>,
 }
 
 impl Server {
     /// Start the websockets server given the arguments for the `server` subcommand.
-    pub fn launch(bind_addr: &str, bus: Address<Bus>) -> Result<(), Error> {
+    pub fn launch(bind_addr: &str, bus: Address<Bus>,
+                  max_frame_bytes: Option<usize>) -> Result<(), Error> {
         // Create a websocket server instance bound to the address provided in arguments.
         let listener = WebsocketServer::bind(bind_addr, Arbiter::handle()).context(
             ErrorKind::UnableToBindWebsocketServer)?;
@@ -63,6 +73,7 @@ impl Server {
             Self {
                 bus: bus,
                 rng: RefCell::new(rand::thread_rng()),
+                max_frame_bytes: max_frame_bytes,
             }
         });
 
@@ -73,10 +84,33 @@ impl Server {
 impl Actor for Server {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) { info!("websocket server started"); }
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        info!("websocket server started");
+
+        let address: SyncAddress<_> = ctx.address();
+        let signals = Arbiter::system_registry().get::<ProcessSignals>();
+        signals.send(Subscribe(address.subscriber()));
+    }
+
     fn stopped(&mut self, _ctx: &mut Context<Self>) { info!("websocket server finished"); }
 }
 
+impl Handler<Signal> for Server {
+    type Result = ();
+
+    /// Stop accepting connections when the daemon is signalled to stop, sessions that are
+    /// already connected are told to reconnect elsewhere by the bus.
+    fn handle(&mut self, signal: Signal, ctx: &mut Context<Self>) {
+        match signal.0 {
+            SignalType::Term | SignalType::Int | SignalType::Quit => {
+                info!("websocket server no longer accepting connections");
+                ctx.stop();
+            },
+            _ => { },
+        }
+    }
+}
+
 impl StreamHandler<Connection, Error> for Server {
     /// Handle an incoming connection and create a session.
     fn handle(&mut self, conn: Connection, _: &mut Context<Self>) {
@@ -85,11 +119,17 @@ impl StreamHandler<Connection, Error> for Server {
             let bus = self.bus.clone();
             let session_id = self.rng.borrow_mut().gen::<usize>();
             let addr = conn.addr;
+            let max_frame_bytes = self.max_frame_bytes;
+            // The frames are read with a codec that enforces the frame size limit, so that frames
+            // that are too large are never buffered.
+            let framed = Framed::from_parts(framed.into_parts(),
+                                            LimitedMessageCodec::new(max_frame_bytes));
             let _: () = Session::create(move |ctx| {
                 let (reader, writer) = FramedReader::wrap(framed);
                 Session::add_stream(reader, ctx);
 
-                Session::new(addr.clone(), bus.clone(), session_id.clone(), writer)
+                Session::new(addr.clone(), bus.clone(), session_id.clone(), max_frame_bytes,
+                             writer)
             });
         } else {
             warn!("websocket connection upgrade failed");
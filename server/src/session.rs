@@ -1,4 +1,3 @@
-use std::str::from_utf8;
 use std::net::SocketAddr;
 
 use actix::{
@@ -11,44 +10,148 @@ use actix::{
     FramedWriter,
     StreamHandler
 };
+use common::{Encoding, LogContext};
+use common::protocol::{ENCODING_JSON, ENCODING_MESSAGEPACK, FEATURE_NACK, negotiate};
+use common::schemas::{Hello, Message, NewEvents, Rejected};
 use failure::{Error, ResultExt};
-use serde_json::{from_str, Value};
 use websocket::async::TcpStream;
-use websocket::codec::ws::MessageCodec;
-use websocket::message::OwnedMessage;
+use websocket::message::{CloseData, OwnedMessage};
 
 use bus::Bus;
+use codec::{Frame, LimitedMessageCodec};
 use error::ErrorKind;
 use signals;
 
+/// The encodings that the event bus is able to speak, in order of preference.
+const SUPPORTED_ENCODINGS: &[&str] = &[ENCODING_JSON, ENCODING_MESSAGEPACK];
+/// The optional protocol features that the event bus supports.
+const SUPPORTED_FEATURES: &[&str] = &[FEATURE_NACK];
+/// The websocket close status for a message that is too big to process.
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Handshake is how far a session has got with the `hello`/`welcome` exchange. The `hello` must be
+/// the first message a client sends, clients that send anything else first speak the original
+/// protocol with JSON and no optional features.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Handshake {
+    Pending,
+    Negotiated,
+    Legacy,
+}
+
 /// Session contains the state pertaining to one connected client.
 pub struct Session {
     pub addr: SocketAddr,
     bus: Address<Bus>,
-    pub framed: FramedWriter<TcpStream, MessageCodec<OwnedMessage>>,
+    pub framed: FramedWriter<TcpStream, LimitedMessageCodec>,
     session_id: usize,
+    /// This field records whether the client has sent a `hello`, which is only accepted as its
+    /// first message.
+    handshake: Handshake,
+    /// This field contains the encoding negotiated during the handshake, this is JSON until a
+    /// handshake negotiates otherwise.
+    pub encoding: Encoding,
+    /// This field contains the optional features negotiated during the handshake, messages for
+    /// any other features are refused.
+    features: Vec<String>,
+    /// This field contains the largest frame that the client can send, in bytes.
+    max_frame_bytes: Option<usize>,
+    /// This field contains the client type that the client last registered as, it is only used
+    /// for logging.
+    client_type: Option<String>,
 }
 
 impl Session {
     /// Create a Session from a socket address and a bus actor.
     pub fn new(addr: SocketAddr, bus: Address<Bus>, session_id: usize,
-               framed: FramedWriter<TcpStream, MessageCodec<OwnedMessage>>) -> Self {
+               max_frame_bytes: Option<usize>,
+               framed: FramedWriter<TcpStream, LimitedMessageCodec>) -> Self {
         Self {
             addr,
             bus,
             session_id,
             framed,
+            handshake: Handshake::Pending,
+            encoding: Encoding::Json,
+            features: Vec::new(),
+            max_frame_bytes,
+            client_type: None,
+        }
+    }
+
+    /// Create a log context for handling a message of this session.
+    pub fn log_context(&self) -> LogContext {
+        LogContext::new()
+            .field("session", self.addr)
+            .optional_field("client_type", self.client_type.as_ref())
+    }
+
+    /// Negotiate the protocol version, encoding and features with the client. Incompatible
+    /// clients are sent the reason that they were refused and then disconnected.
+    fn process_hello(&mut self, hello: Hello, ctx: &mut Context<Self>) -> Result<(), Error> {
+        if self.handshake != Handshake::Pending {
+            warn!("refusing hello that was not the first message: client='{}' handshake='{:?}'",
+                  self.addr, self.handshake);
+            return Err(Error::from(ErrorKind::UnexpectedHello));
         }
+
+        let welcome = negotiate(&hello, SUPPORTED_ENCODINGS, SUPPORTED_FEATURES);
+
+        if welcome.status == "accepted" {
+            info!("accepted client handshake: client='{}' protocol_version='{}' \
+                  encoding='{:?}' features='{:?}'", self.addr, welcome.protocol_version,
+                  welcome.encoding, welcome.features);
+            self.handshake = Handshake::Negotiated;
+
+            // The welcome is always sent as JSON, the negotiated encoding applies afterwards.
+            let encoding = welcome.encoding.as_ref().and_then(|e| Encoding::from_name(e));
+            self.features = welcome.features.clone();
+            self.send_message(Message::Welcome(welcome))?;
+            self.encoding = encoding.unwrap_or_default();
+            Ok(())
+        } else {
+            warn!("refusing incompatible client: client='{}' reason='{:?}'",
+                  self.addr, welcome.reason);
+            self.send_message(Message::Welcome(welcome))?;
+            self.framed.send(OwnedMessage::Close(None));
+            ctx.stop();
+            Ok(())
+        }
+    }
+
+    /// Check whether an optional feature was negotiated during the handshake.
+    fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Tell the client that a frame was discarded by the codec because it was too large, and then
+    /// close the connection. There is no way to know which message was in the frame, so closing
+    /// the connection is the only way for the client to find out that it was not processed.
+    fn reject_frame(&mut self, size: usize, ctx: &mut Context<Self>) -> Result<(), Error> {
+        let limit = self.max_frame_bytes.unwrap_or(0);
+        warn!("rejected frame that is too large: client='{}' size='{}' limit='{}'",
+              self.addr, size, limit);
+        self.send_message(Message::Rejected(Rejected {
+            reason: String::from("frame_too_large"),
+            limit: limit,
+            size: size,
+        }))?;
+        self.framed.send(OwnedMessage::Close(Some(CloseData {
+            status_code: CLOSE_MESSAGE_TOO_BIG,
+            reason: String::from("frame_too_large"),
+        })));
+        ctx.stop();
+        Ok(())
     }
 
     /// Process an incoming message on the Websockets connection.
     fn process_message(&mut self, message: OwnedMessage,
                        ctx: &mut Context<Self>) -> Result<(), Error> {
-        let contents: String = match message {
-            OwnedMessage::Text(m) => m,
-            OwnedMessage::Binary(b) => {
-                from_utf8(&b).context(ErrorKind::ParseBytesAsUtf8)?.to_string()
-            },
+        let contents: Message = match message {
+            OwnedMessage::Text(m) => Encoding::Json.decode(m.as_bytes()).context(
+                ErrorKind::ParseMessageFromWebsockets)?,
+            OwnedMessage::Binary(b) => self.encoding.decode(&b).context(
+                ErrorKind::ParseMessageFromWebsockets)?,
             OwnedMessage::Close(_) => {
                 info!("received a close from server");
                 ctx.stop();
@@ -65,53 +168,147 @@ impl Session {
             },
         };
 
-        let parsed_contents: Value = from_str(&contents).context(
-                ErrorKind::ParseJsonFromWebsockets)?;
-        let message_type = parsed_contents["message_type"].as_str().ok_or(
-                ErrorKind::NoMessageTypeFromWebsockets)?;
+        let message_type = contents.message_type();
+        let is_hello = match contents {
+            Message::Hello(_) => true,
+            _ => false,
+        };
+        if self.handshake == Handshake::Pending && !is_hello {
+            info!("client did not send a hello, using the original protocol: client='{}'",
+                  self.addr);
+            self.handshake = Handshake::Legacy;
+        }
+
+        if let Some(feature) = required_feature(&contents) {
+            if !self.supports(feature) {
+                warn!("refusing message for a feature that was not negotiated: client='{}' \
+                      message_type='{}' feature='{}'", self.addr, message_type, feature);
+                return Err(Error::from(ErrorKind::FeatureNotNegotiated));
+            }
+        }
 
-        match message_type {
-            "query" => {
+        match contents {
+            Message::Hello(hello) => {
+                debug!("processing hello message");
+                self.process_hello(hello, ctx)?;
+            },
+            Message::Query(query) => {
                 debug!("sending query message to bus");
                 let query = signals::Query {
-                    message: contents,
+                    message: query,
                     sender: ctx.address(),
+                    addr: self.addr,
                     bus: self.bus.clone(),
                 };
                 self.bus.send(query);
                 debug!("sent query message to bus");
             },
-            "new" => {
+            Message::NewEvents(new_events) => {
                 debug!("sending new event message to bus");
                 let new_event = signals::NewEvent {
-                    message: contents,
+                    message: new_events,
                     sender: (ctx.address(), self.addr),
                     bus: self.bus.clone(),
                     session_id: self.session_id,
+                    target: None,
                 };
                 self.bus.send(new_event);
                 debug!("sent new event message to bus");
             },
-            "register" => {
+            Message::Command(command) => {
+                debug!("sending command message to bus");
+                let new_event = signals::NewEvent {
+                    message: NewEvents {
+                        events: vec![command.command],
+                        atomic: false,
+                    },
+                    sender: (ctx.address(), self.addr),
+                    bus: self.bus.clone(),
+                    session_id: self.session_id,
+                    target: Some(command.client_type),
+                };
+                self.bus.send(new_event);
+                debug!("sent command message to bus");
+            },
+            Message::Register(register) => {
                 debug!("sending register message to bus");
+                self.client_type = Some(register.client_type.clone());
                 let register = signals::Register {
-                    message: contents,
+                    message: register,
                     sender: (ctx.address(), self.addr),
                     bus: self.bus.clone(),
                 };
                 self.bus.send(register);
                 debug!("sent register message to bus");
             },
-            "ack" => {
+            Message::Ack(ack) => {
                 debug!("sending acknowledgement message to bus");
                 let acknowledgement = signals::Acknowledgement {
-                    message: contents,
+                    message: ack,
                     addr: self.addr,
                 };
                 self.bus.send(acknowledgement);
                 debug!("sent acknowledgement message to bus");
             },
+            Message::Nack(nack) => {
+                debug!("sending negative acknowledgement message to bus");
+                let negative_acknowledgement = signals::NegativeAcknowledgement {
+                    message: nack,
+                    addr: self.addr,
+                };
+                self.bus.send(negative_acknowledgement);
+                debug!("sent negative acknowledgement message to bus");
+            },
+            Message::GetEvent(get_event) => {
+                debug!("sending get event message to bus");
+                let get_event = signals::GetEvent {
+                    message: get_event,
+                    sender: ctx.address(),
+                };
+                self.bus.send(get_event);
+                debug!("sent get event message to bus");
+            },
+            Message::RegisterSchema(register_schema) => {
+                debug!("sending register schema message to bus");
+                let register_schema = signals::RegisterSchema {
+                    message: register_schema,
+                    sender: ctx.address(),
+                };
+                self.bus.send(register_schema);
+                debug!("sent register schema message to bus");
+            },
+            Message::Cancel(cancel) => {
+                debug!("sending cancel message to bus");
+                let cancel = signals::Cancel {
+                    message: cancel,
+                    sender: ctx.address(),
+                    addr: self.addr,
+                };
+                self.bus.send(cancel);
+                debug!("sent cancel message to bus");
+            },
+            Message::Request(request) => {
+                debug!("sending request message to bus");
+                let request = signals::Request {
+                    message: request,
+                    addr: self.addr,
+                };
+                self.bus.send(request);
+                debug!("sent request message to bus");
+            },
+            Message::Reply(reply) => {
+                debug!("sending reply message to bus");
+                let reply = signals::Reply {
+                    message: reply,
+                    addr: self.addr,
+                };
+                self.bus.send(reply);
+                debug!("sent reply message to bus");
+            },
+            // Any other messages are only ever sent from the bus to clients.
             _ => {
+                warn!("received unexpected message from client: client='{}' message_type='{}'",
+                      self.addr, message_type);
                 return Err(Error::from(ErrorKind::InvalidWebsocketMessageType));
             },
         };
@@ -120,11 +317,19 @@ impl Session {
     }
 }
 
+/// Find the optional feature that must have been negotiated for a client to send a message.
+fn required_feature(message: &Message) -> Option<&'static str> {
+    match *message {
+        Message::Nack(_) => Some(FEATURE_NACK),
+        _ => None,
+    }
+}
+
 impl Actor for Session {
     type Context = Context<Self>;
 }
 
-impl StreamHandler<OwnedMessage, FramedError<MessageCodec<OwnedMessage>>> for Session {
+impl StreamHandler<Frame, FramedError<LimitedMessageCodec>> for Session {
     // When we get a new session, talk to the bus actor and add it to the sessions map.
     fn started(&mut self, ctx: &mut Self::Context) {
         debug!("started session: client='{}'", self.addr);
@@ -138,8 +343,14 @@ impl StreamHandler<OwnedMessage, FramedError<MessageCodec<OwnedMessage>>> for Se
         info!("sent connect message to bus");
     }
 
-    fn handle(&mut self, message: OwnedMessage, ctx: &mut Context<Self>) {
-        if let Err(e) = self.process_message(message, ctx) {
+    fn handle(&mut self, frame: Frame, ctx: &mut Context<Self>) {
+        let _log = self.log_context();
+        let result = match frame {
+            Frame::Message(message) => self.process_message(message, ctx),
+            Frame::TooLarge(size) => self.reject_frame(size, ctx),
+        };
+
+        if let Err(e) = result {
             error!("processing message from websockets: session='{}' error='{}'", self.addr, e);
         }
     }
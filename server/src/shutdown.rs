@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::process::exit;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actix::{Arbiter, AsyncContext, Context, Handler, SyncAddress};
+use actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
+use actix::msgs::SystemExit;
+use common::schemas::{Event, Message, Shutdown};
+use couchbase::BinaryDocument;
+use failure::{Error, ResultExt};
+use futures::Future;
+use serde_json::to_string;
+
+use bus::Bus;
+use error::ErrorKind;
+use signals::SendToClient;
+
+impl Bus {
+    /// Subscribe to the signals that the process receives so that the bus can shut down
+    /// gracefully.
+    pub fn subscribe_to_signals(&mut self, ctx: &mut Context<Self>) {
+        let address: SyncAddress<_> = ctx.address();
+        let signals = Arbiter::system_registry().get::<ProcessSignals>();
+        signals.send(Subscribe(address.subscriber()));
+    }
+
+    /// The notice that is sent to clients when the bus is shutting down.
+    pub fn shutdown_notice(&self) -> Message {
+        Message::Shutdown(Shutdown {
+            reason: String::from("shutting_down"),
+            deadline_ms: self.shutdown_timeout.as_secs() * 1000,
+        })
+    }
+
+    /// Persist the events that are waiting for a client type, removing the document once there
+    /// are none left. Events that are being replayed to the instances of a fan-out client type
+    /// are kept in the document until their replay window has passed, so that instances that
+    /// register after a restart still receive them.
+    pub fn persist_pending_to_couchbase(&mut self) -> Result<(), Error> {
+        let mut persisted: BTreeMap<&String, Vec<&Event>> = BTreeMap::new();
+        for (client_type, events) in self.pending_events.iter().chain(self.fanout_replay.iter()) {
+            let kept = persisted.entry(client_type).or_insert_with(Vec::new);
+            for event in events {
+                if !kept.iter().any(|existing| existing.id == event.id) {
+                    kept.push(event);
+                }
+            }
+        }
+
+        if persisted.values().all(Vec::is_empty) {
+            if let Err(e) = self.consistency_bucket.remove("pending").wait() {
+                debug!("no pending events to remove from couchbase: error='{}'", e);
+            }
+            return Ok(());
+        }
+
+        let serialized = to_string(&persisted).context(ErrorKind::SerializeHashMapForCouchbase)?;
+
+        let document = BinaryDocument::create("pending", None,
+                                              Some(serialized.as_bytes().to_owned()), None);
+
+        info!("persisting pending events to couchbase: client_types='{}'", persisted.len());
+        self.consistency_bucket.upsert(document).wait()?;
+
+        Ok(())
+    }
+
+    /// Move the events that sessions have not acknowledged into the pending events of their
+    /// client type, so that they are sent to the first instance of that type to register after
+    /// the bus restarts.
+    fn requeue_unacknowledged_events(&mut self) {
+        for details in self.sessions.values() {
+            let client_type = match details.client_type {
+                Some(ref client_type) => client_type,
+                None => continue,
+            };
+
+            let mut events: Vec<_> = details.unacknowledged_events.values().cloned().collect();
+            events.sort_by_key(|event| event.timestamp_raw);
+
+            let pending = self.pending_events.entry(client_type.clone()).or_insert_with(Vec::new);
+            for event in events {
+                // Each instance of a fan-out client type has its own copy of the event.
+                if !pending.iter().any(|existing| existing.id == event.id) {
+                    pending.push(event);
+                }
+            }
+        }
+    }
+
+    /// Check whether every session has acknowledged or rejected the events sent to it.
+    fn sessions_settled(&self) -> bool {
+        self.sessions.values().all(|details| details.unacknowledged_events.is_empty())
+    }
+
+    /// Keep sessions open so that they can settle the events they were sent, until they have all
+    /// done so or the deadline is close, and then finish shutting down.
+    fn drain_sessions(&mut self, deadline: Instant, ctx: &mut Context<Self>) {
+        let margin = self.drain_interval * 2;
+        if self.sessions_settled() {
+            info!("every session has settled its events");
+        } else if Instant::now() + margin >= deadline {
+            warn!("shutdown deadline is close, requeueing unsettled events: sessions='{}'",
+                  self.sessions.values()
+                      .filter(|details| !details.unacknowledged_events.is_empty())
+                      .count());
+        } else {
+            ctx.run_later(self.drain_interval, move |bus, ctx| {
+                bus.drain_sessions(deadline, ctx);
+            });
+            return;
+        }
+
+        self.finish_shut_down(deadline, ctx);
+    }
+
+    /// Persist the state that would be lost, flush the producer and exit.
+    fn finish_shut_down(&mut self, deadline: Instant, ctx: &mut Context<Self>) {
+        self.requeue_unacknowledged_events();
+        if let Err(e) = self.persist_consistency_to_couchbase() {
+            error!("failed to persist consistency map on shutdown: error='{}'", e);
+        }
+        if let Err(e) = self.persist_pending_to_couchbase() {
+            error!("failed to persist pending events on shutdown: error='{}'", e);
+        }
+
+        let now = Instant::now();
+        let remaining = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+        let flush = if remaining > self.drain_interval {
+            remaining - self.drain_interval
+        } else {
+            Duration::from_secs(0)
+        };
+        let flush_millis = flush.as_secs() * 1000 + u64::from(flush.subsec_millis());
+        info!("flushing kafka producer: in_flight='{}'", self.producer.in_flight_count());
+        self.producer.flush(flush_millis as i32);
+
+        ctx.run_later(self.drain_interval, |_, _| {
+            info!("bus shut down, exiting");
+            Arbiter::system().send(SystemExit(0));
+        });
+    }
+
+    /// Stop the bus: tell every client to reconnect elsewhere, wait for sessions to settle the
+    /// events they were sent, persist the state that would be lost, flush the producer and exit
+    /// once the shutdown timeout has passed at the latest.
+    fn shut_down(&mut self, ctx: &mut Context<Self>) {
+        if self.shutting_down {
+            warn!("bus is already shutting down");
+            return;
+        }
+        self.shutting_down = true;
+
+        let timeout = self.shutdown_timeout;
+        info!("shutting down bus: sessions='{}' deadline_secs='{}'",
+              self.sessions.len(), timeout.as_secs());
+
+        // Persisting and flushing blocks, so make sure that we exit even if they never finish.
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            error!("shutdown deadline passed, exiting");
+            exit(1);
+        });
+
+        let notice = self.shutdown_notice();
+        for details in self.sessions.values() {
+            details.address.send(SendToClient(notice.clone()));
+        }
+
+        self.drain_sessions(Instant::now() + timeout, ctx);
+    }
+}
+
+impl Handler<Signal> for Bus {
+    type Result = ();
+
+    fn handle(&mut self, signal: Signal, ctx: &mut Context<Self>) {
+        match signal.0 {
+            SignalType::Term | SignalType::Int | SignalType::Quit => {
+                info!("received signal to shut down: signal='{:?}'", signal.0);
+                self.shut_down(ctx);
+            },
+            _ => debug!("ignoring signal: signal='{:?}'", signal.0),
+        }
+    }
+}
@@ -1,9 +1,8 @@
 use std::net::SocketAddr;
 
 use actix::{Context, Handler, ResponseType};
-use common::schemas::Event;
-use failure::{Error, ResultExt};
-use serde_json::from_str;
+use common::schemas::Ack;
+use failure::Error;
 
 use bus::Bus;
 use error::ErrorKind;
@@ -12,7 +11,7 @@ use error::ErrorKind;
 /// processing of a message.
 #[derive(Clone)]
 pub struct Acknowledgement {
-    pub message: String,
+    pub message: Ack,
     pub addr: SocketAddr,
 }
 
@@ -23,20 +22,23 @@ impl ResponseType for Acknowledgement {
 
 impl Bus {
     fn process_acknowledgement(&mut self, message: Acknowledgement) -> Result<(), Error> {
-        let parsed: Event = from_str(&message.message).context(ErrorKind::ParseAcknowledgement)?;
         match self.sessions.get_mut(&message.addr) {
             Some(details) => {
-                if details.unacknowledged_events.remove(&parsed) {
-                    info!("successfully removed event from unacknowledged events: client='{}'",
-                          message.addr);
+                details.lanes.complete(&message.message.event_id);
+                if details.unacknowledged_events.remove(&message.message.event_id).is_some() {
+                    info!("successfully removed event from unacknowledged events: client='{}' \
+                          event_id='{}'", message.addr, message.message.event_id);
                 } else {
-                    warn!("attempt to remove unacknowledged event that does not exist");
+                    warn!("attempt to remove unacknowledged event that does not exist: \
+                          event_id='{}'", message.message.event_id);
                 }
-
-                Ok(())
             },
             None => return Err(Error::from(ErrorKind::SessionNotInHashMap)),
         }
+
+        // The acknowledged event no longer counts against the in-flight budget of the client.
+        self.flush_client(message.addr);
+        Ok(())
     }
 }
 
@@ -44,6 +46,7 @@ impl Handler<Acknowledgement> for Bus {
     type Result = ();
 
     fn handle(&mut self, message: Acknowledgement, _: &mut Context<Self>) {
+        let _log = self.acknowledgement_log_context(&message.addr, &message.message.event_id);
         debug!("received 'acknowledgement' signal: client='{}'", message.addr);
         if let Err(e) = self.process_acknowledgement(message) {
             error!("processing unacknowledgement: error='{}'", e);
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use actix::{Context, Handler, ResponseType};
+use common::schemas::{ConsistencyKey, Event, Predicate};
+use failure::{Error, ResultExt};
+use serde_json::{Value, to_value};
+
+use bus::{Bus, RegisteredTypes, SessionDetails};
+use error::ErrorKind;
+use signals::Close;
+
+/// AdminCommand is an operation that an operator has requested through the admin API.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdminCommand {
+    ListSessions,
+    ListStickyKeys,
+    ListPendingEvents,
+    /// Close the connection of the session with the given address, its unacknowledged events
+    /// are redelivered as if it had disconnected by itself.
+    Disconnect(SocketAddr),
+    /// Forget which session handles a consistency key for a client type, so that the next event
+    /// with that key is sent to whichever session is next in turn. The key is only released while
+    /// the session has no unacknowledged or queued events for it, unless `force` is set.
+    ReleaseStickyKey { client_type: String, key: ConsistencyKey, force: bool },
+    /// Send the pending events of a client type to its sessions now.
+    FlushPendingEvents(String),
+}
+
+/// AdminResponse contains the HTTP status code and JSON body that answer an admin command.
+pub struct AdminResponse(pub u16, pub Value);
+
+/// The `Admin` message is sent to the Bus from the admin http server when an operator wants to
+/// inspect or change the state of the bus.
+pub struct Admin(pub AdminCommand);
+
+impl ResponseType for Admin {
+    type Item = AdminResponse;
+    type Error = Error;
+}
+
+/// SessionSummary is how a session is shown through the admin API.
+#[derive(Serialize)]
+struct SessionSummary {
+    address: String,
+    client_type: Option<String>,
+    /// This field is set if the session is registered for every event type, in which case
+    /// `event_types` is empty.
+    all_event_types: bool,
+    event_types: Vec<String>,
+    predicates: Vec<Predicate>,
+    sticky_keys: Vec<ConsistencyKey>,
+    /// This field contains the events sent to the session that it has not acknowledged, oldest
+    /// first.
+    unacknowledged_events: Vec<Event>,
+    /// This field contains how many events are waiting for prefetch budget before they are sent.
+    queued_events: usize,
+}
+
+/// StickyKey is how the ownership of a consistency key is shown through the admin API.
+#[derive(Serialize)]
+struct StickyKey {
+    client_type: String,
+    key: ConsistencyKey,
+    session: String,
+}
+
+impl SessionSummary {
+    fn new(address: &SocketAddr, details: &SessionDetails) -> Self {
+        let (all_event_types, event_types) = match details.registered_types {
+            RegisteredTypes::All => (true, Vec::new()),
+            RegisteredTypes::Some(ref event_types) => (false, event_types.clone()),
+        };
+
+        let mut sticky_keys: Vec<_> = details.consistency_keys.iter()
+            .map(|&(_, ref key)| key.clone())
+            .collect();
+        sticky_keys.sort();
+
+        let mut unacknowledged_events: Vec<_> = details.unacknowledged_events.values()
+            .cloned()
+            .collect();
+        unacknowledged_events.sort_by_key(|event| event.timestamp_raw);
+
+        Self {
+            address: address.to_string(),
+            client_type: details.client_type.clone(),
+            all_event_types: all_event_types,
+            event_types: event_types,
+            predicates: details.predicates.clone(),
+            sticky_keys: sticky_keys,
+            unacknowledged_events: unacknowledged_events,
+            queued_events: details.lanes.queued(),
+        }
+    }
+}
+
+impl Bus {
+    fn list_sessions(&self) -> Result<AdminResponse, Error> {
+        let mut sessions: Vec<_> = self.sessions.iter()
+            .map(|(address, details)| SessionSummary::new(address, details))
+            .collect();
+        sessions.sort_by(|a, b| a.address.cmp(&b.address));
+
+        Ok(AdminResponse(200, to_value(&sessions).context(ErrorKind::SerializeJsonForSending)?))
+    }
+
+    fn list_sticky_keys(&self) -> Result<AdminResponse, Error> {
+        let mut keys: Vec<_> = self.sticky_consistency.iter()
+            .map(|(&(ref client_type, ref key), session)| StickyKey {
+                client_type: client_type.clone(),
+                key: key.clone(),
+                session: session.to_string(),
+            })
+            .collect();
+        keys.sort_by(|a, b| (&a.client_type, &a.key).cmp(&(&b.client_type, &b.key)));
+
+        Ok(AdminResponse(200, to_value(&keys).context(ErrorKind::SerializeJsonForSending)?))
+    }
+
+    fn list_pending_events(&self) -> Result<AdminResponse, Error> {
+        let pending: BTreeMap<_, _> = self.pending_events.iter().collect();
+        Ok(AdminResponse(200, to_value(&pending).context(ErrorKind::SerializeJsonForSending)?))
+    }
+
+    fn force_disconnect(&mut self, address: SocketAddr) -> Result<AdminResponse, Error> {
+        match self.sessions.get(&address) {
+            Some(details) => {
+                warn!("disconnecting session for operator: client='{}'", address);
+                // The session tells the bus that it has disconnected once it has stopped, which
+                // cleans up after it in the same way as any other disconnect.
+                details.address.send(Close);
+                Ok(AdminResponse(202, json!({ "status": "disconnecting" })))
+            },
+            None => Ok(AdminResponse(404, json!({ "error": "session_not_found" }))),
+        }
+    }
+
+    /// Check whether a session has no events for a consistency key that are unacknowledged or
+    /// waiting to be sent, so that releasing the key can't reorder them.
+    fn sticky_key_idle(&self, session: &SocketAddr, key: &ConsistencyKey) -> bool {
+        match self.sessions.get(session) {
+            Some(details) => {
+                !details.lanes.has_queued(key) &&
+                    !details.unacknowledged_events.values()
+                        .any(|event| event.consistency.key == *key)
+            },
+            None => true,
+        }
+    }
+
+    fn release_sticky_key(&mut self, client_type: String, key: ConsistencyKey,
+                          force: bool) -> Result<AdminResponse, Error> {
+        let sticky_key = (client_type, key);
+        let session = match self.sticky_consistency.get(&sticky_key) {
+            Some(session) => *session,
+            None => return Ok(AdminResponse(404, json!({ "error": "sticky_key_not_found" }))),
+        };
+
+        // The next event for the key could be sent to another session before the events that
+        // the current session has for it, which would break the ordering of the key.
+        if !force && !self.sticky_key_idle(&session, &sticky_key.1) {
+            return Ok(AdminResponse(409, json!({
+                "error": "sticky_key_in_use",
+                "session": session.to_string(),
+            })));
+        }
+
+        self.sticky_consistency.remove(&sticky_key);
+        warn!("releasing sticky key for operator: client_type='{}' key='{}' client='{}' \
+              force='{}'", sticky_key.0, sticky_key.1, session, force);
+        if let Some(details) = self.sessions.get_mut(&session) {
+            details.consistency_keys.remove(&sticky_key);
+        }
+
+        Ok(AdminResponse(200, json!({ "status": "released", "session": session.to_string() })))
+    }
+
+    fn flush_pending_events(&mut self, client_type: String) -> Result<AdminResponse, Error> {
+        let count = self.pending_events.get(&client_type).map_or(0, Vec::len);
+        if count == 0 {
+            return Ok(AdminResponse(404, json!({ "error": "no_pending_events" })));
+        }
+
+        warn!("flushing pending events for operator: client_type='{}' events='{}'",
+              client_type, count);
+        self.resend_events_for_client_type(client_type.clone())?;
+
+        // Events that still can't be sent, because there are no sessions of the client type,
+        // are pending again.
+        let remaining = self.pending_events.get(&client_type).map_or(0, Vec::len);
+        Ok(AdminResponse(200, json!({ "flushed": count - remaining, "pending": remaining })))
+    }
+
+    fn process_admin_command(&mut self, command: AdminCommand) -> Result<AdminResponse, Error> {
+        match command {
+            AdminCommand::ListSessions => self.list_sessions(),
+            AdminCommand::ListStickyKeys => self.list_sticky_keys(),
+            AdminCommand::ListPendingEvents => self.list_pending_events(),
+            AdminCommand::Disconnect(address) => self.force_disconnect(address),
+            AdminCommand::ReleaseStickyKey { client_type, key, force } => {
+                self.release_sticky_key(client_type, key, force)
+            },
+            AdminCommand::FlushPendingEvents(client_type) => {
+                self.flush_pending_events(client_type)
+            },
+        }
+    }
+}
+
+impl Handler<Admin> for Bus {
+    type Result = Result<AdminResponse, Error>;
+
+    fn handle(&mut self, message: Admin, _: &mut Context<Self>) -> Self::Result {
+        debug!("received 'admin' signal: command='{:?}'", message.0);
+        self.process_admin_command(message.0)
+    }
+}
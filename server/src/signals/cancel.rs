@@ -0,0 +1,48 @@
+use std::net::SocketAddr;
+
+use actix::{Address, Context, Handler, ResponseType};
+use common::schemas::{Cancel as CancelSchema, Cancellation, Message};
+use failure::Error;
+
+use bus::Bus;
+use session::Session;
+use signals::SendToClient;
+
+/// The `Cancel` message is sent to the Bus when a client wants to cancel a scheduled event.
+pub struct Cancel {
+    pub message: CancelSchema,
+    pub sender: Address<Session>,
+    pub addr: SocketAddr,
+}
+
+impl ResponseType for Cancel {
+    type Item = ();
+    type Error = ();
+}
+
+impl Bus {
+    fn process_cancel(&mut self, message: Cancel) -> Result<(), Error> {
+        let event_id = message.message.event_id;
+        let message_id = message.message.message_id;
+        debug!("cancelling scheduled event: event_id='{}'", event_id);
+
+        let cancelled = self.cancel_scheduled_event(&event_id, &message.addr)?;
+        message.sender.send(SendToClient(Message::Cancellation(Cancellation {
+            event_id: event_id,
+            cancelled: cancelled,
+            message_id: message_id,
+        })));
+        Ok(())
+    }
+}
+
+impl Handler<Cancel> for Bus {
+    type Result = ();
+
+    fn handle(&mut self, message: Cancel, _: &mut Context<Self>) {
+        let _log = self.session_log_context(&message.addr);
+        if let Err(e) = self.process_cancel(message) {
+            error!("processing cancel: error='{}'", e);
+        }
+    }
+}
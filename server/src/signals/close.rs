@@ -0,0 +1,23 @@
+use actix::{ActorContext, Context, Handler, ResponseType};
+use websocket::message::OwnedMessage;
+
+use session::Session;
+
+/// The `Close` message is sent to a Session when the bus wants to disconnect its client, such as
+/// when an operator forces it to through the admin API.
+pub struct Close;
+
+impl ResponseType for Close {
+    type Item = ();
+    type Error = ();
+}
+
+impl Handler<Close> for Session {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Context<Self>) {
+        info!("closing session: client='{}'", self.addr);
+        self.framed.send(OwnedMessage::Close(None));
+        ctx.stop();
+    }
+}
@@ -1,10 +1,13 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 
 use actix::{Address, Context, Handler, ResponseType};
 
 use bus::{Bus, SessionDetails, RegisteredTypes};
+use lanes::Lanes;
+use rate_limit::TokenBucket;
 use session::Session;
+use signals::SendToClient;
 
 /// The `Connect` message is sent to the Bus when a client connects.
 pub struct Connect {
@@ -21,12 +24,23 @@ impl Handler<Connect> for Bus {
     type Result = ();
 
     fn handle(&mut self, message: Connect, _: &mut Context<Self>) {
+        // The websocket server stops accepting connections on shutdown, but a client could have
+        // connected just before it did.
+        if self.shutting_down {
+            info!("sending shutdown notice to client that connected while shutting down: \
+                  client='{}'", message.addr);
+            message.session.send(SendToClient(self.shutdown_notice()));
+        }
+
         let details = SessionDetails {
             address: message.session,
             registered_types: RegisteredTypes::All,
+            predicates: Vec::new(),
             client_type: None,
             consistency_keys: HashSet::new(),
-            unacknowledged_events: HashSet::new(),
+            unacknowledged_events: HashMap::new(),
+            lanes: Lanes::new(),
+            rate_limiter: self.rate_limits.session.map(TokenBucket::new),
         };
 
         if let Some(_) = self.sessions.insert(message.addr, details) {
@@ -2,8 +2,9 @@ use std::net::SocketAddr;
 
 use actix::{Context, Handler, ResponseType};
 use common::VecDequeExt;
+use common::schemas::{DeliveryMode, Reply};
 use failure::Error;
-use serde_json::to_string_pretty;
+use serde_json::{Value, to_string_pretty};
 
 use bus::Bus;
 use error::ErrorKind;
@@ -55,6 +56,27 @@ impl Bus {
         }
     }
 
+    /// Reply with an error to the requests that were forwarded to a disconnecting client, rather
+    /// than leaving them to time out.
+    fn fail_pending_requests(&mut self, addr: &SocketAddr) {
+        let request_ids: Vec<String> = self.pending_requests.iter()
+            .filter(|&(_, pending)| pending.responder == *addr)
+            .map(|(request_id, _)| request_id.clone())
+            .collect();
+
+        for request_id in request_ids {
+            if let Some(pending) = self.pending_requests.remove(&request_id) {
+                warn!("responder disconnected before replying: request_id='{}' responder='{}'",
+                      request_id, addr);
+                self.send_reply(&pending.origin, Reply {
+                    request_id: pending.request_id,
+                    data: Value::Null,
+                    error: Some(String::from("responder disconnected")),
+                });
+            }
+        }
+    }
+
     fn handle_unacknowledged_events(&mut self, message: Disconnect) -> Result<(), Error> {
         debug!("processing unacknowledged events for disconnecting client: client='{}'",
                message.addr);
@@ -67,15 +89,31 @@ impl Bus {
             None => return Err(Error::from(ErrorKind::SessionNotInHashMap)),
         };
 
+        // The other instances of a fan-out client type have received their own copies of these
+        // events, so they are only kept if there are no instances left to receive them.
+        if self.delivery_mode(&client_type) == DeliveryMode::Fanout {
+            let remaining = self.round_robin_state.get(&client_type).map_or(0, |q| q.len());
+            if remaining > 0 {
+                info!("dropping unacknowledged events of fan-out client: client='{}' \
+                      events='{}'", message.addr, unacknowledged_events.len());
+                return Ok(());
+            }
+        }
 
-        for unacknowledged_event in unacknowledged_events.iter() {
-            debug!("re-propagating unacknowledged event: event=\n{}",
+        self.metrics.increment("redeliveries_total", vec![
+            ("client_type", client_type.clone()),
+            ("reason", String::from("disconnect")),
+        ], unacknowledged_events.len() as u64);
+        for unacknowledged_event in unacknowledged_events.values() {
+            trace!("re-propagating unacknowledged event: event=\n{}",
                    to_string_pretty(&unacknowledged_event)?);
-            // We should convert this back to a event for sending - in the list it is meant for
-            // matching with the expected incoming acks.
-            let mut unacknowledged_event = unacknowledged_event.clone();
-            unacknowledged_event.message_type = Some(String::from("event"));
-            self.propagate_event_to_client_type(&unacknowledged_event, client_type.clone());
+            // The other sessions are leaving too while shutting down, so the event is kept for
+            // the next instance of the bus.
+            if self.shutting_down {
+                self.save_pending_event(unacknowledged_event, client_type.clone());
+            } else {
+                self.propagate_event_to_client_type(unacknowledged_event, client_type.clone());
+            }
         }
 
         Ok(())
@@ -86,11 +124,15 @@ impl Handler<Disconnect> for Bus {
     type Result = ();
 
     fn handle(&mut self, message: Disconnect, _: &mut Context<Self>) {
+        let _log = self.session_log_context(&message.addr);
         info!("removing session from bus: client='{}'", message.addr);
 
         // Remove the client address from the round robin state.
         self.remove_client_from_round_robin_state(message.clone());
 
+        // Fail the requests that this client will now never reply to.
+        self.fail_pending_requests(&message.addr);
+
         // Process any unacknowledged events.
         if let Err(e) = self.handle_unacknowledged_events(message.clone()) {
             error!("handling unacknowledged events: error='{}'", e);
@@ -0,0 +1,101 @@
+use actix::{Address, Context, Handler, ResponseType};
+use common::schemas::{Event, EventLookup, GetEvent as GetEventSchema, Message};
+use couchbase::{BinaryDocument, CouchbaseError};
+use failure::{Error, Fail, ResultExt};
+use futures::Future;
+use serde::de::DeserializeOwned;
+use serde_json::from_str;
+
+use bus::Bus;
+use error::ErrorKind;
+use session::Session;
+use signals::SendToClient;
+use signals::new_event::StoredPayload;
+
+/// The `GetEvent` message is sent to the Bus when a client wants to look up an event by its id.
+pub struct GetEvent {
+    pub message: GetEventSchema,
+    pub sender: Address<Session>,
+}
+
+impl ResponseType for GetEvent {
+    type Item = ();
+    type Error = ();
+}
+
+impl Bus {
+    fn find_document<T: DeserializeOwned>(&mut self,
+                                          document_id: &str) -> Result<Option<T>, Error> {
+        let document = match self.event_bucket.get::<BinaryDocument, _>(document_id).wait() {
+            Ok(document) => document,
+            Err(CouchbaseError::KeyDoesNotExist) => return Ok(None),
+            Err(e) => return Err(Error::from(e.context(ErrorKind::CouchbaseFailedGetEvent))),
+        };
+
+        match document.content_as_str()? {
+            Some(text) => Ok(Some(from_str(text).context(ErrorKind::CouchbaseDeserialize)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fill in the data of an event that was stored out-of-line, so that predicates, upcasters
+    /// and clients see the data rather than `null`.
+    pub fn load_payload(&mut self, mut event: Event) -> Result<Event, Error> {
+        if let Some(payload_ref) = event.payload_ref.clone() {
+            debug!("loading out-of-line payload: event_id='{}' payload_ref='{}'",
+                   event.id, payload_ref);
+            let stored: Option<StoredPayload> = self.find_document(&payload_ref)?;
+            match stored {
+                Some(stored) => event.data = stored.payload,
+                None => warn!("out-of-line payload is missing: event_id='{}' payload_ref='{}'",
+                              event.id, payload_ref),
+            }
+        }
+
+        Ok(event)
+    }
+
+    /// Find an event by its id, including its data if that was stored out-of-line.
+    fn find_event(&mut self, event_id: &str) -> Result<Option<Event>, Error> {
+        let event: Event = match self.find_document(event_id)? {
+            Some(event) => event,
+            None => return Ok(None),
+        };
+
+        let event = self.load_payload(event)?;
+        Ok(Some(self.upcasters.upcast(event)))
+    }
+
+    pub fn process_get_event(&mut self, message: GetEvent) {
+        let event_id = message.message.event_id;
+        let message_id = message.message.message_id;
+        debug!("looking up event: event_id='{}'", event_id);
+
+        // The client is always answered, even if the lookup fails, so that it isn't left waiting.
+        let (event, error) = match self.find_event(&event_id) {
+            Ok(event) => (event, None),
+            Err(e) => {
+                error!("failed to look up event: event_id='{}' error='{}'", event_id, e);
+                (None, Some(e.to_string()))
+            },
+        };
+        if event.is_none() && error.is_none() {
+            info!("event lookup found no event: event_id='{}'", event_id);
+        }
+
+        message.sender.send(SendToClient(Message::EventLookup(EventLookup {
+            event_id: event_id,
+            event: event,
+            error: error,
+            message_id: message_id,
+        })));
+    }
+}
+
+impl Handler<GetEvent> for Bus {
+    type Result = ();
+
+    fn handle(&mut self, message: GetEvent, _: &mut Context<Self>) {
+        self.process_get_event(message);
+    }
+}
@@ -0,0 +1,57 @@
+use actix::{Context, Handler, ResponseType};
+
+use bus::Bus;
+
+/// The `GetMetrics` message is sent to the Bus when the metrics endpoint is requested, the Bus
+/// responds with its metrics in the Prometheus text format.
+pub struct GetMetrics;
+
+impl ResponseType for GetMetrics {
+    type Item = String;
+    type Error = ();
+}
+
+impl Bus {
+    /// Set the gauges that describe the current state of the bus. They are recomputed each time
+    /// that metrics are requested rather than kept up to date as the state changes.
+    fn update_gauges(&mut self) {
+        self.metrics.reset_gauges();
+
+        for details in self.sessions.values() {
+            let client_type = details.client_type.clone()
+                .unwrap_or_else(|| String::from("unregistered"));
+            self.metrics.add_to_gauge("sessions", vec![("client_type", client_type.clone())],
+                                      1.0);
+            self.metrics.add_to_gauge("unacknowledged_events",
+                                      vec![("client_type", client_type)],
+                                      details.unacknowledged_events.len() as f64);
+        }
+
+        for (client_type, queue) in &self.round_robin_state {
+            self.metrics.set_gauge("round_robin_queue_length",
+                                   vec![("client_type", client_type.clone())],
+                                   queue.len() as f64);
+        }
+
+        for (client_type, events) in &self.pending_events {
+            self.metrics.set_gauge("pending_events", vec![("client_type", client_type.clone())],
+                                   events.len() as f64);
+        }
+
+        self.metrics.set_gauge("scheduled_events", Vec::new(), self.scheduled.len() as f64);
+        self.metrics.set_gauge("pending_requests", Vec::new(),
+                               self.pending_requests.len() as f64);
+        self.metrics.set_gauge("kafka_in_flight_events", Vec::new(),
+                               self.producer.in_flight_count() as f64);
+    }
+}
+
+impl Handler<GetMetrics> for Bus {
+    type Result = Result<String, ()>;
+
+    fn handle(&mut self, _: GetMetrics, _: &mut Context<Self>) -> Self::Result {
+        debug!("received 'get metrics' signal");
+        self.update_gauges();
+        Ok(self.metrics.render())
+    }
+}
@@ -1,17 +1,37 @@
 mod acknowledgement;
+mod admin;
+mod cancel;
+mod close;
 mod connect;
 mod disconnect;
+mod get_event;
+mod get_metrics;
+mod negative_acknowledgement;
 mod new_event;
+mod produce_result;
 mod propagate_event;
 mod query;
 mod register;
+mod register_schema;
+mod reply;
+mod request;
 mod send_to_client;
 
 pub use self::acknowledgement::Acknowledgement;
+pub use self::admin::{Admin, AdminCommand, AdminResponse};
+pub use self::cancel::Cancel;
+pub use self::close::Close;
 pub use self::connect::Connect;
 pub use self::disconnect::Disconnect;
+pub use self::get_event::GetEvent;
+pub use self::get_metrics::GetMetrics;
+pub use self::negative_acknowledgement::NegativeAcknowledgement;
 pub use self::new_event::NewEvent;
+pub use self::produce_result::ProduceResult;
 pub use self::propagate_event::{PropagateEvent};
 pub use self::query::Query;
 pub use self::register::Register;
+pub use self::register_schema::RegisterSchema;
+pub use self::reply::Reply;
+pub use self::request::Request;
 pub use self::send_to_client::SendToClient;
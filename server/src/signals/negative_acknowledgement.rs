@@ -0,0 +1,75 @@
+use std::net::SocketAddr;
+
+use actix::{Context, Handler, ResponseType};
+use common::schemas::{Ack, DeliveryMode};
+use failure::Error;
+
+use bus::Bus;
+use error::ErrorKind;
+
+/// The `NegativeAcknowledgement` message is sent to the Bus when a client was unable to process
+/// a message and would like it to be redelivered.
+#[derive(Clone)]
+pub struct NegativeAcknowledgement {
+    pub message: Ack,
+    pub addr: SocketAddr,
+}
+
+impl ResponseType for NegativeAcknowledgement {
+    type Item = ();
+    type Error = ();
+}
+
+impl Bus {
+    fn process_negative_acknowledgement(&mut self,
+                                        message: NegativeAcknowledgement) -> Result<(), Error> {
+        let event_id = message.message.event_id;
+        let (client_type, event) = match self.sessions.get_mut(&message.addr) {
+            Some(details) => {
+                details.lanes.complete(&event_id);
+                let event = match details.unacknowledged_events.remove(&event_id) {
+                    Some(event) => event,
+                    None => {
+                        warn!("attempt to reject unacknowledged event that does not exist: \
+                              event_id='{}'", event_id);
+                        return Ok(());
+                    },
+                };
+
+                let client_type = details.client_type.clone().ok_or(
+                    Error::from(ErrorKind::UnacknowledgedEventResendWithoutClientType))?;
+                (client_type, event)
+            },
+            None => return Err(Error::from(ErrorKind::SessionNotInHashMap)),
+        };
+
+        info!("redelivering rejected event: client='{}' client_type='{}' event_id='{}'",
+              message.addr, client_type, event_id);
+        self.flush_client(message.addr);
+        self.metrics.increment("redeliveries_total", vec![
+            ("client_type", client_type.clone()),
+            ("reason", String::from("nack")),
+        ], 1);
+
+        // Every instance of a fan-out client type has its own copy of the event, so it is only
+        // redelivered to the instance that rejected it.
+        if self.delivery_mode(&client_type) == DeliveryMode::Fanout {
+            self.propagate_event_to_client(&event, message.addr);
+        } else {
+            self.propagate_event_to_client_type(&event, client_type);
+        }
+        Ok(())
+    }
+}
+
+impl Handler<NegativeAcknowledgement> for Bus {
+    type Result = ();
+
+    fn handle(&mut self, message: NegativeAcknowledgement, _: &mut Context<Self>) {
+        let _log = self.acknowledgement_log_context(&message.addr, &message.message.event_id);
+        debug!("received 'negative acknowledgement' signal: client='{}'", message.addr);
+        if let Err(e) = self.process_negative_acknowledgement(message) {
+            error!("processing negative acknowledgement: error='{}'", e);
+        }
+    }
+}
@@ -1,12 +1,19 @@
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::time::Instant;
 
-use actix::{Address, Context, Handler, ResponseType};
-use chrono::Local;
-use common::hash_json;
+use actix::{Address, Arbiter, Context, Handler, ResponseType};
+use chrono::{DateTime, Local};
+use common::{LogContext, generate_event_id, hash_json};
 use common::schemas::{
     Consistency,
+    ConsistencyKey,
     ConsistencyValue,
     Event,
+    EventId,
+    Message,
+    NewEvent as NewEventSchema,
     NewEvents,
     Receipt,
     Receipts,
@@ -15,19 +22,39 @@ use couchbase::{Document, BinaryDocument};
 use failure::{Error, ResultExt};
 use futures::Future;
 use serde::Serialize;
-use serde_json::{from_str, to_string, to_string_pretty};
+use serde_json::{Value, from_str, to_string, to_string_pretty};
 
-use bus::Bus;
+use bus::{Bus, IdempotencyRecord};
+use consumer::Envelope;
 use error::ErrorKind;
+use rate_limit::TokenBucket;
 use session::Session;
-use signals::SendToClient;
+use signals::{ProduceResult, SendToClient};
 
-/// The `NewEvent` message is sent to the Bus when new events are sent from websockets.
+/// The prefix of the documents that idempotency records are persisted in, they are followed by
+/// a hash of the key so that the length of the document id is bounded.
+const IDEMPOTENCY_PREFIX: &str = "idempotency-";
+
+/// Couchbase treats expiries of more than this many seconds as a unix timestamp rather than an
+/// offset from now.
+const MAX_RELATIVE_EXPIRY_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// StoredPayload is the document that the data of an event is stored in when it is too large to
+/// store with the event. The data is nested so that it can't be mistaken for an event.
+#[derive(Deserialize, Serialize)]
+pub struct StoredPayload {
+    pub payload: Value,
+}
+
+/// The `NewEvent` message is sent to the Bus when new events or commands are sent from
+/// websockets.
 pub struct NewEvent {
-    pub message: String,
+    pub message: NewEvents,
     pub sender: (Address<Session>, SocketAddr),
     pub bus: Address<Bus>,
     pub session_id: usize,
+    /// This field contains the client type that the events are commands for, if they are.
+    pub target: Option<String>,
 }
 
 impl ResponseType for NewEvent {
@@ -36,17 +63,34 @@ impl ResponseType for NewEvent {
 }
 
 impl Bus {
-    fn send_to_kafka<T: Serialize>(&mut self, event: &T,
-                                   event_type: &String) -> Result<(), Error> {
-        let serialized = to_string(event).context(
-            ErrorKind::SerializeJsonForSending)?;
-        let pretty_serialized = to_string_pretty(event).context(
+    pub fn send_to_kafka(&mut self, event: &Event, event_type: &String) -> Result<(), Error> {
+        // The version of rdkafka in use doesn't support message headers, so they are carried in
+        // an envelope around the event.
+        let envelope = Envelope::new(event.clone());
+        let serialized = to_string(&envelope).context(
             ErrorKind::SerializeJsonForSending)?;
 
-        info!("sending event to kafka: key='{}' topic='{}' event=\n{}",
-              event_type, self.topic, pretty_serialized);
-        self.producer.send_copy::<String, String>(&self.topic, None, Some(&serialized),
-                                                  Some(event_type), None, 1000);
+        info!("sending event to kafka: key='{}' topic='{}'", event_type, self.topic);
+        trace!("sending event to kafka: envelope=\n{}", to_string_pretty(&envelope).context(
+            ErrorKind::SerializeJsonForSending)?);
+        let sent_at = Instant::now();
+        let delivery = self.producer.send_copy::<String, String>(&self.topic, None,
+                                                                 Some(&serialized),
+                                                                 Some(event_type), None, 1000);
+
+        // The latency of the produce is recorded by the bus once Kafka has acknowledged it.
+        if let Some(ref address) = self.address {
+            let address = address.clone();
+            let event_type = event_type.clone();
+            Arbiter::handle().spawn(delivery.then(move |result| {
+                address.send(ProduceResult {
+                    event_type: event_type,
+                    duration: sent_at.elapsed(),
+                    delivered: match result { Ok(Ok(_)) => true, _ => false },
+                });
+                Ok(())
+            }));
+        }
         Ok(())
     }
 
@@ -54,19 +98,19 @@ impl Bus {
                                           document_id: &str) -> Result<(), Error> {
         let serialized = to_string(event).context(
             ErrorKind::SerializeJsonForSending)?;
-        let pretty_serialized = to_string_pretty(event).context(
-            ErrorKind::SerializeJsonForSending)?;
 
         let document = BinaryDocument::create(document_id, None,
                                               Some(serialized.as_bytes().to_owned()), None);
 
-        info!("saving event in couchbase: event=\n{}", pretty_serialized);
+        info!("saving event in couchbase: document_id='{}'", document_id);
+        trace!("saving event in couchbase: event=\n{}", to_string_pretty(event).context(
+            ErrorKind::SerializeJsonForSending)?);
         self.event_bucket.upsert(document).wait()?;
 
         Ok(())
     }
 
-    fn persist_consistency_to_couchbase(&mut self) -> Result<(), Error> {
+    pub fn persist_consistency_to_couchbase(&mut self) -> Result<(), Error> {
         let map = self.consistency.clone();
         let serialized = to_string(&map).context(
             ErrorKind::SerializeHashMapForCouchbase)?;
@@ -80,103 +124,706 @@ impl Bus {
         Ok(())
     }
 
-    pub fn process_new_event(&mut self, message: NewEvent) -> Result<(), Error> {
-        let (session, addr) = message.sender;
+    /// Forget any idempotency keys that were accepted before the deduplication window.
+    fn expire_idempotency_keys(&mut self, now: i64) {
+        while let Some(&(accepted_at, _)) = self.idempotency_expiry.front() {
+            if now - accepted_at < self.dedup_window {
+                break;
+            }
 
-        let now_time = Local::now();
+            if let Some((_, key)) = self.idempotency_expiry.pop_front() {
+                debug!("expiring idempotency key: key='{}'", key);
+                self.idempotency_keys.remove(&key);
+            }
+        }
+    }
 
-        let mut receipt = Receipts {
-            receipts: Vec::new(),
-            message_type: "receipt".to_string(),
-            timestamp: now_time.to_rfc2822(),
-            sender: format!("{:?}", addr.clone()),
+    /// Find how many of a batch of events a session can publish now under the rate limits of the
+    /// session and its client type, and take the tokens for them. An atomic batch is allowed in
+    /// full or not at all. If any events are not allowed, the milliseconds until they could be is
+    /// also returned.
+    fn take_publish_tokens(&mut self, addr: &SocketAddr, count: usize,
+                           atomic: bool) -> (usize, u64) {
+        let now = Instant::now();
+        let mut allowed = count;
+
+        let client_type = self.sessions.get(addr).and_then(|details| details.client_type.clone());
+        if let Some(ref client_type) = client_type {
+            if let Some(limit) = self.rate_limits.client_types.get(client_type).cloned() {
+                let bucket = self.client_type_rate_limiters.entry(client_type.clone())
+                    .or_insert_with(|| TokenBucket::new(limit));
+                allowed = min(allowed, bucket.available(now));
+            }
+        }
+        if let Some(bucket) = self.sessions.get_mut(addr).and_then(|d| d.rate_limiter.as_mut()) {
+            allowed = min(allowed, bucket.available(now));
+        }
+
+        if atomic && allowed < count {
+            allowed = 0;
+        }
+
+        let mut retry_after = 0;
+        let limited = count - allowed;
+        if let Some(ref client_type) = client_type {
+            if let Some(bucket) = self.client_type_rate_limiters.get_mut(client_type) {
+                bucket.take(allowed);
+                if limited > 0 {
+                    retry_after = max(retry_after, bucket.retry_after_millis(limited));
+                }
+            }
+        }
+        if let Some(bucket) = self.sessions.get_mut(addr).and_then(|d| d.rate_limiter.as_mut()) {
+            bucket.take(allowed);
+            if limited > 0 {
+                retry_after = max(retry_after, bucket.retry_after_millis(limited));
+            }
+        }
+
+        if limited > 0 {
+            let client_type = client_type.unwrap_or_default();
+            warn!("rate limited events: client='{}' client_type='{}' events='{}' \
+                  retry_after_ms='{}'", addr, client_type, limited, retry_after);
+            self.metrics.increment("rate_limited_events_total",
+                                   vec![("client_type", client_type)], limited as u64);
+        }
+
+        (allowed, retry_after)
+    }
+
+    /// Count an event that a client published by its type and the status of its receipt.
+    fn count_event(&mut self, raw_event: &NewEventSchema, status: &str) {
+        // The event type is chosen by the client, so the types of invalid events aren't used as
+        // labels and the number of other types is bounded.
+        let event_type = if status == "invalid" {
+            String::from("invalid")
+        } else {
+            self.metrics.bounded_label("event_type", &raw_event.event_type)
         };
+        self.metrics.increment("events_total", vec![
+            ("event_type", event_type),
+            ("status", status.to_owned()),
+        ], 1);
+    }
 
-        let parsed: NewEvents = from_str(&message.message).context(
-            ErrorKind::ParseNewEventMessage)?;
-        info!("parsed new event message: message=\n{}",
-              to_string_pretty(&parsed).context(ErrorKind::SerializeJsonForSending)?);
-
-        for raw_event in parsed.events.iter() {
-            let key = raw_event.consistency.key.clone();
-
-            debug!("checking consistency: raw='{:?}'", raw_event.consistency.value);
-            let value = match raw_event.consistency.value.clone() {
-                ConsistencyValue::Explicit(v) => v,
-                ConsistencyValue::Implicit => {
-                    if let Some(&ConsistencyValue::Explicit(x)) = self.consistency.get(&key) {
-                        x + 1
-                    } else {
-                        0
-                    }
-                },
-            };
-            debug!("found consistency: value='{}'", value);
-            let value = ConsistencyValue::Explicit(value);
-
-            let consistency = Consistency { key: key, value: value.clone() };
-            let event = Event {
-                consistency: consistency,
-                correlation_id: raw_event.correlation_id,
-                data: raw_event.data.clone(),
-                event_type: raw_event.event_type.clone(),
-                message_type: None,
-                timestamp: now_time.to_rfc2822(),
-                // Store the timestamp in raw form too - easier to query.
-                timestamp_raw: Some(now_time.timestamp()),
-                sender: receipt.sender.clone(),
-                session_id: Some(message.session_id),
+    /// Find the idempotency record of an earlier event that this event is a duplicate of. Keys
+    /// that were accepted before the bus started are looked up in couchbase.
+    fn find_duplicate(&self, raw_event: &NewEventSchema) -> Option<IdempotencyRecord> {
+        let key = raw_event.idempotency_key.as_ref()?;
+        if let Some(record) = self.idempotency_keys.get(key) {
+            return Some(record.clone());
+        }
+
+        // Reading from couchbase blocks the bus, so it is only done while keys accepted by an
+        // earlier instance of the bus could still be within the window.
+        let now = Local::now().timestamp();
+        if now - self.created_at >= self.dedup_window {
+            return None;
+        }
+
+        // The document expires with the deduplication window, but not at exactly the same time.
+        self.load_idempotency_record(key)
+            .filter(|record| now - record.accepted_at < self.dedup_window)
+    }
+
+    fn record_idempotency_key(&mut self, raw_event: &NewEventSchema, event: &Event, now: i64) {
+        if let Some(ref idempotency_key) = raw_event.idempotency_key {
+            debug!("recording idempotency key: key='{}'", idempotency_key);
+            let record = IdempotencyRecord {
+                event_id: event.id.clone(),
+                consistency: event.consistency.clone(),
+                accepted_at: now,
             };
+            if let Err(e) = self.persist_idempotency_record(idempotency_key, &record) {
+                warn!("failed to save idempotency key to couchbase: key='{}' error='{}'",
+                      idempotency_key, e);
+            }
+
+            self.idempotency_keys.insert(idempotency_key.clone(), record);
+            self.idempotency_expiry.push_back((now, idempotency_key.clone()));
+        }
+    }
 
-            let key = raw_event.consistency.key.clone();
-            let success = if let Some(&ConsistencyValue::Explicit(x)) = self.consistency.get(&key) {
-                debug!("comparing consistency: expect='{:?}' found='{:?}'",
-                       value, ConsistencyValue::Explicit(x + 1));
-                value == ConsistencyValue::Explicit(x + 1)
+    /// Persist an idempotency record so that duplicates are still detected after a restart. The
+    /// document expires once the record falls outside of the deduplication window.
+    fn persist_idempotency_record(&mut self, key: &str,
+                                  record: &IdempotencyRecord) -> Result<(), Error> {
+        // Nothing is a duplicate without a window, and an expiry of zero would never expire.
+        if self.dedup_window <= 0 {
+            return Ok(());
+        }
+
+        let expiry = if self.dedup_window > MAX_RELATIVE_EXPIRY_SECS {
+            record.accepted_at + self.dedup_window
+        } else {
+            self.dedup_window
+        };
+
+        let serialized = to_string(record).context(ErrorKind::SerializeJsonForSending)?;
+        let document_id = idempotency_document_id(key)?;
+        let document = BinaryDocument::create(document_id.as_str(), None,
+                                              Some(serialized.as_bytes().to_owned()),
+                                              Some(expiry as u32));
+
+        debug!("persisting idempotency key to couchbase: document_id='{}'", document_id);
+        self.consistency_bucket.upsert(document).wait()?;
+        Ok(())
+    }
+
+    /// Load the idempotency record of a key from couchbase, if it has not expired.
+    fn load_idempotency_record(&self, key: &str) -> Option<IdempotencyRecord> {
+        let document_id = idempotency_document_id(key).ok()?;
+        let result = self.consistency_bucket.get::<BinaryDocument, _>(document_id.as_str()).wait();
+        let document = match result {
+            Ok(document) => document,
+            Err(e) => {
+                debug!("no idempotency key in couchbase: key='{}' error='{}'", key, e);
+                return None;
+            },
+        };
+
+        let record = document.content_as_str().ok()
+            .and_then(|content| content)
+            .and_then(|content| from_str(content).ok());
+        if record.is_some() {
+            info!("found idempotency key in couchbase: key='{}'", key);
+        }
+        record
+    }
+
+    /// Send an accepted event to Kafka, unless it should be delivered later in which case it is
+    /// scheduled instead. `client_type` is the client type of the session that published it.
+    fn dispatch_event(&mut self, event: &Event, client_type: Option<&String>,
+                      now: i64) -> Result<(), Error> {
+        let _log = LogContext::new()
+            .field("event_id", &event.id)
+            .field("correlation_id", event.correlation_id);
+        if event.deliver_at.map(|deliver_at| deliver_at > now).unwrap_or(false) {
+            return self.schedule_event(event.clone(), client_type);
+        }
+
+        info!("sending event to kafka: event_id='{}' correlation_id='{}' sequence_key='{}', \
+              sequence_value='{}'", event.id, event.correlation_id, event.consistency.key,
+              event.consistency.value);
+        self.send_to_kafka(event, &event.event_type)
+    }
+
+    /// Dispatch an event that has already been committed. It can't be rejected anymore, so a
+    /// failure is only logged.
+    fn dispatch_or_log(&mut self, event: &Event, client_type: Option<&String>, now: i64) {
+        if let Err(e) = self.dispatch_event(event, client_type, now) {
+            error!("failed to dispatch committed event: event_id='{}' error='{}'", event.id, e);
+        }
+    }
+
+    /// Remove a document from couchbase while undoing a partially committed batch.
+    fn remove_from_couchbase(&mut self, document_id: &str) {
+        warn!("removing document from couchbase: document_id='{}'", document_id);
+        if let Err(e) = self.event_bucket.remove(document_id).wait() {
+            error!("failed to remove document from couchbase: document_id='{}' error='{}'",
+                   document_id, e);
+        }
+    }
+
+    /// Store the data of an event in its own document if it is too large to keep with the event,
+    /// returning the event that should be persisted and propagated.
+    fn offload_payload(&mut self, event: &Event) -> Result<Event, Error> {
+        match event.payload_ref {
+            Some(ref payload_ref) => {
+                info!("storing payload out-of-line: event_id='{}' payload_ref='{}'",
+                      event.id, payload_ref);
+                self.persist_to_couchbase(&StoredPayload { payload: event.data.clone() },
+                                          payload_ref)?;
+                Ok(Event { data: Value::Null, ..event.clone() })
+            },
+            None => Ok(event.clone()),
+        }
+    }
+
+    /// Find the serialized size of the data of an event, in bytes.
+    fn data_size(raw_event: &NewEventSchema) -> Result<usize, Error> {
+        Ok(to_string(&raw_event.data).context(ErrorKind::SerializeJsonForSending)?.len())
+    }
+
+    /// Find the status of the receipt for an event that failed validation.
+    fn rejection_status(&self, raw_event: &NewEventSchema) -> &'static str {
+        let too_large = Self::data_size(raw_event)
+            .map(|size| self.size_limits.is_data_too_large(size))
+            .unwrap_or(false);
+        if too_large && !self.size_limits.offload_payloads { "too_large" } else { "invalid" }
+    }
+
+    /// Validate the data of an event against the schema registry and size limits, returning any
+    /// errors.
+    fn validate_event(&self, raw_event: &NewEventSchema) -> Vec<String> {
+        let mut errors = self.schemas.validate(&raw_event.event_type, raw_event.schema_version,
+                                               &raw_event.data);
+        if !self.size_limits.offload_payloads {
+            match Self::data_size(raw_event) {
+                Ok(size) if self.size_limits.is_data_too_large(size) => {
+                    errors.push(format!("data is {} bytes, the limit is {} bytes", size,
+                                        self.size_limits.max_data_bytes.unwrap_or_default()));
+                },
+                Ok(_) => {},
+                Err(e) => errors.push(format!("data could not be serialized: {}", e)),
+            }
+        }
+        if let Some(ref deliver_at) = raw_event.deliver_at {
+            if DateTime::parse_from_rfc3339(deliver_at).is_err() {
+                errors.push(format!("deliver_at is not an RFC 3339 timestamp: {}", deliver_at));
+            }
+        }
+
+        if !errors.is_empty() {
+            info!("received invalid event: event_type='{}' errors='{:?}'",
+                  raw_event.event_type, errors);
+        }
+        errors
+    }
+
+    /// Find the consistency value that the next event for a key must have. Values in `staged`
+    /// are used ahead of those in the bus so that events in an atomic batch can follow each other.
+    fn next_consistency(&self, staged: &HashMap<ConsistencyKey, ConsistencyValue>,
+                        key: &ConsistencyKey) -> u32 {
+        match staged.get(key).or_else(|| self.consistency.get(key)) {
+            Some(&ConsistencyValue::Explicit(x)) => x + 1,
+            _ => 0,
+        }
+    }
+
+    /// Check the expected versions of the other keys that an event depends on. These keys are
+    /// not advanced by the event.
+    fn check_expected_versions(&self, raw_event: &NewEventSchema,
+                               staged: &HashMap<ConsistencyKey, ConsistencyValue>) -> bool {
+        raw_event.expected_versions.iter().all(|expected| {
+            let next = self.next_consistency(staged, &expected.key);
+            debug!("checking expected version: key='{}' expect='{}' found='{}'",
+                   expected.key, expected.value, next);
+
+            match expected.value {
+                ConsistencyValue::Explicit(v) => v == next,
+                ConsistencyValue::Implicit => true,
+            }
+        })
+    }
+
+    /// Find the schema version of events of a type in their current shape, for events that don't
+    /// give one. This is the end of the upcaster chain if it is later than the latest schema, so
+    /// that new events are never upcast again when they are read.
+    fn current_schema_version(&self, event_type: &str) -> u32 {
+        let latest_schema = self.schemas.latest_version(event_type);
+        let upcaster_head = self.upcasters.head_version(event_type);
+        max(latest_schema, upcaster_head).unwrap_or(1)
+    }
+
+    /// Build the event that will be committed for a new event and check whether it has the
+    /// expected consistency value and versions.
+    fn prepare_event(&self, raw_event: &NewEventSchema,
+                     staged: &HashMap<ConsistencyKey, ConsistencyValue>,
+                     now_time: &DateTime<Local>, sender: &str, session_id: usize,
+                     target: Option<&String>) -> (Event, bool) {
+        let key = raw_event.consistency.key.clone();
+        let expected = self.next_consistency(staged, &key);
+
+        debug!("checking consistency: raw='{:?}'", raw_event.consistency.value);
+        let value = match raw_event.consistency.value {
+            ConsistencyValue::Explicit(v) => v,
+            ConsistencyValue::Implicit => expected,
+        };
+        debug!("comparing consistency: expect='{}' found='{}'", value, expected);
+
+        // Data that is too large is only accepted when it can be stored out-of-line, which
+        // happens once the event is committed.
+        let id = generate_event_id();
+        let too_large = Self::data_size(raw_event)
+            .map(|size| self.size_limits.is_data_too_large(size))
+            .unwrap_or(false);
+        let payload_ref = if too_large && self.size_limits.offload_payloads {
+            Some(format!("payload-{}", id))
+        } else {
+            None
+        };
+
+        let event = Event {
+            id: id,
+            causation_id: raw_event.causation_id.clone(),
+            consistency: Consistency { key: key, value: ConsistencyValue::Explicit(value) },
+            deliver_at: deliver_at(raw_event, now_time),
+            correlation_id: raw_event.correlation_id,
+            data: raw_event.data.clone(),
+            event_type: raw_event.event_type.clone(),
+            metadata: raw_event.metadata.clone(),
+            payload_ref: payload_ref,
+            schema_version: raw_event.schema_version
+                .unwrap_or_else(|| self.current_schema_version(&raw_event.event_type)),
+            timestamp: now_time.to_rfc2822(),
+            // Store the timestamp in raw form too - easier to query.
+            timestamp_raw: Some(now_time.timestamp()),
+            sender: sender.to_owned(),
+            session_id: Some(session_id),
+            target: target.cloned(),
+        };
+
+        let success = value == expected && self.check_expected_versions(raw_event, staged);
+        (event, success)
+    }
+
+    /// Store an event that has passed every check and send it on. The consistency value is only
+    /// advanced once the event is stored, so that a failed event can be retried as it was.
+    fn commit_event(&mut self, raw_event: &NewEventSchema, event: &Event,
+                    client_type: Option<&String>, now: i64) -> Result<(), Error> {
+        let stored = self.offload_payload(event)?;
+        info!("sending event to couchbase: event_id='{}'", event.id);
+        self.persist_to_couchbase(&stored, &stored.id)?;
+
+        self.consistency.insert(event.consistency.key.clone(), event.consistency.value.clone());
+        if let Err(e) = self.persist_consistency_to_couchbase() {
+            warn!("failed to save consistency to couchbase: error='{:?}'", e);
+        }
+        self.record_idempotency_key(raw_event, event, now);
+
+        self.dispatch_or_log(&stored, client_type, now);
+        Ok(())
+    }
+
+    /// Accept or reject each event in a batch independently.
+    fn process_events(&mut self, events: &[NewEventSchema], now_time: &DateTime<Local>,
+                      sender: &str, session_id: usize, target: Option<&String>,
+                      client_type: Option<&String>) -> Result<Vec<Receipt>, Error> {
+        let mut receipts = Vec::new();
+
+        for (position, raw_event) in events.iter().enumerate() {
+            let _log = LogContext::new().field("correlation_id", raw_event.correlation_id);
+            if let Some(original) = self.find_duplicate(raw_event) {
+                info!("received duplicate event: event_id='{}'", original.event_id);
+                receipts.push(build_receipt(raw_event, position, original.consistency,
+                                            Some(original.event_id), "duplicate")?);
+                continue;
+            }
+
+            let errors = self.validate_event(raw_event);
+            if !errors.is_empty() {
+                let status = self.rejection_status(raw_event);
+                let mut receipt = build_receipt(raw_event, position, raw_event.consistency.clone(),
+                                                None, status)?;
+                receipt.errors = errors;
+                receipts.push(receipt);
+                continue;
+            }
+
+            let staged = HashMap::new();
+            let (event, success) = self.prepare_event(raw_event, &staged, now_time, sender,
+                                                      session_id, target);
+            let _event_log = LogContext::new().field("event_id", &event.id);
+
+            let (status, event_id) = if !success {
+                ("inconsistent", None)
             } else {
-                debug!("comparing consistency: expect='{:?}' found='{:?}'",
-                       value, ConsistencyValue::Explicit(0));
-                value == ConsistencyValue::Explicit(0)
+                match self.commit_event(raw_event, &event, client_type, now_time.timestamp()) {
+                    Ok(()) => ("success", Some(event.id.clone())),
+                    Err(e) => {
+                        error!("failed to commit event: event_id='{}' error='{}'", event.id, e);
+                        ("failed", None)
+                    },
+                }
             };
 
-            let mut status = "inconsistent";
-            if success {
-                self.consistency.insert(key, value.clone());
-                if let Err(e) = self.persist_consistency_to_couchbase() {
-                    warn!("failed to save consistency to couchbase: error='{:?}'", e);
+            receipts.push(build_receipt(raw_event, position, event.consistency.clone(),
+                                        event_id, status)?);
+        }
+
+        Ok(receipts)
+    }
+
+    /// Accept every event in a batch or none of them. Consistency is checked for all events
+    /// before anything is committed.
+    fn process_atomic_events(&mut self, events: &[NewEventSchema], now_time: &DateTime<Local>,
+                             sender: &str, session_id: usize, target: Option<&String>,
+                             client_type: Option<&String>) -> Result<Vec<Receipt>, Error> {
+        let duplicates: Vec<Option<IdempotencyRecord>> = events.iter()
+            .map(|raw_event| self.find_duplicate(raw_event))
+            .collect();
+
+        // A retry of an atomic batch that was already accepted is a duplicate as a whole, but a
+        // batch that only repeats some earlier events can't be applied atomically.
+        if !events.is_empty() && duplicates.iter().all(Option::is_some) {
+            info!("received duplicate atomic batch: events='{}'", events.len());
+            let mut receipts = Vec::new();
+            for (position, (raw_event, original)) in events.iter().zip(duplicates).enumerate() {
+                if let Some(original) = original {
+                    receipts.push(build_receipt(raw_event, position, original.consistency,
+                                                Some(original.event_id), "duplicate")?);
+                }
+            }
+            return Ok(receipts);
+        }
+
+        // Events that repeat an idempotency key from earlier in the batch would both be accepted,
+        // since neither has been recorded yet, so the batch is invalid.
+        let mut keys = HashSet::new();
+        let errors: Vec<Vec<String>> = events.iter()
+            .map(|raw_event| {
+                let mut errors = self.validate_event(raw_event);
+                if let Some(ref key) = raw_event.idempotency_key {
+                    if !keys.insert(key.clone()) {
+                        errors.push(format!("idempotency key is repeated in the batch: {}", key));
+                    }
                 }
-                status = "success";
+                errors
+            })
+            .collect();
+        if !errors.iter().all(Vec::is_empty) {
+            info!("rejecting invalid atomic batch: events='{}'", events.len());
+            let mut receipts = Vec::new();
+            for (position, (raw_event, errors)) in events.iter().zip(errors).enumerate() {
+                let status = self.rejection_status(raw_event);
+                let mut receipt = build_receipt(raw_event, position, raw_event.consistency.clone(),
+                                                None, status)?;
+                receipt.errors = errors;
+                receipts.push(receipt);
+            }
+            return Ok(receipts);
+        }
+
+        let mut success = duplicates.iter().all(Option::is_none);
+        let mut staged = HashMap::new();
+        let mut prepared = Vec::new();
+        for raw_event in events {
+            let (event, consistent) = self.prepare_event(raw_event, &staged, now_time, sender,
+                                                         session_id, target);
+            staged.insert(event.consistency.key.clone(), event.consistency.value.clone());
+            success = success && consistent;
+            prepared.push(event);
+        }
+
+        let status = if success {
+            info!("committing atomic batch: events='{}'", prepared.len());
+            match self.commit_atomic_events(&prepared, staged, client_type,
+                                            now_time.timestamp()) {
+                Ok(()) => {
+                    for (raw_event, event) in events.iter().zip(prepared.iter()) {
+                        self.record_idempotency_key(raw_event, event, now_time.timestamp());
+                    }
+                    "success"
+                },
+                Err(e) => {
+                    error!("failed to commit atomic batch: events='{}' error='{}'",
+                           prepared.len(), e);
+                    "failed"
+                },
+            }
+        } else {
+            info!("rejecting atomic batch: events='{}'", prepared.len());
+            "inconsistent"
+        };
+
+        let mut receipts = Vec::new();
+        for (position, (raw_event, event)) in events.iter().zip(prepared).enumerate() {
+            let event_id = if status == "success" { Some(event.id) } else { None };
+            receipts.push(build_receipt(raw_event, position, event.consistency, event_id,
+                                        status)?);
+        }
+
+        Ok(receipts)
+    }
 
-                info!("sending event to kafka: sequence_key='{}', sequence_value='{}'",
-                      raw_event.consistency.key.clone(), value);
-                self.send_to_kafka(&event, &event.event_type)?;
+    /// Persist a batch of events that have passed validation. If any event can't be saved in
+    /// couchbase then those already saved are removed again before anything is sent to Kafka.
+    /// Only the write to couchbase is all or nothing, the events are then sent to Kafka without
+    /// waiting for it to acknowledge them.
+    fn commit_atomic_events(&mut self, events: &[Event],
+                            staged: HashMap<ConsistencyKey, ConsistencyValue>,
+                            client_type: Option<&String>, now: i64) -> Result<(), Error> {
+        let mut stored = Vec::new();
+        for (saved, event) in events.iter().enumerate() {
+            let _log = LogContext::new()
+                .field("event_id", &event.id)
+                .field("correlation_id", event.correlation_id);
+            info!("sending event to couchbase: event_id='{}'", event.id);
+            let result = match self.offload_payload(event) {
+                Ok(offloaded) => self.persist_to_couchbase(&offloaded, &offloaded.id)
+                    .map(|_| offloaded),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(offloaded) => stored.push(offloaded),
+                Err(e) => {
+                    for rollback in &events[..saved] {
+                        self.remove_from_couchbase(&rollback.id);
+                    }
+                    for rollback in &events[..saved + 1] {
+                        if let Some(ref payload_ref) = rollback.payload_ref {
+                            self.remove_from_couchbase(payload_ref);
+                        }
+                    }
+
+                    return Err(e);
+                },
+            }
+        }
+
+        self.consistency.extend(staged);
+        if let Err(e) = self.persist_consistency_to_couchbase() {
+            warn!("failed to save consistency to couchbase: error='{:?}'", e);
+        }
+
+        for event in &stored {
+            self.dispatch_or_log(event, client_type, now);
+        }
+
+        Ok(())
+    }
+
+    /// Build a receipt with the same status for every event of a batch that wasn't processed.
+    fn reject_batch(&mut self, events: &[NewEventSchema], status: &str,
+                    error: Option<String>) -> Result<Vec<Receipt>, Error> {
+        let mut receipts = Vec::new();
+        for (position, raw_event) in events.iter().enumerate() {
+            let mut receipt = build_receipt(raw_event, position, raw_event.consistency.clone(),
+                                            None, status)?;
+            receipt.errors.extend(error.clone());
+            self.count_event(raw_event, &receipt.status);
+            receipts.push(receipt);
+        }
+        Ok(receipts)
+    }
+
+    pub fn process_new_event(&mut self, message: NewEvent) -> Result<(), Error> {
+        let (session, addr) = message.sender;
+
+        let now_time = Local::now();
+        let sender = format!("{:?}", addr.clone());
+
+        let parsed = message.message;
+        trace!("parsed new event message: message=\n{}",
+               to_string_pretty(&parsed).context(ErrorKind::SerializeJsonForSending)?);
 
-                // Do a separate hash to include timestamp, sender etc to make the hash always
-                // unique.
-                let hash_all = hash_json(&event.clone())?;
-                info!("sending event to couchbase");
-                self.persist_to_couchbase(&event, &hash_all.to_string())?;
+        self.expire_idempotency_keys(now_time.timestamp());
+
+        let target = message.target.as_ref();
+        if let Some(client_type) = target {
+            info!("received command: client_type='{}'", client_type);
+        }
+
+        // Clients have been told to reconnect elsewhere, so they are told to publish there too.
+        if self.shutting_down {
+            info!("rejecting events while shutting down: client='{}' events='{}'",
+                  addr, parsed.events.len());
+            let receipts = self.reject_batch(&parsed.events, "shutting_down", None)?;
+            session.send(SendToClient(Message::Receipts(Receipts {
+                receipts: receipts,
+                timestamp: now_time.to_rfc2822(),
+                sender: sender,
+            })));
+            return Ok(());
+        }
+
+        if let Some(limit) = self.size_limits.max_batch_events {
+            if parsed.events.len() > limit {
+                warn!("rejecting batch that is too large: client='{}' events='{}' limit='{}'",
+                      addr, parsed.events.len(), limit);
+                let error = format!("batch has {} events, the limit is {} events",
+                                    parsed.events.len(), limit);
+                let receipts = self.reject_batch(&parsed.events, "too_large", Some(error))?;
+                session.send(SendToClient(Message::Receipts(Receipts {
+                    receipts: receipts,
+                    timestamp: now_time.to_rfc2822(),
+                    sender: sender,
+                })));
+                return Ok(());
             }
+        }
+
+        let (allowed, retry_after) = self.take_publish_tokens(&addr, parsed.events.len(),
+                                                              parsed.atomic);
+        let (accepted, limited) = parsed.events.split_at(allowed);
+
+        let client_type = self.sessions.get(&addr).and_then(|details| details.client_type.clone());
+        let processed = if accepted.is_empty() {
+            Ok(Vec::new())
+        } else if parsed.atomic {
+            self.process_atomic_events(accepted, &now_time, &sender, message.session_id, target,
+                                       client_type.as_ref())
+        } else {
+            self.process_events(accepted, &now_time, &sender, message.session_id, target,
+                                client_type.as_ref())
+        };
+        // The client is sent a receipt for every event even if the batch couldn't be processed,
+        // so that it isn't left waiting.
+        let mut receipts = match processed {
+            Ok(receipts) => receipts,
+            Err(e) => {
+                error!("failed to process new events: client='{}' error='{}'", addr, e);
+                let mut receipts = Vec::new();
+                for (position, raw_event) in accepted.iter().enumerate() {
+                    receipts.push(build_receipt(raw_event, position,
+                                                raw_event.consistency.clone(), None, "failed")?);
+                }
+                receipts
+            },
+        };
 
-            receipt.receipts.push(Receipt {
-                // We just care about verifying the integrity of the data,
-                // so the hash need only be done on this.
-                checksum: hash_json(&event.data.clone())?,
-                status: status.to_string()
-            });
+        for (offset, raw_event) in limited.iter().enumerate() {
+            let mut receipt = build_receipt(raw_event, allowed + offset,
+                                            raw_event.consistency.clone(), None, "rate_limited")?;
+            receipt.retry_after_ms = Some(retry_after);
+            receipts.push(receipt);
         }
 
+        for (raw_event, receipt) in parsed.events.iter().zip(&receipts) {
+            self.count_event(raw_event, &receipt.status);
+        }
+
+        let receipt = Receipts {
+            receipts: receipts,
+            timestamp: now_time.to_rfc2822(),
+            sender: sender,
+        };
+
         info!("sending receipt to the client");
-        session.send(SendToClient(receipt));
+        session.send(SendToClient(Message::Receipts(receipt)));
 
         Ok(())
     }
 }
 
+/// Find the raw timestamp that an event should be delivered at, from either `deliver_at` or
+/// `delay`. Events without either are delivered immediately.
+fn deliver_at(raw_event: &NewEventSchema, now_time: &DateTime<Local>) -> Option<i64> {
+    match raw_event.deliver_at {
+        Some(ref deliver_at) => DateTime::parse_from_rfc3339(deliver_at).ok()
+            .map(|deliver_at| deliver_at.timestamp()),
+        None => raw_event.delay.map(|delay| now_time.timestamp() + delay as i64),
+    }
+}
+
+/// Find the id of the document that the idempotency record of a key is persisted in.
+fn idempotency_document_id(key: &str) -> Result<String, Error> {
+    Ok(format!("{}{}", IDEMPOTENCY_PREFIX, hash_json(&key)?))
+}
+
+fn build_receipt(raw_event: &NewEventSchema, position: usize, consistency: Consistency,
+                 event_id: Option<EventId>, status: &str) -> Result<Receipt, Error> {
+    Ok(Receipt {
+        // We just care about verifying the integrity of the data,
+        // so the hash need only be done on this.
+        checksum: hash_json(&raw_event.data)?,
+        consistency: Some(consistency),
+        errors: Vec::new(),
+        event_id: event_id,
+        message_id: raw_event.message_id.clone(),
+        position: position,
+        retry_after_ms: None,
+        status: status.to_string(),
+    })
+}
+
 impl Handler<NewEvent> for Bus {
     type Result = ();
 
     fn handle(&mut self, message: NewEvent, _: &mut Context<Self>) {
+        let _log = self.session_log_context(&message.sender.1);
         if let Err(e) = self.process_new_event(message) {
             error!("processing new event: error='{}'", e);
         }
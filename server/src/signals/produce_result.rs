@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use actix::{Context, Handler, ResponseType};
+
+use bus::Bus;
+
+/// The `ProduceResult` message is sent to the Bus by itself once Kafka has acknowledged, or failed
+/// to acknowledge, an event that was sent to it.
+pub struct ProduceResult {
+    pub event_type: String,
+    /// This field contains how long it took from sending the event until it was acknowledged.
+    pub duration: Duration,
+    pub delivered: bool,
+}
+
+impl ResponseType for ProduceResult {
+    type Item = ();
+    type Error = ();
+}
+
+impl Handler<ProduceResult> for Bus {
+    type Result = ();
+
+    fn handle(&mut self, message: ProduceResult, _: &mut Context<Self>) {
+        let status = if message.delivered { "success" } else { "failed" };
+        if !message.delivered {
+            warn!("failed to deliver event to kafka: key='{}'", message.event_type);
+        }
+
+        let event_type = self.metrics.bounded_label("event_type", &message.event_type);
+        self.metrics.observe("kafka_produce_duration_seconds", vec![
+            ("event_type", event_type),
+            ("status", String::from(status)),
+        ], message.duration);
+    }
+}
@@ -3,10 +3,18 @@ use std::collections::hash_map::Entry;
 use std::net::SocketAddr;
 
 use actix::{Context, Handler, ResponseType};
-use common::schemas::Event;
+use common::LogContext;
+use common::schemas::{
+    ConsistencyKey,
+    DeliveryMode,
+    Event,
+    Message,
+    Predicate,
+    matches_all,
+};
 use failure::Error;
 
-use bus::{Bus, SessionDetails, RegisteredTypes};
+use bus::{Bus, RegisteredTypes};
 use error::ErrorKind;
 use signals::SendToClient;
 
@@ -24,13 +32,15 @@ impl ResponseType for PropagateEvent {
 }
 
 enum ShouldSend {
-    Yes(SocketAddr, SessionDetails),
+    Yes(SocketAddr),
     No,
 }
 
-fn should_send_to_client_type(registered_types: &RegisteredTypes, event_type: &str,
-                              client_type: &Option<String>) -> bool {
-    let is_registered = match *registered_types {
+fn should_send_to_client_type(registered_types: &RegisteredTypes, predicates: &[Predicate],
+                              event: &Event, client_type: &Option<String>) -> bool {
+    let event_type = &event.event_type;
+    // Commands are sent to their target client type whatever event types it registered for.
+    let is_registered = event.target.is_some() || match *registered_types {
         RegisteredTypes::All => true,
         RegisteredTypes::Some(ref types) => {
             if types.contains(&event_type.to_owned()) {
@@ -41,17 +51,24 @@ fn should_send_to_client_type(registered_types: &RegisteredTypes, event_type: &s
         },
     };
     let has_client_type = client_type.is_some();
-    is_registered && has_client_type
+    // Events that don't match the predicates are never sent, so they count as acknowledged.
+    let is_matched = event.target.is_some() || matches_all(predicates, &event.data);
+    is_registered && is_matched && has_client_type
 }
 
 impl Bus {
-    fn next_client_for_sending(&mut self, event: Event,
-                               client_type: &String) -> Result<ShouldSend, Error>
-    {
-        let sticky_key = (client_type.clone(), event.consistency.key.clone());
-        let socket = if let Some(socket) = self.sticky_consistency.get(&sticky_key) {
-            debug!("found sticky client for: key='{}'", event.consistency.key);
-            *socket
+    /// Pick the instance of a client type that should receive a message. If a consistency key
+    /// is given then the instance that has previously received that key is preferred and will
+    /// continue to receive it in future, otherwise instances are picked in turn.
+    pub fn select_client(&mut self, client_type: &String,
+                         key: Option<&ConsistencyKey>) -> Result<SocketAddr, Error> {
+        let sticky_key = key.map(|key| (client_type.clone(), key.clone()));
+        let sticky_socket = sticky_key.as_ref()
+            .and_then(|sticky_key| self.sticky_consistency.get(sticky_key))
+            .cloned();
+        let socket = if let Some(socket) = sticky_socket {
+            debug!("found sticky client for: key='{:?}'", key);
+            socket
         } else {
             debug!("finding non-sticky client for: client_type='{}'", client_type);
             match self.round_robin_state.get_mut(client_type) {
@@ -69,26 +86,38 @@ impl Bus {
         };
 
         if let Some(details) = self.sessions.get_mut(&socket) {
-            // Ensure that this client always receives this consistency key in future.
-            self.sticky_consistency.insert(sticky_key.clone(), socket);
-            details.consistency_keys.insert(sticky_key);
+            if let Some(sticky_key) = sticky_key {
+                // Ensure that this client always receives this consistency key in future.
+                self.sticky_consistency.insert(sticky_key.clone(), socket);
+                details.consistency_keys.insert(sticky_key);
+            }
+            Ok(socket)
+        } else {
+            Err(Error::from(ErrorKind::SessionNotInHashMap))
+        }
+    }
 
+    fn next_client_for_sending(&mut self, event: Event,
+                               client_type: &String) -> Result<ShouldSend, Error>
+    {
+        let socket = self.select_client(client_type, Some(&event.consistency.key))?;
+
+        if let Some(details) = self.sessions.get_mut(&socket) {
             // We need to check whether we should send to this client. In theory, this could
             // cause a certain client type to miss an event entirely if this were to return
             // false. However, given that all instances of a client type should be consistent
             // in which event types they are interested in, in practice this shouldn't cause
             // an issue.
-            if should_send_to_client_type(&details.registered_types, &event.event_type,
+            if should_send_to_client_type(&details.registered_types, &details.predicates, &event,
                                           &details.client_type) {
                 info!("sending 'send to client' signal: client='{}'", socket);
                 // Keep track of this event as unacknowledged.
-                let mut expected_ack_event = event.clone();
-                expected_ack_event.message_type = Some(String::from("ack"));
-                details.unacknowledged_events.insert(expected_ack_event);
+                details.unacknowledged_events.insert(event.id.clone(), event);
 
-                Ok(ShouldSend::Yes(socket.clone(), details.clone()))
+                Ok(ShouldSend::Yes(socket.clone()))
             } else {
-                info!("not sending 'send to client' signal: client='{}'", socket);
+                info!("not sending 'send to client' signal, acknowledging on behalf of client: \
+                      client='{}' event_id='{}'", socket, event.id);
                 Ok(ShouldSend::No)
             }
         } else {
@@ -96,37 +125,146 @@ impl Bus {
         }
     }
 
+    /// Find how events are delivered to the instances of a client type.
+    pub fn delivery_mode(&self, client_type: &str) -> DeliveryMode {
+        self.delivery_modes.get(client_type).cloned().unwrap_or_default()
+    }
+
+    /// Queue an event for a client in the lane for its priority and send whatever the client
+    /// has the budget for.
+    fn send_event_to_client(&mut self, socket: SocketAddr, event: Event) {
+        let priority = self.lanes.priority(&event.event_type);
+        if let Some(details) = self.sessions.get_mut(&socket) {
+            debug!("queueing event for client: client='{}' event_id='{}' priority='{}'",
+                   socket, event.id, priority);
+            details.lanes.push(priority, event);
+        }
+
+        self.flush_client(socket);
+    }
+
+    /// Send the queued events that a client has the budget for, this should be called whenever
+    /// a client acknowledges or rejects an event.
+    pub fn flush_client(&mut self, socket: SocketAddr) {
+        if let Some(details) = self.sessions.get_mut(&socket) {
+            for event in details.lanes.drain_ready(&self.lanes) {
+                let _log = LogContext::new()
+                    .field("session", socket)
+                    .optional_field("client_type", details.client_type.as_ref())
+                    .field("event_id", &event.id)
+                    .field("correlation_id", event.correlation_id);
+                info!("sending 'send to client' signal: client='{}' event_id='{}'",
+                      socket, event.id);
+                details.address.send(SendToClient(Message::Event(event)));
+            }
+        }
+    }
+
+    /// Send an event to a single client, if it is interested in the event.
+    pub fn propagate_event_to_client(&mut self, event: &Event, socket: SocketAddr) {
+        let _log = LogContext::new().field("session", socket);
+        let should_send = match self.sessions.get_mut(&socket) {
+            Some(details) => {
+                let should_send = should_send_to_client_type(
+                    &details.registered_types, &details.predicates, event, &details.client_type);
+                if should_send {
+                    details.unacknowledged_events.insert(event.id.clone(), event.clone());
+                } else {
+                    info!("not sending 'send to client' signal, acknowledging on behalf of \
+                          client: client='{}' event_id='{}'", socket, event.id);
+                }
+                should_send
+            },
+            None => {
+                error!("client is not present in sessions. this is a bug.");
+                false
+            },
+        };
+
+        if should_send {
+            self.send_event_to_client(socket, event.clone());
+        }
+    }
+
+    /// Send an event to every connected instance of a fan-out client type. Each instance tracks
+    /// its own acknowledgement of the event.
+    fn propagate_event_to_all_instances(&mut self, event: &Event, client_type: String) {
+        let sockets: Vec<SocketAddr> = self.round_robin_state.get(&client_type)
+            .map(|queue| queue.iter().cloned().collect())
+            .unwrap_or_default();
+
+        if sockets.is_empty() {
+            warn!("no clients of fan-out client type, saving for resend at later time: \
+                  client_type='{}'", client_type);
+            self.save_pending_event(event, client_type);
+            return;
+        }
+
+        for socket in sockets {
+            self.propagate_event_to_client(event, socket);
+        }
+    }
+
+    pub fn save_pending_event(&mut self, event: &Event, client_type: String) {
+        match self.pending_events.entry(client_type.clone()) {
+            Entry::Occupied(mut entry) => {
+                debug!("adding another pending event for client type: client_type='{}'",
+                       client_type);
+                let mut existing_events = { entry.get().clone() };
+                existing_events.push(event.clone());
+                entry.insert(existing_events);
+            },
+            Entry::Vacant(entry) => {
+                debug!("adding first pending event for client type: client_type='{}'",
+                       client_type);
+                entry.insert(vec![event.clone()]);
+            },
+        }
+    }
+
     pub fn propagate_event_to_client_type(&mut self, event: &Event, client_type: String) {
+        let _log = LogContext::new().field("client_type", &client_type);
+        if self.delivery_mode(&client_type) == DeliveryMode::Fanout {
+            info!("sending to every client of client type: client_type='{}'", client_type);
+            self.propagate_event_to_all_instances(event, client_type);
+            return;
+        }
+
         // For each client type, we take the next available round robin selected client.
         match self.next_client_for_sending(event.clone(), &client_type) {
-            Ok(ShouldSend::Yes(socket, details)) => {
+            Ok(ShouldSend::Yes(socket)) => {
                 info!("client selection: client='{}'", socket);
-                details.address.send(SendToClient(event.clone()));
+                self.send_event_to_client(socket, event.clone());
             },
             // If we aren't registered for this event, this client type will never be registered,
             // don't mark as pending.
             Ok(ShouldSend::No) => {},
             Err(_) => {
                 warn!("round robin selection failed, saving for resend at later time");
-                match self.pending_events.entry(client_type.clone()) {
-                    Entry::Occupied(mut entry) => {
-                        debug!("adding another pending event for client type: client_type='{}'",
-                               client_type);
-                        let mut existing_events = { entry.get().clone() };
-                        existing_events.push(event.clone());
-                        entry.insert(existing_events);
-                    },
-                    Entry::Vacant(entry) => {
-                        debug!("adding first pending event for client type: client_type='{}'",
-                               client_type);
-                        entry.insert(vec![event.clone()]);
-                    },
-                }
+                self.save_pending_event(event, client_type);
             },
         };
     }
 
+    /// Save an event as pending for every client type that it would be sent to, so that it is
+    /// persisted rather than sent.
+    fn defer_event(&mut self, event: Event) {
+        let client_types = match event.target {
+            Some(ref client_type) => vec![client_type.clone()],
+            None => self.round_robin_state.keys().cloned().collect(),
+        };
+        for client_type in client_types {
+            self.save_pending_event(&event, client_type);
+        }
+    }
+
     pub fn propagate_event(&mut self, event: Event) {
+        if let Some(client_type) = event.target.clone() {
+            info!("sending command to client type: client_type='{}'", client_type);
+            self.propagate_event_to_client_type(&event, client_type);
+            return;
+        }
+
         let types = self.round_robin_state.keys().cloned().collect::<Vec<_>>();
         debug!("checking client types: client_types='{:?}'", types);
         for client_type in types {
@@ -140,7 +278,27 @@ impl Handler<PropagateEvent> for Bus {
     type Result = ();
 
     fn handle(&mut self, message: PropagateEvent, _: &mut Context<Self>) {
+        let _log = LogContext::new()
+            .field("event_id", &message.event.id)
+            .field("correlation_id", message.event.correlation_id);
         debug!("received propagate event signal");
-        self.propagate_event(message.event);
+        let event = match self.load_payload(message.event) {
+            Ok(event) => event,
+            Err(e) => {
+                error!("failed to load out-of-line payload, not propagating event: error='{}'",
+                       e);
+                return;
+            },
+        };
+        let event = self.upcasters.upcast(event);
+
+        // Sessions have been told to reconnect elsewhere, so events that arrive now are kept for
+        // the next instance of the bus instead of being sent to them.
+        if self.shutting_down {
+            info!("deferring event while shutting down: event_id='{}'", event.id);
+            self.defer_event(event);
+            return;
+        }
+        self.propagate_event(event);
     }
 }
@@ -1,10 +1,13 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+
 use actix::{Address, Context, Handler, ResponseType};
-use chrono::DateTime;
-use common::schemas::{Event, Rebuild, Query as QuerySchema};
+use chrono::{DateTime, Local};
+use common::schemas::{Event, Message, Rebuild, Query as QuerySchema};
 use couchbase::{N1qlResult};
 use failure::{Error, Fail, ResultExt};
 use futures::{Stream};
-use serde_json::{from_str, to_string_pretty};
+use serde_json::{from_str, to_string, to_string_pretty};
 
 use bus::Bus;
 use error::ErrorKind;
@@ -13,8 +16,9 @@ use signals::SendToClient;
 
 /// The `Query` message is sent to the Bus when query requests are sent from websockets.
 pub struct Query {
-    pub message: String,
+    pub message: QuerySchema,
     pub sender: Address<Session>,
+    pub addr: SocketAddr,
     pub bus: Address<Bus>,
 }
 
@@ -30,11 +34,8 @@ pub struct CouchbaseStoredEvent {
 
 impl Bus {
     pub fn process_query_message(&mut self, message: Query) -> Result<(), Error> {
-        // parse the JSON message
-        let parsed: QuerySchema = from_str(&message.message).context(
-            ErrorKind::ParseQueryMessage)?;
-
-        debug!("parsed query event message: message=\n{}",
+        let parsed = message.message;
+        trace!("parsed query event message: message=\n{}",
               to_string_pretty(&parsed).context(ErrorKind::SerializeJsonForSending)?);
 
         let query_timestamp = if parsed.since.to_uppercase() == "*" {
@@ -49,21 +50,33 @@ impl Bus {
         let event_types: Vec<String> = parsed.event_types.iter()
             .map(|et| { "\"".to_string() + &et + "\"" })
             .collect();
+        // Commands are only part of the history of their target client type. The client type is
+        // serialized as a JSON string so that it is quoted and escaped.
+        let client_type = self.sessions.get(&message.addr)
+            .and_then(|details| details.client_type.as_ref())
+            .map(to_string);
+        let target = match client_type {
+            Some(client_type) => format!("(target IS NOT VALUED OR target = {})",
+                                         client_type.context(ErrorKind::SerializeJsonForSending)?),
+            None => String::from("target IS NOT VALUED"),
+        };
+
+        // Scheduled events are only returned once they are due.
         let query = format!(r#"
                                 SELECT * FROM events
                                 WHERE event_type IN [{}] AND timestamp_raw > {}
+                                AND (deliver_at IS NOT VALUED OR deliver_at <= {})
+                                AND {}
                                 ORDER BY timestamp_raw ASC
                             "#,
-                            event_types.join(", "), query_timestamp);
+                            event_types.join(", "), query_timestamp, Local::now().timestamp(),
+                            target);
         debug!("executing query: query=\n{}", query);
 
         let client_session = message.sender;
         let result_iter = self.event_bucket.query_n1ql(query).wait();
 
-        let mut rebuild = Rebuild {
-            message_type: String::from("rebuild"),
-            events: Vec::new(),
-        };
+        let mut events = Vec::new();
         for row in result_iter {
             match row {
                 Ok(N1qlResult::Meta(meta)) => {
@@ -75,16 +88,23 @@ impl Bus {
 
                     let parsed_row: CouchbaseStoredEvent = from_str(&row.as_ref()).context(
                         ErrorKind::CouchbaseDeserialize)?;
-                    let mut event = parsed_row.events.clone();
-                    event.message_type = Some(String::from("rebuild"));
-                    rebuild.events.push(event);
+                    events.push(parsed_row.events);
                 },
                 Err(e) => return Err(Error::from(e.context(
                             ErrorKind::CouchbaseFailedGetQueryResult))),
             }
         }
 
-        client_session.send(SendToClient(rebuild));
+        let mut rebuild = Rebuild {
+            events: Vec::new(),
+            message_id: parsed.message_id,
+        };
+        for event in events {
+            let event = self.load_payload(event)?;
+            rebuild.events.push(self.upcasters.upcast(event));
+        }
+
+        client_session.send(SendToClient(Message::Rebuild(rebuild)));
         Ok(())
     }
 }
@@ -93,8 +113,17 @@ impl Handler<Query> for Bus {
     type Result = ();
 
     fn handle(&mut self, message: Query, _: &mut Context<Self>) {
-        if let Err(e) = self.process_query_message(message) {
-            error!("processing query message: error='{}'", e);
-        }
+        let _log = self.session_log_context(&message.addr);
+        let started = Instant::now();
+        let status = match self.process_query_message(message) {
+            Ok(_) => "success",
+            Err(e) => {
+                error!("processing query message: error='{}'", e);
+                "failed"
+            },
+        };
+
+        self.metrics.observe("query_duration_seconds", vec![("status", String::from(status))],
+                             started.elapsed());
     }
 }
@@ -2,11 +2,11 @@ use std::collections::VecDeque;
 use std::collections::hash_map::Entry;
 use std::net::SocketAddr;
 
-use actix::{Address, Context, Handler, ResponseType};
+use actix::{Address, AsyncContext, Context, Handler, ResponseType};
 use common::VecDequeExt;
-use common::schemas::{Register as RegisterSchema, Registration};
+use common::schemas::{DeliveryMode, Message, Register as RegisterSchema, Registration};
 use failure::{Error, ResultExt};
-use serde_json::{from_str, to_string_pretty};
+use serde_json::to_string_pretty;
 
 use bus::{Bus, RegisteredTypes};
 use error::ErrorKind;
@@ -16,7 +16,7 @@ use signals::SendToClient;
 /// The `Register` message is sent to the Bus when a client wants to provide more information about
 /// itself or limit event types it can receive.
 pub struct Register {
-    pub message: String,
+    pub message: RegisterSchema,
     pub bus: Address<Bus>,
     pub sender: (Address<Session>, SocketAddr),
 }
@@ -83,6 +83,10 @@ impl Bus {
 
     fn update_sessions_from_registration(&mut self, socket: SocketAddr,
                                          parsed: RegisterSchema) -> Result<(), Error> {
+        info!("updated delivery mode for client type: client_type='{}' mode='{:?}'",
+              parsed.client_type, parsed.delivery_mode);
+        self.delivery_modes.insert(parsed.client_type.clone(), parsed.delivery_mode);
+
         match self.sessions.get_mut(&socket) {
             Some(details) => {
                 if parsed.event_types.len() == 1 && parsed.event_types[0] == "*" {
@@ -94,6 +98,10 @@ impl Bus {
                     details.registered_types = RegisteredTypes::Some(parsed.event_types.clone());
                 }
 
+                info!("updated predicates for client: client='{}' predicates='{:?}'",
+                      socket, parsed.predicates);
+                details.predicates = parsed.predicates.clone();
+
                 Ok(())
             },
             None => {
@@ -103,6 +111,60 @@ impl Bus {
         }
     }
 
+    /// Find the delivery mode of the other sessions of a client type, if it is not the one that
+    /// a registration asks for. Sessions of a client type must all use the same delivery mode.
+    fn conflicting_delivery_mode(&self, socket: &SocketAddr,
+                                 parsed: &RegisterSchema) -> Option<DeliveryMode> {
+        let has_other_sessions = self.round_robin_state.get(&parsed.client_type)
+            .map_or(false, |queue| queue.iter().any(|other| other != socket));
+        if !has_other_sessions {
+            return None;
+        }
+
+        match self.delivery_modes.get(&parsed.client_type) {
+            Some(mode) if *mode != parsed.delivery_mode => Some(*mode),
+            _ => None,
+        }
+    }
+
+    /// Send the pending events of a fan-out client type to an instance that has just registered.
+    /// Each instance gets its own copy, so the events are kept for the instances that register
+    /// shortly after the first, both in memory and in the persisted pending events.
+    fn replay_events_for_fanout_instance(&mut self, socket: SocketAddr, client_type: String,
+                                         ctx: &mut Context<Self>) {
+        let pending = self.pending_events.remove(&client_type).unwrap_or_default();
+        if !pending.is_empty() {
+            {
+                let replay = self.fanout_replay.entry(client_type.clone())
+                    .or_insert_with(Vec::new);
+                if replay.is_empty() {
+                    let client_type = client_type.clone();
+                    ctx.run_later(self.fanout_replay_window, move |bus, _| {
+                        debug!("no longer replaying pending events: client_type='{}'",
+                               client_type);
+                        bus.fanout_replay.remove(&client_type);
+                        if let Err(e) = bus.persist_pending_to_couchbase() {
+                            warn!("failed to update pending events in couchbase: error='{}'", e);
+                        }
+                    });
+                }
+                replay.extend(pending);
+            }
+
+            // The events stay in the persisted document until the replay window has passed.
+            if let Err(e) = self.persist_pending_to_couchbase() {
+                warn!("failed to update pending events in couchbase: error='{}'", e);
+            }
+        }
+
+        let events = self.fanout_replay.get(&client_type).cloned().unwrap_or_default();
+        info!("replaying pending events to fan-out client: client='{}' client_type='{}' \
+              events='{}'", socket, client_type, events.len());
+        for event in events {
+            self.propagate_event_to_client(&event, socket);
+        }
+    }
+
     pub fn resend_events_for_client_type(&mut self, client_type: String) -> Result<(), Error> {
         debug!("checking for events pending propagation for this client type");
         let pending_events = match self.pending_events.entry(client_type.clone()) {
@@ -121,19 +183,41 @@ impl Bus {
         };
 
         debug!("client type in pending events - resending any pending events");
+        let requeued = !pending_events.is_empty();
         for event in pending_events {
             self.propagate_event_to_client_type(&event, client_type.clone());
         }
+
+        // The persisted pending events could have been loaded at startup, they are only removed
+        // from couchbase now that they have been requeued.
+        if requeued {
+            if let Err(e) = self.persist_pending_to_couchbase() {
+                warn!("failed to update pending events in couchbase: error='{}'", e);
+            }
+        }
         Ok(())
     }
 
-    pub fn register(&mut self, message: Register) -> Result<(), Error> {
+    pub fn register(&mut self, message: Register, ctx: &mut Context<Self>) -> Result<(), Error> {
         let (addr, socket) = message.sender;
 
-        let parsed: RegisterSchema = from_str(&message.message).context(
-            ErrorKind::ParseNewEventMessage)?;
-        info!("parsed register message: message=\n{}",
-              to_string_pretty(&parsed).context(ErrorKind::SerializeJsonForSending)?);
+        let parsed = message.message;
+        trace!("parsed register message: message=\n{}",
+               to_string_pretty(&parsed).context(ErrorKind::SerializeJsonForSending)?);
+
+        if let Some(existing) = self.conflicting_delivery_mode(&socket, &parsed) {
+            warn!("refusing registration with conflicting delivery mode: client='{}' \
+                  client_type='{}' mode='{:?}' existing='{:?}'",
+                  socket, parsed.client_type, parsed.delivery_mode, existing);
+            addr.send(SendToClient(Message::Registration(Registration {
+                client_type: parsed.client_type,
+                event_types: parsed.event_types,
+                error: Some(format!("other sessions of the client type use the {:?} delivery \
+                                     mode", existing)),
+                message_id: parsed.message_id,
+            })));
+            return Ok(());
+        }
 
         self.update_sessions_from_registration(socket, parsed.clone())?;
         self.update_round_robin_state_from_registration(socket, parsed.clone())?;
@@ -142,16 +226,21 @@ impl Bus {
         // if an event is in our global resend list then that means there were no clients
         // connected when the service initially went down and so this is the first client of that
         // type to come back.
-        self.resend_events_for_client_type(parsed.client_type.clone())?;
+        if parsed.delivery_mode == DeliveryMode::Fanout {
+            self.replay_events_for_fanout_instance(socket, parsed.client_type.clone(), ctx);
+        } else {
+            self.resend_events_for_client_type(parsed.client_type.clone())?;
+        }
 
         let response = Registration {
             client_type: parsed.client_type.clone(),
             event_types: parsed.event_types.clone(),
-            message_type: "registration".to_string(),
+            error: None,
+            message_id: parsed.message_id.clone(),
         };
 
         info!("sending receipt to the client");
-        addr.send(SendToClient(response));
+        addr.send(SendToClient(Message::Registration(response)));
         Ok(())
     }
 }
@@ -160,8 +249,10 @@ impl Bus {
 impl Handler<Register> for Bus {
     type Result = ();
 
-    fn handle(&mut self, message: Register, _: &mut Context<Self>) {
-        if let Err(e) = self.register(message) {
+    fn handle(&mut self, message: Register, ctx: &mut Context<Self>) {
+        let _log = self.session_log_context(&message.sender.1)
+            .field("client_type", &message.message.client_type);
+        if let Err(e) = self.register(message, ctx) {
             error!("processing new event: error='{}'", e);
         }
     }
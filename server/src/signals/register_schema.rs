@@ -0,0 +1,43 @@
+use actix::{Address, Context, Handler, ResponseType};
+use common::schemas::{Message, RegisterSchema as RegisterSchemaSchema, SchemaRegistration};
+
+use bus::Bus;
+use session::Session;
+use signals::SendToClient;
+
+/// The `RegisterSchema` message is sent to the Bus when a client wants to add a schema that new
+/// events are validated against. Schemas registered this way are not persisted and must be
+/// registered again after a restart, use a schema directory for schemas that should always be
+/// present.
+pub struct RegisterSchema {
+    pub message: RegisterSchemaSchema,
+    pub sender: Address<Session>,
+}
+
+impl ResponseType for RegisterSchema {
+    type Item = ();
+    type Error = ();
+}
+
+impl Handler<RegisterSchema> for Bus {
+    type Result = ();
+
+    fn handle(&mut self, message: RegisterSchema, _: &mut Context<Self>) {
+        let schema = message.message;
+        info!("registering schema: event_type='{}' version='{}'",
+              schema.event_type, schema.version);
+
+        let errors = match self.schemas.register(&schema.event_type, schema.version,
+                                                 schema.schema) {
+            Ok(()) => Vec::new(),
+            Err(error) => vec![error],
+        };
+
+        message.sender.send(SendToClient(Message::SchemaRegistration(SchemaRegistration {
+            event_type: schema.event_type,
+            version: schema.version,
+            errors: errors,
+            message_id: schema.message_id,
+        })));
+    }
+}
@@ -0,0 +1,55 @@
+use std::net::SocketAddr;
+
+use actix::{Context, Handler, ResponseType};
+use common::schemas::Reply as ReplySchema;
+
+use bus::Bus;
+
+/// The `Reply` message is sent to the Bus when a client replies to a request that was forwarded
+/// to it.
+pub struct Reply {
+    pub message: ReplySchema,
+    pub addr: SocketAddr,
+}
+
+impl ResponseType for Reply {
+    type Item = ();
+    type Error = ();
+}
+
+impl Handler<Reply> for Bus {
+    type Result = ();
+
+    fn handle(&mut self, message: Reply, _: &mut Context<Self>) {
+        let _log = self.session_log_context(&message.addr);
+        let reply = message.message;
+        let responder = match self.pending_requests.get(&reply.request_id) {
+            Some(pending) => pending.responder,
+            None => {
+                warn!("reply did not match a pending request, it may have timed out: \
+                      client='{}' request_id='{}'", message.addr, reply.request_id);
+                return;
+            },
+        };
+
+        // Only the client that the request was forwarded to can answer it, the request stays
+        // pending until it does or the request times out.
+        if responder != message.addr {
+            warn!("dropping reply from a different client than the request was sent to: \
+                  client='{}' responder='{}' request_id='{}'",
+                  message.addr, responder, reply.request_id);
+            return;
+        }
+
+        let pending = match self.pending_requests.remove(&reply.request_id) {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        info!("routing reply: request_id='{}' origin='{}'", reply.request_id, pending.origin);
+        self.send_reply(&pending.origin, ReplySchema {
+            request_id: pending.request_id,
+            ..reply
+        });
+    }
+}
@@ -0,0 +1,111 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use actix::{AsyncContext, Context, Handler, ResponseType};
+use common::generate_event_id;
+use common::schemas::{Message, Reply, Request as RequestSchema};
+use serde_json::Value;
+
+use bus::{Bus, PendingRequest};
+use signals::SendToClient;
+
+/// The `Request` message is sent to the Bus when a client wants to ask a question of another
+/// client type.
+pub struct Request {
+    pub message: RequestSchema,
+    pub addr: SocketAddr,
+}
+
+impl ResponseType for Request {
+    type Item = ();
+    type Error = ();
+}
+
+impl Bus {
+    /// Send a reply to a client, if it is still connected.
+    pub fn send_reply(&self, addr: &SocketAddr, reply: Reply) {
+        match self.sessions.get(addr) {
+            Some(details) => {
+                details.address.send(SendToClient(Message::Reply(reply)));
+            },
+            None => warn!("client for reply is no longer connected: client='{}' request_id='{}'",
+                          addr, reply.request_id),
+        }
+    }
+
+    /// Reply with an error if a request has not been replied to by now.
+    fn expire_request(&mut self, request_id: &str) {
+        if let Some(pending) = self.pending_requests.remove(request_id) {
+            warn!("request timed out: request_id='{}' responder='{}'",
+                  request_id, pending.responder);
+            self.send_reply(&pending.origin, Reply {
+                request_id: pending.request_id,
+                data: Value::Null,
+                error: Some(String::from("request timed out")),
+            });
+        }
+    }
+
+    fn process_request(&mut self, message: Request, ctx: &mut Context<Self>) {
+        let request = message.message;
+        info!("received request: client='{}' client_type='{}' request_type='{}'",
+              message.addr, request.client_type, request.request_type);
+
+        // The client that would be forwarded the request has been told to reconnect elsewhere.
+        if self.shutting_down {
+            info!("rejecting request while shutting down: client='{}' request_id='{}'",
+                  message.addr, request.request_id);
+            self.send_reply(&message.addr, Reply {
+                request_id: request.request_id,
+                data: Value::Null,
+                error: Some(String::from("shutting_down")),
+            });
+            return;
+        }
+
+        let responder = match self.select_client(&request.client_type, request.key.as_ref()) {
+            Ok(responder) => responder,
+            Err(e) => {
+                warn!("no client available for request: client_type='{}' error='{}'",
+                      request.client_type, e);
+                self.send_reply(&message.addr, Reply {
+                    request_id: request.request_id,
+                    data: Value::Null,
+                    error: Some(format!("no client available: {}", e)),
+                });
+                return;
+            },
+        };
+
+        // Requests from different clients could use the same id, so the bus assigns its own id
+        // to the forwarded request and maps it back when the reply arrives.
+        let request_id = generate_event_id();
+        self.pending_requests.insert(request_id.clone(), PendingRequest {
+            origin: message.addr,
+            request_id: request.request_id.clone(),
+            responder: responder,
+        });
+
+        let timeout = request.timeout_ms.unwrap_or(self.request_timeout_ms);
+        if let Some(details) = self.sessions.get(&responder) {
+            info!("forwarding request: request_id='{}' responder='{}'", request_id, responder);
+            details.address.send(SendToClient(Message::Request(RequestSchema {
+                request_id: request_id.clone(),
+                ..request
+            })));
+        }
+
+        ctx.run_later(Duration::from_millis(timeout), move |bus, _| {
+            bus.expire_request(&request_id);
+        });
+    }
+}
+
+impl Handler<Request> for Bus {
+    type Result = ();
+
+    fn handle(&mut self, message: Request, ctx: &mut Context<Self>) {
+        let _log = self.session_log_context(&message.addr);
+        self.process_request(message, ctx);
+    }
+}
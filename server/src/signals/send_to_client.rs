@@ -1,42 +1,42 @@
 use actix::{Context, Handler, ResponseType};
+use common::schemas::Message;
 use failure::{Error, ResultExt};
-use serde::Serialize;
-use serde_json::{to_string, to_string_pretty};
-use websocket::message::OwnedMessage;
+use serde_json::to_string_pretty;
 
 use error::ErrorKind;
 use session::Session;
 
 /// The `SendToClient` message is sent to a Session when a message needs to be sent to the client
 /// managed by that session.
-pub struct SendToClient<T: Serialize + Send>(pub T);
+pub struct SendToClient(pub Message);
 
-impl<T: Send> ResponseType for SendToClient<T>
-    where T: Serialize
-{
+impl ResponseType for SendToClient {
     type Item = ();
     type Error = ();
 }
 
 impl Session {
-    pub fn send_message<T: Serialize>(&mut self, message: T) -> Result<(), Error> {
-        let serialized = to_string(&message).context(
-            ErrorKind::SerializeJsonForSending)?;
-        let pretty_serialized = to_string_pretty(&message).context(
-            ErrorKind::SerializeJsonForSending)?;
+    pub fn send_message(&mut self, message: Message) -> Result<(), Error> {
+        trace!("sending message: client='{}' message_type='{}' message=\n{}", self.addr,
+               message.message_type(),
+               to_string_pretty(&message).context(ErrorKind::SerializeJsonForSending)?);
 
-        info!("sending message: client='{}' message=\n{}", self.addr, pretty_serialized);
-        Ok(self.framed.send(OwnedMessage::Text(serialized)))
+        let frame = self.encoding.encode(&message)?;
+        Ok(self.framed.send(frame))
     }
 }
 
-impl<T: Send> Handler<SendToClient<T>> for Session
-    where T: Serialize
-{
+impl Handler<SendToClient> for Session {
     type Result = ();
 
-    fn handle(&mut self, message: SendToClient<T>,
+    fn handle(&mut self, message: SendToClient,
               _ctx: &mut Context<Self>) {
+        let _log = match message.0 {
+            Message::Event(ref event) => self.log_context()
+                .field("event_id", &event.id)
+                .field("correlation_id", event.correlation_id),
+            _ => self.log_context(),
+        };
         if let Err(e) = self.send_message(message.0) {
             error!("unable to send to message on websockets:\nclient='{}' error=\n{}",
                    self.addr, e);
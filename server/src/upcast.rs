@@ -0,0 +1,81 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use common::schemas::Event;
+use failure::{Error, ResultExt};
+use json_patch::{Patch, patch};
+use serde_json::from_str;
+
+use error::ErrorKind;
+use versioned::{VersionedErrors, read_versioned_directory};
+
+/// Upcasters contains JSON patches that transform the data of an event from one schema version to
+/// the next, so that clients only ever see the latest shape of an event regardless of when it
+/// was persisted.
+#[derive(Debug, Default)]
+pub struct Upcasters {
+    patches: HashMap<String, BTreeMap<u32, Patch>>,
+}
+
+impl Upcasters {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load every upcaster from a directory laid out as `<event_type>/<from_version>.json`, where
+    /// each file is a JSON patch from that version to the next.
+    pub fn load_directory(&mut self, path: &Path) -> Result<(), Error> {
+        let errors = VersionedErrors {
+            read_directory: ErrorKind::ReadUpcasterDirectory,
+            invalid_file_name: ErrorKind::InvalidUpcasterFileName,
+            read_file: ErrorKind::ReadUpcasterFile,
+        };
+
+        for file in read_versioned_directory(path, &errors)? {
+            let upcaster: Patch = from_str(&file.contents).context(ErrorKind::ParseUpcaster)?;
+
+            info!("loaded upcaster: event_type='{}' from_version='{}'",
+                  file.event_type, file.version);
+            self.patches.entry(file.event_type)
+                .or_insert_with(BTreeMap::new)
+                .insert(file.version, upcaster);
+        }
+
+        Ok(())
+    }
+
+    /// Find the schema version that the upcasters for an event type end at, which is the
+    /// version of events in their current shape, if there are any upcasters.
+    pub fn head_version(&self, event_type: &str) -> Option<u32> {
+        self.patches.get(event_type)
+            .and_then(|patches| patches.keys().next_back())
+            .map(|version| version + 1)
+    }
+
+    /// Apply every upcaster from the schema version of an event onwards. If any upcaster fails
+    /// then the event is returned unchanged.
+    pub fn upcast(&self, event: Event) -> Event {
+        let patches = match self.patches.get(&event.event_type) {
+            Some(patches) => patches,
+            None => return event,
+        };
+
+        let mut data = event.data.clone();
+        let mut version = event.schema_version;
+        while let Some(upcaster) = patches.get(&version) {
+            if let Err(e) = patch(&mut data, upcaster) {
+                warn!("failed to upcast event: event_id='{}' from_version='{}' error='{:?}'",
+                      event.id, version, e);
+                return event;
+            }
+            version += 1;
+        }
+
+        if version != event.schema_version {
+            debug!("upcast event: event_id='{}' from_version='{}' to_version='{}'",
+                   event.id, event.schema_version, version);
+        }
+
+        Event { data: data, schema_version: version, ..event }
+    }
+}
@@ -0,0 +1,68 @@
+use std::fs::{File, read_dir};
+use std::io::Read;
+use std::path::Path;
+
+use failure::{Error, ResultExt};
+
+use error::ErrorKind;
+
+/// VersionedFile is a file read from a directory laid out as `<event_type>/<version>.json`.
+pub struct VersionedFile {
+    pub event_type: String,
+    pub version: u32,
+    pub contents: String,
+}
+
+/// VersionedErrors contains the errors that reading a versioned directory fails with, so that
+/// each kind of directory can report its own.
+pub struct VersionedErrors {
+    pub read_directory: ErrorKind,
+    pub invalid_file_name: ErrorKind,
+    pub read_file: ErrorKind,
+}
+
+/// Read every file from a directory laid out as `<event_type>/<version>.json`. Entries that
+/// aren't directories of JSON files are skipped.
+pub fn read_versioned_directory(path: &Path,
+                                errors: &VersionedErrors) -> Result<Vec<VersionedFile>, Error> {
+    let mut files = Vec::new();
+    for entry in read_dir(path).context(errors.read_directory)? {
+        let entry = entry.context(errors.read_directory)?;
+        if !entry.path().is_dir() {
+            continue;
+        }
+
+        let event_type = match entry.file_name().into_string() {
+            Ok(event_type) => event_type,
+            Err(name) => {
+                warn!("skipping directory with invalid name: path='{}' name='{:?}'",
+                      path.display(), name);
+                continue;
+            },
+        };
+
+        for file in read_dir(entry.path()).context(errors.read_directory)? {
+            let path = file.context(errors.read_directory)?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let version = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u32>().ok())
+                .ok_or(errors.invalid_file_name)?;
+
+            let mut contents = String::new();
+            let mut file = File::open(&path).context(errors.read_file)?;
+            file.read_to_string(&mut contents).context(errors.read_file)?;
+
+            files.push(VersionedFile {
+                event_type: event_type.clone(),
+                version: version,
+                contents: contents,
+            });
+        }
+    }
+
+    Ok(files)
+}
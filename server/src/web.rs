@@ -0,0 +1,276 @@
+use std::net::SocketAddr;
+
+use actix::SyncAddress;
+use actix_web::{
+    Application,
+    AsyncResponder,
+    Error as ActixWebError,
+    HttpRequest,
+    HttpResponse,
+    HttpServer,
+    Method,
+    StatusCode,
+};
+use actix_web::httpcodes::{
+    HTTPBadRequest,
+    HTTPInternalServerError,
+    HTTPNotFound,
+    HTTPOk,
+    HTTPUnauthorized,
+};
+use failure::{Error, ResultExt};
+use futures::{Future, future};
+use serde_json::from_slice;
+
+use bus::Bus;
+use error::ErrorKind;
+use signals::{Admin, AdminCommand, AdminResponse, GetMetrics};
+
+/// The content type of the Prometheus text exposition format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The scheme of the authorization header that admin requests provide their token with.
+const BEARER_PREFIX: &str = "Bearer ";
+
+/// The function that answers every request to one of the http servers.
+type RequestHandler = fn(HttpRequest<State>)
+    -> Box<Future<Item=HttpResponse, Error=ActixWebError>>;
+
+struct State {
+    bus: SyncAddress<Bus>,
+    /// This field contains the token that requests must provide, if the server requires one.
+    token: Option<String>,
+}
+
+/// StickyKeyRelease is the body of a request to release a sticky key.
+#[derive(Deserialize)]
+struct StickyKeyRelease {
+    client_type: String,
+    key: String,
+    /// This field is set to release the key even if its session still has events for it.
+    #[serde(default)]
+    force: bool,
+}
+
+fn handle(req: HttpRequest<State>) -> Box<Future<Item=HttpResponse, Error=ActixWebError>> {
+    debug!("received a request on http: uri='{}'", req.uri());
+
+    match req.path() {
+        "/health_check" => {
+            debug!("responding to health check");
+            future::ok::<_, ActixWebError>(HTTPOk.with_body("OK")).responder()
+        },
+        "/metrics" => {
+            req.state().bus.call_fut(GetMetrics)
+                .then(|result| {
+                    match result {
+                        Ok(Ok(metrics)) => {
+                            HttpResponse::build(StatusCode::OK)
+                                .content_type(METRICS_CONTENT_TYPE)
+                                .body(metrics)
+                                .map_err(ActixWebError::from)
+                        },
+                        _ => {
+                            error!("failed to get metrics from bus");
+                            Ok(HTTPInternalServerError.into())
+                        },
+                    }
+                }).responder()
+        },
+        _ => future::ok::<HttpResponse, ActixWebError>(HTTPNotFound.into()).responder(),
+    }
+}
+
+/// Find the admin command that a request is for, or the response if it isn't for one.
+fn admin_command(method: &Method, path: &str, body: &[u8]) -> Result<AdminCommand, HttpResponse> {
+    let mut segments = path.trim_matches('/').split('/');
+    let route = (segments.next(), segments.next(), segments.next(), segments.next());
+
+    match (method.as_str(), route) {
+        ("GET", (Some("sessions"), None, _, _)) => Ok(AdminCommand::ListSessions),
+        ("GET", (Some("sticky_keys"), None, _, _)) => Ok(AdminCommand::ListStickyKeys),
+        ("GET", (Some("pending_events"), None, _, _)) => Ok(AdminCommand::ListPendingEvents),
+        ("POST", (Some("sessions"), Some(address), Some("disconnect"), None)) => {
+            match address.parse::<SocketAddr>() {
+                Ok(address) => Ok(AdminCommand::Disconnect(address)),
+                Err(_) => Err(HTTPBadRequest.into()),
+            }
+        },
+        ("POST", (Some("sticky_keys"), Some("release"), None, _)) => {
+            match from_slice::<StickyKeyRelease>(body) {
+                Ok(release) => Ok(AdminCommand::ReleaseStickyKey {
+                    client_type: release.client_type,
+                    key: release.key,
+                    force: release.force,
+                }),
+                Err(_) => Err(HTTPBadRequest.into()),
+            }
+        },
+        ("POST", (Some("pending_events"), Some(client_type), Some("flush"), None)) => {
+            Ok(AdminCommand::FlushPendingEvents(client_type.to_owned()))
+        },
+        _ => Err(HTTPNotFound.into()),
+    }
+}
+
+/// Check the authorization header of a request against the bearer token. The comparison takes
+/// the same time however much of the token matches, so that it can't be guessed byte by byte.
+fn authorized(header: Option<&str>, token: &str) -> bool {
+    let provided = match header {
+        Some(header) if header.starts_with(BEARER_PREFIX) => &header[BEARER_PREFIX.len()..],
+        _ => return false,
+    };
+
+    let difference = provided.bytes().zip(token.bytes())
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    provided.len() == token.len() && difference == 0
+}
+
+fn handle_admin(req: HttpRequest<State>) -> Box<Future<Item=HttpResponse, Error=ActixWebError>> {
+    let bus = req.state().bus.clone();
+    let method = req.method().clone();
+    let path = req.path().to_owned();
+    debug!("received a request on admin http: method='{}' uri='{}'", method, req.uri());
+
+    let is_authorized = {
+        let header = req.headers().get("authorization").and_then(|value| value.to_str().ok());
+        req.state().token.as_ref().map_or(false, |token| authorized(header, token))
+    };
+    if !is_authorized {
+        warn!("refusing unauthorized request on admin http: method='{}' uri='{}'",
+              method, req.uri());
+        return future::ok::<HttpResponse, ActixWebError>(HTTPUnauthorized.into()).responder();
+    }
+
+    req.body()
+       .limit(2048)
+       .from_err()
+       .and_then(move |bytes| {
+           let command = match admin_command(&method, &path, &bytes) {
+               Ok(command) => command,
+               Err(response) => return Ok(response),
+           };
+
+           match bus.call_fut(Admin(command)).wait() {
+               Ok(Ok(AdminResponse(status, body))) => {
+                   let status_code = match StatusCode::from_u16(status) {
+                       Ok(code) => code,
+                       Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                   };
+                   HttpResponse::build(status_code).json(body)
+               },
+               Ok(Err(e)) => {
+                   error!("failure in admin signal: error='{}'", e);
+                   Ok(HTTPInternalServerError.into())
+               },
+               Err(_) => Ok(HTTPInternalServerError.into()),
+           }
+       }).responder()
+}
+
+fn start(bind_address: String, bus: SyncAddress<Bus>, token: Option<String>,
+         handler: RequestHandler) -> Result<(), Error> {
+    HttpServer::new(move || {
+            let state = State { bus: bus.clone(), token: token.clone() };
+
+            Application::with_state(state)
+                .handler("/", handler)
+        })
+        .bind(bind_address)
+        .context(ErrorKind::UnableToBindHttpServer)?
+        .start();
+    Ok(())
+}
+
+/// Start the http server that exposes the metrics of the bus for Prometheus to scrape.
+pub fn start_webserver(bind_address: String, bus: SyncAddress<Bus>) -> Result<(), Error> {
+    info!("starting http server: bind='{}'", bind_address);
+    start(bind_address, bus, None, handle)
+}
+
+/// Start the http server of the admin API, which lets operators inspect sessions, sticky keys
+/// and pending events and intervene when something is stuck. Every request must provide the
+/// token as a bearer token in its authorization header.
+pub fn start_admin_server(bind_address: String, token: String,
+                          bus: SyncAddress<Bus>) -> Result<(), Error> {
+    info!("starting admin http server: bind='{}'", bind_address);
+    start(bind_address, bus, Some(token), handle_admin)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{Method, StatusCode};
+
+    use signals::AdminCommand;
+    use super::{admin_command, authorized};
+
+    fn status(method: Method, path: &str, body: &str) -> StatusCode {
+        admin_command(&method, path, body.as_bytes()).unwrap_err().status()
+    }
+
+    #[test]
+    fn routes_listings() {
+        assert_eq!(admin_command(&Method::GET, "/sessions", b"").unwrap(),
+                   AdminCommand::ListSessions);
+        assert_eq!(admin_command(&Method::GET, "/sticky_keys/", b"").unwrap(),
+                   AdminCommand::ListStickyKeys);
+        assert_eq!(admin_command(&Method::GET, "/pending_events", b"").unwrap(),
+                   AdminCommand::ListPendingEvents);
+    }
+
+    #[test]
+    fn routes_disconnect() {
+        assert_eq!(admin_command(&Method::POST, "/sessions/127.0.0.1:1234/disconnect", b"")
+                       .unwrap(),
+                   AdminCommand::Disconnect("127.0.0.1:1234".parse().unwrap()));
+        assert_eq!(status(Method::POST, "/sessions/localhost/disconnect", ""),
+                   StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn routes_sticky_key_release() {
+        let body = r#"{ "client_type": "accounts", "key": "account-1" }"#;
+        assert_eq!(admin_command(&Method::POST, "/sticky_keys/release", body.as_bytes()).unwrap(),
+                   AdminCommand::ReleaseStickyKey {
+                       client_type: String::from("accounts"),
+                       key: String::from("account-1"),
+                       force: false,
+                   });
+
+        let body = r#"{ "client_type": "accounts", "key": "account-1", "force": true }"#;
+        assert_eq!(admin_command(&Method::POST, "/sticky_keys/release", body.as_bytes()).unwrap(),
+                   AdminCommand::ReleaseStickyKey {
+                       client_type: String::from("accounts"),
+                       key: String::from("account-1"),
+                       force: true,
+                   });
+
+        assert_eq!(status(Method::POST, "/sticky_keys/release", r#"{ "key": "account-1" }"#),
+                   StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn routes_flush() {
+        assert_eq!(admin_command(&Method::POST, "/pending_events/accounts/flush", b"").unwrap(),
+                   AdminCommand::FlushPendingEvents(String::from("accounts")));
+    }
+
+    #[test]
+    fn unknown_routes_are_not_found() {
+        assert_eq!(status(Method::GET, "/", ""), StatusCode::NOT_FOUND);
+        assert_eq!(status(Method::POST, "/sessions", ""), StatusCode::NOT_FOUND);
+        assert_eq!(status(Method::GET, "/sessions/127.0.0.1:1234/disconnect", ""),
+                   StatusCode::NOT_FOUND);
+        assert_eq!(status(Method::GET, "/sessions/extra", ""), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn requires_bearer_token() {
+        assert!(authorized(Some("Bearer secret"), "secret"));
+        assert!(!authorized(Some("Bearer secrets"), "secret"));
+        assert!(!authorized(Some("Bearer secreT"), "secret"));
+        assert!(!authorized(Some("secret"), "secret"));
+        assert!(!authorized(Some("Basic secret"), "secret"));
+        assert!(!authorized(None, "secret"));
+    }
+}
@@ -63,7 +63,8 @@ bus:add_rebuild_handler("AcceptedTransaction", rebuild_accepted_or_rejected("acc
 bus:add_rebuild_handler("RejectedTransaction", rebuild_accepted_or_rejected("rejected"))
 
 -- We only need a receipt listener for event types that we send.
-bus:add_receipt_listener("PendingTransaction", function(status, event_type, key, correlation, data)
+bus:add_receipt_listener("PendingTransaction", function(status, event_type, key, correlation, data,
+                                                       event_id, retry_after_ms)
     log:debug("received " .. event_type .. " receipt")
     local transaction = redis:get(key)
 
@@ -73,6 +74,16 @@ bus:add_receipt_listener("PendingTransaction", function(status, event_type, key,
         if status == "inconsistent" then
             -- Resend the event.
             bus:send(event_type, key, false, correlation, data)
+        -- If we sent too many events, then resend it once the bus will accept it.
+        elseif status == "rate_limited" then
+            local retry_after = retry_after_ms or 1000
+            log:warn("transaction was rate limited, retrying in " .. retry_after .. "ms")
+            bus:send(event_type, key, true, correlation, data, { send_after_ms = retry_after })
+        -- If the bus refused the transaction, then resending it would not help.
+        elseif status == "invalid" or status == "too_large" then
+            log:error("transaction was refused by the bus with status " .. status)
+            transaction.status = "failed"
+            redis:set(key, transaction)
         -- If the transaction has not already been accepted/rejected then
         -- we update the status to 'pending'.
         elseif transaction.status == "created" then
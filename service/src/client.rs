@@ -1,4 +1,3 @@
-use std::str::from_utf8;
 use std::process::exit;
 
 use actix::{
@@ -13,8 +12,10 @@ use actix::{
     StreamHandler,
     SyncAddress
 };
+use common::Encoding;
+use common::protocol::PROTOCOL_VERSION;
+use common::schemas::{Hello, Message, Welcome};
 use failure::{Error, ResultExt};
-use serde_json::{from_str, Value};
 use websocket::ClientBuilder;
 use websocket::async::TcpStream;
 use websocket::async::futures::{self, Future};
@@ -23,21 +24,25 @@ use websocket::message::OwnedMessage;
 
 use error::ErrorKind;
 use interpreter::Interpreter;
-use signals::{Event, Link, Rebuild, Receipt, Registration};
+use signals::{BusReply, BusRequest, Event, Link, Rebuild, Receipt, Registration};
 
 pub struct Client {
     pub interpreter: SyncAddress<Interpreter>,
     pub framed: FramedWriter<TcpStream, MessageCodec<OwnedMessage>>,
+    /// This field contains the encoding that is offered to the server before JSON.
+    pub preferred_encoding: Encoding,
+    /// This field contains the encoding negotiated during the handshake.
+    pub encoding: Encoding,
 }
 
 impl Client {
-    pub fn launch(server_address: String,
-                  interpreter: SyncAddress<Interpreter>) -> Result<(), Error> {
+    pub fn launch(server_address: String, interpreter: SyncAddress<Interpreter>,
+                  preferred_encoding: Encoding) -> Result<(), Error> {
         Arbiter::handle().spawn(
             ClientBuilder::new(&server_address)
                 .context(ErrorKind::WebsocketClientBuilderCreate)?
                 .async_connect_insecure(Arbiter::handle())
-                .and_then(|(framed, _)| {
+                .and_then(move |(framed, _)| {
                     let _: () = Client::create(|ctx| {
                         let (reader, writer) = FramedReader::wrap(framed);
                         Client::add_stream(reader, ctx);
@@ -45,6 +50,9 @@ impl Client {
                         Client {
                             interpreter: interpreter,
                             framed: writer,
+                            preferred_encoding: preferred_encoding,
+                            // Everything before the welcome is sent as JSON.
+                            encoding: Encoding::Json,
                         }
                     });
 
@@ -64,14 +72,51 @@ impl Actor for Client {
 }
 
 impl Client {
+    /// Send the `hello` message that starts the handshake with the event bus.
+    fn send_hello(&mut self) -> Result<(), Error> {
+        let mut encodings = vec![String::from(self.preferred_encoding.name())];
+        if self.preferred_encoding != Encoding::Json {
+            encodings.push(String::from(Encoding::Json.name()));
+        }
+
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: None,
+            encodings: encodings,
+            features: Vec::new(),
+        };
+
+        info!("sending hello to server");
+        self.send_message(Message::Hello(hello))
+    }
+
+    /// Check the outcome of the handshake and, if we were accepted, link the client to the
+    /// interpreter so that registration can begin.
+    fn process_welcome(&mut self, parsed: Welcome, ctx: &mut Context<Self>) -> Result<(), Error> {
+        if parsed.status != "accepted" {
+            warn!("handshake refused by server: reason='{:?}'", parsed.reason);
+            return Err(Error::from(ErrorKind::HandshakeRefused));
+        }
+
+        info!("handshake accepted by server: protocol_version='{}' encoding='{:?}' \
+              features='{:?}'", parsed.protocol_version, parsed.encoding, parsed.features);
+        self.encoding = parsed.encoding.as_ref()
+            .and_then(|e| Encoding::from_name(e))
+            .unwrap_or_default();
+
+        info!("sending link to interpreter");
+        self.interpreter.send(Link { client: ctx.address() });
+        Ok(())
+    }
+
     /// Process an incoming message on the Websockets connection.
     fn process_message(&mut self, message: OwnedMessage,
                        ctx: &mut Context<Self>) -> Result<(), Error> {
-        let contents: String = match message {
-            OwnedMessage::Text(m) => m,
-            OwnedMessage::Binary(b) => {
-                from_utf8(&b).context(ErrorKind::ParseBytesAsUtf8)?.to_string()
-            },
+        let contents: Message = match message {
+            OwnedMessage::Text(m) => Encoding::Json.decode(m.as_bytes()).context(
+                ErrorKind::ParseMessageFromWebsockets)?,
+            OwnedMessage::Binary(b) => self.encoding.decode(&b).context(
+                ErrorKind::ParseMessageFromWebsockets)?,
             OwnedMessage::Close(_) => {
                 info!("received a close from server");
                 ctx.stop();
@@ -88,41 +133,75 @@ impl Client {
             },
         };
 
-        let parsed_contents: Value = from_str(&contents).context(
-                ErrorKind::ParseJsonFromWebsockets)?;
-        let message_type = parsed_contents["message_type"].as_str().ok_or(
-                ErrorKind::NoMessageTypeFromWebsockets)?;
-
-        match message_type {
-            "event" => {
+        let message_type = contents.message_type();
+        match contents {
+            Message::Welcome(welcome) => {
+                info!("processing welcome message");
+                if let Err(e) = self.process_welcome(welcome, ctx) {
+                    error!("closing service, failed handshake: error='{}'", e);
+                    exit(1);
+                }
+            },
+            Message::Event(event) => {
                 info!("sending event message to interpreter");
                 self.interpreter.send(Event {
-                    message: contents
+                    message: event
                 });
                 info!("sent event message to interpreter");
             },
-            "rebuild" => {
+            Message::Rebuild(rebuild) => {
                 info!("sending rebuild message to interpreter");
                 self.interpreter.send(Rebuild {
-                    message: contents
+                    message: rebuild
                 });
                 info!("sent rebuild message to interpreter");
             },
-            "receipt" => {
+            Message::Receipts(receipts) => {
                 info!("sending receipt message to interpreter");
                 self.interpreter.send(Receipt {
-                    message: contents
+                    message: receipts
                 });
                 info!("sent receipt message to interpreter");
             },
-            "registration" => {
+            Message::Registration(registration) => {
                 info!("sending registration message to interpreter");
                 self.interpreter.send(Registration {
-                    message: contents
+                    message: registration
                 });
                 info!("sent registration message to interpreter");
             },
+            Message::Request(request) => {
+                info!("sending request message to interpreter");
+                self.interpreter.send(BusRequest {
+                    message: request
+                });
+                info!("sent request message to interpreter");
+            },
+            Message::Reply(reply) => {
+                info!("sending reply message to interpreter");
+                self.interpreter.send(BusReply {
+                    message: reply
+                });
+                info!("sent reply message to interpreter");
+            },
+            Message::Cancellation(cancellation) => {
+                info!("received cancellation: event_id='{}' cancelled='{}'",
+                      cancellation.event_id, cancellation.cancelled);
+            },
+            Message::Rejected(rejected) => {
+                error!("message rejected by server: reason='{}' limit='{}' size='{}'",
+                       rejected.reason, rejected.limit, rejected.size);
+            },
+            Message::Shutdown(shutdown) => {
+                // The service exits once the event bus closes the connection, events that are
+                // being processed until then can still be acknowledged.
+                warn!("event bus is shutting down: reason='{}' deadline_ms='{}'",
+                      shutdown.reason, shutdown.deadline_ms);
+            },
+            // Any other messages are only ever sent from clients to the bus.
             _ => {
+                warn!("received unexpected message from server: message_type='{}'",
+                      message_type);
                 return Err(Error::from(ErrorKind::InvalidWebsocketMessageType));
             },
         };
@@ -132,9 +211,12 @@ impl Client {
 }
 
 impl StreamHandler<OwnedMessage, FramedError<MessageCodec<OwnedMessage>>> for Client {
-    fn started(&mut self, ctx: &mut Self::Context) {
-        info!("websocket client started. sending link to interpreter");
-        self.interpreter.send(Link { client: ctx.address() });
+    fn started(&mut self, _: &mut Self::Context) {
+        info!("websocket client started. sending hello to server");
+        if let Err(e) = self.send_hello() {
+            error!("closing service, failed to send hello: error='{}'", e);
+            exit(1);
+        }
     }
 
     fn handle(&mut self, message: OwnedMessage, ctx: &mut Context<Self>) {
@@ -0,0 +1,94 @@
+use std::env;
+use std::path::Path;
+
+use clap::ArgMatches;
+use common::{Encoding, LoggingConfig};
+use common::config::load_config;
+use failure::Error;
+
+use error::ErrorKind;
+
+/// Environment variables starting with this prefix override the configuration file.
+pub const ENV_PREFIX: &str = "SERVICED_";
+
+/// Config contains every tunable of the service daemon. It is loaded from the defaults, then the
+/// configuration file, then environment variables and then command line flags, each overriding
+/// the last.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub logging: LoggingConfig,
+    pub service: ServiceConfig,
+}
+
+/// ServiceConfig contains where the service finds its script and the servers it talks to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// This field contains the path to the Lua script to run as a service.
+    pub script: Option<String>,
+    /// This field contains the address to bind the HTTP server to.
+    pub bind: String,
+    /// This field contains the address of the Redis server.
+    pub redis: String,
+    /// This field contains the address of the event bus websocket server.
+    pub server: String,
+    /// This field contains the encoding that is preferred for messages to and from the event bus.
+    pub encoding: String,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            script: None,
+            bind: String::from("0.0.0.0:8080"),
+            redis: String::from("redis://localhost/"),
+            server: String::from("ws://localhost:8081"),
+            encoding: String::from("json"),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the file named by the `config` argument or the
+    /// `SERVICED_CONFIG` environment variable, if either is provided, and from the environment.
+    pub fn load(arguments: &ArgMatches) -> Result<Self, Error> {
+        let path_var = format!("{}CONFIG", ENV_PREFIX);
+        let path = arguments.value_of("config").map(String::from)
+            .or_else(|| env::var(&path_var).ok());
+        let vars = env::vars().filter(|&(ref name, _)| *name != path_var);
+
+        load_config(path.as_ref().map(Path::new), ENV_PREFIX, vars)
+    }
+
+    /// Override the configuration with the flags that were provided.
+    pub fn apply_arguments(&mut self, arguments: &ArgMatches) {
+        if let Some(script) = arguments.value_of("input") {
+            self.service.script = Some(script.to_owned());
+        }
+        if let Some(bind) = arguments.value_of("bind-address") {
+            self.service.bind = bind.to_owned();
+        }
+        if let Some(redis) = arguments.value_of("redis-address") {
+            self.service.redis = redis.to_owned();
+        }
+        if let Some(server) = arguments.value_of("server-address") {
+            self.service.server = server.to_owned();
+        }
+        if let Some(encoding) = arguments.value_of("encoding") {
+            self.service.encoding = encoding.to_owned();
+        }
+    }
+
+    /// Check the parts of the configuration that are not checked when it is loaded.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.logging.validate()?;
+        self.encoding()?;
+        Ok(())
+    }
+
+    pub fn encoding(&self) -> Result<Encoding, Error> {
+        Encoding::from_name(&self.service.encoding)
+            .ok_or(Error::from(ErrorKind::InvalidEncodingArgument))
+    }
+}
@@ -16,12 +16,10 @@ pub enum ErrorKind {
     WebsocketClientBuilderCreate,
     #[fail(display = "Received invalid message type over websockets")]
     InvalidWebsocketMessageType,
-    #[fail(display = "Invalid JSON received on websockets")]
-    ParseJsonFromWebsockets,
-    #[fail(display = "No message type in JSON from websockets")]
-    NoMessageTypeFromWebsockets,
-    #[fail(display = "Failed to parse bytes as UTF8 string")]
-    ParseBytesAsUtf8,
+    #[fail(display = "Invalid message received on websockets")]
+    ParseMessageFromWebsockets,
+    #[fail(display = "Handshake was refused by the event bus")]
+    HandshakeRefused,
 
     #[fail(display = "Unable to bind to http port")]
     HttpBindToPort,
@@ -29,14 +27,10 @@ pub enum ErrorKind {
     #[fail(display = "Client has not linked to interpreter")]
     ClientNotLinkedToInterpreter,
 
-    #[fail(display = "Missing websocket server argument. This is a bug, should be a default")]
-    MissingWebsocketServerArgument,
-    #[fail(display = "Missing bind address argument. This is a bug, should be a default")]
-    MissingBindAddressArgument,
-    #[fail(display = "Missing redis address argument. This is a bug, should be a default")]
-    MissingRedisAddressArgument,
-    #[fail(display = "Missing Lua script argument. This is a bug, should be a default")]
+    #[fail(display = "No Lua script was provided as an argument or in the configuration")]
     MissingLuaScriptArgument,
+    #[fail(display = "Unknown encoding argument")]
+    InvalidEncodingArgument,
 
     #[fail(display = "Failed to create Redis client")]
     RedisClientCreate,
@@ -61,12 +55,18 @@ pub enum ErrorKind {
 
     #[fail(display = "Event handler not found in Lua register")]
     MissingEventHandlerRegistryValue,
+    #[fail(display = "Failed to set current event in Lua register")]
+    SetCurrentEvent,
     #[fail(display = "Rebuild handler not found in Lua register")]
     MissingRebuildHandlerRegistryValue,
     #[fail(display = "Receipt handler not found in Lua register")]
     MissingReceiptHandlerRegistryValue,
     #[fail(display = "HTTP handler not found in Lua register")]
     MissingHttpHandlerRegistryValue,
+    #[fail(display = "Request handler not found in Lua register")]
+    MissingRequestHandlerRegistryValue,
+    #[fail(display = "Reply handler not found in Lua register")]
+    MissingReplyHandlerRegistryValue,
 
     #[fail(display = "Failure when running rebuild handler")]
     FailedRebuildHandler,
@@ -80,6 +80,18 @@ pub enum ErrorKind {
     ParseHttpHandlerResult,
     #[fail(display = "Failure when parsing content from HTTP request")]
     ParseHttpContent,
+    #[fail(display = "Predicates passed to register are invalid")]
+    InvalidPredicates,
+    #[fail(display = "Delivery mode passed to register must be 'competing' or 'fanout'")]
+    InvalidDeliveryMode,
+    #[fail(display = "Failure when running request handler")]
+    FailedRequestHandler,
+    #[fail(display = "Invalid return type from request handler")]
+    RequestHandlerInvalidReturnType,
+    #[fail(display = "Failure when parsing result from request handler")]
+    ParseRequestHandlerResult,
+    #[fail(display = "Failure when running reply handler")]
+    FailedReplyHandler,
 
     #[fail(display = "Failed to serialize value to json for sending")]
     SerializeJsonForSending,
@@ -106,15 +118,17 @@ pub enum ErrorKind {
     #[fail(display = "Unable to log Lua value of this type")]
     UnsupportedLoggingType,
 
-    #[fail(display = "Invalid type passed as correlation id")]
+    #[fail(display = "Invalid correlation id, it should be an integer that is not negative")]
     InvalidCorrelationIdType,
-    #[fail(display = "Found implicit consistency in state. This is a bug and should not happen")]
-    ImplicitConsistencyInMap,
 
     #[fail(display = "Failed to parse incoming event JSON")]
     ParseEventMessage,
     #[fail(display = "Failed to parse incoming receipt JSON")]
     ParseReceiptMessage,
+    #[fail(display = "Failed to parse incoming request JSON")]
+    ParseRequestMessage,
+    #[fail(display = "Failed to parse incoming reply JSON")]
+    ParseReplyMessage,
 
     #[fail(display = "Failed to create regex set for router")]
     RouterCreateRegexSet,
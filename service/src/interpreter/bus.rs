@@ -3,18 +3,27 @@ use std::process::exit;
 use std::rc::Rc;
 
 use actix::{Arbiter, SyncAddress};
-use failure::Error;
+use failure::{Error, ResultExt};
 use rand::{Rng, thread_rng};
-use rlua::{Function, Table, UserData, UserDataMethods, Value as LuaValue};
-use serde_json::Value;
+use common::schemas::{DeliveryMode, Predicate, Request};
+use rlua::{
+    Function,
+    Lua,
+    Result as LuaResult,
+    Table,
+    UserData,
+    UserDataMethods,
+    Value as LuaValue,
+};
+use serde_json::{Value, from_value};
 use websocket::async::futures;
 
 use error::ErrorKind;
-use interpreter::Interpreter;
+use interpreter::{CURRENT_EVENT_KEY, Interpreter};
 use interpreter::extensions::{ToLuaError, ToLuaErrorResult};
 use interpreter::helpers::lua_to_json;
 use interpreter::router::Router;
-use signals::NewEvent;
+use signals::{Cancel, NewEvent, SendRequest};
 
 #[derive(Clone)]
 pub struct Bus {
@@ -22,10 +31,13 @@ pub struct Bus {
 
     pub event_types: HashSet<String>,
     pub client_type: Option<String>,
+    pub predicates: Vec<Predicate>,
+    pub delivery_mode: DeliveryMode,
 
     pub event_handlers: HashMap<String, String>,
     pub rebuild_handlers: HashMap<String, String>,
     pub receipt_handlers: HashMap<String, String>,
+    pub request_handlers: HashMap<String, String>,
     pub http_router: Rc<Router>,
 }
 
@@ -35,9 +47,12 @@ impl Bus {
             interpreter: address,
             event_types: HashSet::new(),
             client_type: None,
+            predicates: Vec::new(),
+            delivery_mode: DeliveryMode::Competing,
             event_handlers: HashMap::new(),
             rebuild_handlers: HashMap::new(),
             receipt_handlers: HashMap::new(),
+            request_handlers: HashMap::new(),
             http_router: Rc::new(Router::new()),
         }
     }
@@ -51,9 +66,21 @@ impl Bus {
 
 impl UserData for Bus {
     fn add_methods(methods: &mut UserDataMethods<Self>) {
-        methods.add_method_mut("register", |_, this, client_type: String| {
+        methods.add_method_mut("register", |lua, this,
+                               (client_type, predicates, delivery_mode):
+                               (String, Option<Table>, Option<String>)| {
             debug!("received register call from lua: client_type'{}'", client_type);
             this.client_type = Some(client_type);
+            if let Some(predicates) = predicates {
+                let predicates = lua_to_json(lua, predicates).to_lua_error()?;
+                this.predicates = from_value(predicates).context(
+                    ErrorKind::InvalidPredicates).to_lua_error()?;
+            }
+            this.delivery_mode = match delivery_mode.as_ref().map(String::as_str) {
+                None | Some("competing") => DeliveryMode::Competing,
+                Some("fanout") => DeliveryMode::Fanout,
+                Some(_) => return Err(Error::from(ErrorKind::InvalidDeliveryMode).to_lua_error()),
+            };
             debug!("finished register call from lua");
             Ok(())
         });
@@ -101,6 +128,19 @@ impl UserData for Bus {
             Ok(())
         });
 
+        methods.add_method_mut("add_request_handler", |lua, this,
+                               (request_type, handler): (String, Function)| {
+            debug!("received add_request_handler call from lua: request_type='{}'", request_type);
+            let key = this.generate_key();
+            lua.set_named_registry_value(&key, handler)?;
+
+            match this.request_handlers.insert(request_type.clone(), key) {
+                Some(_) => info!("old request handler replaced: type='{}'", request_type),
+                None => info!("new request handler added: type='{}'", request_type),
+            }
+            Ok(())
+        });
+
         methods.add_method_mut("add_route", |lua, this,
                                (path, method, handler): (String, String, Function)| {
             if path == "/health_check" {
@@ -127,37 +167,127 @@ impl UserData for Bus {
             Ok(())
         });
 
-        methods.add_method("send", |lua, this,
-                           (event_type, consistency_key, implicit, correlation_id, data):
-                           (String, String, bool, LuaValue, Table)| {
+        methods.add_method("send", |lua, this, args: SendArgs| {
             debug!("received send call from lua");
+            send_new_event(lua, this, None, args)?;
+            debug!("finished send call from lua");
+            Ok(())
+        });
+
+        methods.add_method("command", |lua, this,
+                           (client_type, event_type, consistency_key, implicit, correlation_id,
+                            data, options):
+                           (String, String, String, bool, LuaValue, Table, Option<Table>)| {
+            debug!("received command call from lua: client_type='{}'", client_type);
+            send_new_event(lua, this, Some(client_type), (event_type, consistency_key, implicit,
+                                                           correlation_id, data, options))?;
+            debug!("finished command call from lua");
+            Ok(())
+        });
+
+        methods.add_method("cancel", |_, this, event_id: String| {
+            debug!("received cancel call from lua: event_id='{}'", event_id);
+            let interpreter = this.interpreter.clone();
+            Arbiter::handle().spawn_fn(move || {
+                interpreter.send(Cancel { event_id });
+                futures::future::ok(())
+            });
+            Ok(())
+        });
+
+        methods.add_method("request", |lua, this,
+                           (client_type, request_type, data, callback, options):
+                           (String, String, Table, Function, Option<Table>)| {
+            debug!("received request call from lua: client_type='{}' request_type='{}'",
+                   client_type, request_type);
             let data: Value = lua_to_json(lua, data).to_lua_error()?;
 
-            let correlation_id: Option<u32> = match correlation_id {
-                LuaValue::Nil => None,
-                LuaValue::Integer(v) => Some(v as u32),
-                _ => return Err(Error::from(ErrorKind::InvalidCorrelationIdType).to_lua_error()),
-            };
+            let mut key = None;
+            let mut timeout_ms = None;
+            if let Some(options) = options {
+                key = options.get::<_, Option<String>>("key")?;
+                timeout_ms = options.get::<_, Option<u64>>("timeout_ms")?;
+            }
 
-            // In order to use actor addresses, we must be running from an actor, else the program
-            // will hang and everything breaks. To get around this, we can use the arbiter handle
-            // to send the signal.
+            // The callback is kept in the registry under the request id until the reply arrives.
+            let request_id = this.generate_key();
+            lua.set_named_registry_value(&request_id, callback)?;
+
+            let message = Request { request_id, client_type, request_type, key, data, timeout_ms };
             let interpreter = this.interpreter.clone();
             Arbiter::handle().spawn_fn(move || {
-                interpreter.send(NewEvent {
-                    event_type,
-                    consistency_key,
-                    data,
-                    implicit,
-                    correlation_id,
-                });
+                interpreter.send(SendRequest { message });
                 futures::future::ok(())
             });
 
-            debug!("finished send call from lua");
+            debug!("finished request call from lua");
             Ok(())
         });
+    }
+}
+
+/// The arguments of `bus:send` and, after the client type, `bus:command`.
+type SendArgs<'lua> = (String, String, bool, LuaValue<'lua>, Table<'lua>, Option<Table<'lua>>);
 
+/// Send a new event, or a command if a target client type is given, from the arguments of a Lua
+/// call.
+fn send_new_event<'lua>(lua: &'lua Lua, bus: &Bus, target: Option<String>,
+                        args: SendArgs<'lua>) -> LuaResult<()> {
+    let (event_type, consistency_key, implicit, correlation_id, data, options) = args;
+    let data: Value = lua_to_json(lua, data).to_lua_error()?;
 
+    let correlation_id: Option<u64> = match correlation_id {
+        LuaValue::Nil => None,
+        // Correlation ids are unsigned, so negative ids are refused rather than wrapped.
+        LuaValue::Integer(v) if v >= 0 => Some(v as u64),
+        _ => return Err(Error::from(ErrorKind::InvalidCorrelationIdType).to_lua_error()),
+    };
+
+    // Events sent while handling an event are caused by that event unless the options
+    // say otherwise.
+    let mut causation_id: Option<String> = lua.named_registry_value(CURRENT_EVENT_KEY)?;
+    let mut idempotency_key = None;
+    let mut metadata = HashMap::new();
+    let mut schema_version = None;
+    let mut deliver_at = None;
+    let mut delay = None;
+    let mut send_after_ms = None;
+    if let Some(options) = options {
+        if let Some(id) = options.get::<_, Option<String>>("causation_id")? {
+            causation_id = Some(id);
+        }
+        idempotency_key = options.get::<_, Option<String>>("idempotency_key")?;
+        if let Some(map) = options.get::<_, Option<HashMap<String, String>>>("metadata")? {
+            metadata = map;
+        }
+        schema_version = options.get::<_, Option<u32>>("schema_version")?;
+        deliver_at = options.get::<_, Option<String>>("deliver_at")?;
+        delay = options.get::<_, Option<u64>>("delay")?;
+        send_after_ms = options.get::<_, Option<u64>>("send_after_ms")?;
     }
+
+    // In order to use actor addresses, we must be running from an actor, else the program
+    // will hang and everything breaks. To get around this, we can use the arbiter handle
+    // to send the signal.
+    let interpreter = bus.interpreter.clone();
+    Arbiter::handle().spawn_fn(move || {
+        interpreter.send(NewEvent {
+            causation_id,
+            consistency_key,
+            data,
+            event_type,
+            idempotency_key,
+            implicit,
+            correlation_id,
+            deliver_at,
+            delay,
+            metadata,
+            schema_version,
+            send_after_ms,
+            target,
+        });
+        futures::future::ok(())
+    });
+
+    Ok(())
 }
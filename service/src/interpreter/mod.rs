@@ -7,13 +7,13 @@ mod router;
 mod status_codes;
 
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::ops::Deref;
 
 use actix::{Actor, Context, SyncAddress};
-use common::schemas::{ConsistencyKey, ConsistencyValue, NewEvent};
+use busclient::{ConsistencyTracker, ReceiptTracker};
+use common::schemas::NewEvent;
 use failure::{Error, ResultExt};
 use rand::{self, ThreadRng};
 use redis::Client as RedisClient;
@@ -28,12 +28,15 @@ pub use interpreter::redis::RedisInterface;
 pub use interpreter::status_codes::add_http_status_codes;
 
 pub const TIMESTAMP_KEY: &'static str = "__CLIENT_TIMESTAMP";
+/// The id of the event being handled is kept in the Lua registry under this key so that events
+/// sent from a handler can refer to the event that caused them.
+pub const CURRENT_EVENT_KEY: &'static str = "__CURRENT_EVENT_ID";
 
 pub struct Interpreter {
     pub client: Option<SyncAddress<Client>>,
-    pub consistency: HashMap<ConsistencyKey, ConsistencyValue>,
+    pub consistency: ConsistencyTracker,
     pub lua: Lua,
-    pub receipt_lookup: HashMap<String, NewEvent>,
+    pub receipt_lookup: ReceiptTracker<NewEvent>,
     pub redis: RedisClient,
     pub rng: RefCell<ThreadRng>,
     pub script: String,
@@ -53,9 +56,9 @@ impl Interpreter {
 
         let interpreter = Self {
             client: None,
-            consistency: HashMap::new(),
+            consistency: ConsistencyTracker::new(),
             lua: lua,
-            receipt_lookup: HashMap::new(),
+            receipt_lookup: ReceiptTracker::new(),
             redis: redis,
             rng: RefCell::new(rand::thread_rng()),
             script: contents,
@@ -2,6 +2,7 @@
 
 extern crate actix;
 extern crate actix_web;
+extern crate busclient;
 extern crate chrono;
 #[macro_use] extern crate clap;
 extern crate common;
@@ -9,6 +10,7 @@ extern crate common;
 extern crate http;
 #[macro_use] extern crate log;
 extern crate serde;
+#[macro_use] extern crate serde_derive;
 extern crate serde_json;
 extern crate rand;
 extern crate redis;
@@ -17,6 +19,7 @@ extern crate rlua;
 extern crate websocket;
 
 mod client;
+mod config;
 mod error;
 mod interpreter;
 mod signals;
@@ -25,12 +28,13 @@ mod web;
 use std::process::exit;
 
 use actix::{SyncAddress, System};
-use clap::{Arg, ArgMatches, App};
-use common::configure_logging;
+use clap::{Arg, App, AppSettings, SubCommand};
+use common::{LogFormat, LoggingConfig, configure_logging};
+use common::config::render_config;
 use failure::Error;
-use log::LogLevelFilter;
 
 use client::Client;
+use config::Config;
 use error::ErrorKind;
 use interpreter::Interpreter;
 use web::start_webserver;
@@ -43,56 +47,115 @@ fn main() {
         .arg(Arg::with_name("log-level")
              .short("l")
              .long("log-level")
-             .help("Log level")
-             .default_value("info")
+             .help("Log level, overrides the configuration file")
              .possible_values(&["off", "trace", "debug", "info", "warn", "error"])
              .takes_value(true))
+        .arg(Arg::with_name("log-format")
+             .long("log-format")
+             .help("Log format, overrides the configuration file")
+             .possible_values(&["text", "json"])
+             .takes_value(true))
+        .arg(Arg::with_name("config")
+             .short("c")
+             .long("config")
+             .help("TOML configuration file, settings in it can be overridden by environment \
+                   variables such as SERVICED_SERVICE__REDIS and by flags")
+             .takes_value(true))
         .arg(Arg::with_name("bind-address")
              .short("b")
              .long("bind")
              .help("Address for binding HTTP server")
-             .default_value("0.0.0.0:8080")
              .takes_value(true))
         .arg(Arg::with_name("redis-address")
              .short("r")
              .long("redis")
              .help("Redis server address")
-             .default_value("redis://localhost/")
              .takes_value(true))
         .arg(Arg::with_name("server-address")
              .short("s")
              .long("server")
              .help("Websocket server address")
-             .default_value("ws://localhost:8081")
+             .takes_value(true))
+        .arg(Arg::with_name("encoding")
+             .short("e")
+             .long("encoding")
+             .help("Preferred encoding for messages to and from the event bus")
+             .possible_values(&["json", "msgpack"])
              .takes_value(true))
         .arg(Arg::with_name("input")
-             .help("Path to lua script to run as a service")
-             .index(1)
-             .required(true))
+             .help("Path to lua script to run as a service, overrides the configuration file")
+             .index(1))
+        .subcommand(SubCommand::with_name("config")
+                    .about("Inspect the configuration of the service daemon")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("check")
+                                .about("Validate and print the effective configuration")))
         .get_matches();
 
-    let level = value_t!(matches, "log-level", LogLevelFilter).unwrap_or(LogLevelFilter::Trace);
-    configure_logging(level);
+    let config = Config::load(&matches);
 
-    if let Err(e) = start_client(matches) {
-        error!("failed to start client: error='{}'", e);
+    // Flags override the logging configuration, which is used even if the rest of the
+    // configuration didn't load so that the error can be logged.
+    let mut logging = config.as_ref().map(|config| config.logging.clone()).unwrap_or_default();
+    if let Some(level) = matches.value_of("log-level") {
+        logging.level = level.to_owned();
+    }
+    if let Some(format) = matches.value_of("log-format").and_then(LogFormat::from_name) {
+        logging.format = format;
+    }
+    if let Err(e) = configure_logging(&logging) {
+        // Log the error with the default configuration, which is always valid.
+        if configure_logging(&LoggingConfig::default()).is_ok() {
+            error!("failed to configure logging: error='{}'", e);
+        }
+        exit(1);
     }
+
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("failed to load configuration: error='{}'", e);
+            exit(1);
+        },
+    };
+
+    match matches.subcommand() {
+        ("config", Some(arguments)) => {
+            if let ("check", Some(_)) = arguments.subcommand() {
+                if let Err(e) = check_config(&config) {
+                    error!("configuration is invalid: error='{}'", e);
+                    exit(1);
+                }
+            }
+        },
+        _ => {
+            config.apply_arguments(&matches);
+            if let Err(e) = start_client(config) {
+                error!("failed to start client: error='{}'", e);
+            }
+        },
+    }
+}
+
+/// Validate the configuration and print the effective configuration.
+fn check_config(config: &Config) -> Result<(), Error> {
+    config.validate()?;
+    print!("{}", render_config(config)?);
+    Ok(())
 }
 
-fn start_client(arguments: ArgMatches) -> Result<(), Error> {
+fn start_client(config: Config) -> Result<(), Error> {
+    config.validate()?;
     let system = System::new(crate_name!());
 
-    let bind_address = arguments.value_of("bind-address").ok_or(
-        ErrorKind::MissingBindAddressArgument)?.to_owned();
-    let redis_address = arguments.value_of("redis-address").ok_or(
-        ErrorKind::MissingRedisAddressArgument)?.to_owned();
-    let server_address = arguments.value_of("server-address").ok_or(
-        ErrorKind::MissingWebsocketServerArgument)?.to_owned();
-    let script_path = arguments.value_of("input").ok_or(
-        ErrorKind::MissingLuaScriptArgument)?.to_owned();
+    let script_path = config.service.script.clone().ok_or(
+        ErrorKind::MissingLuaScriptArgument)?;
+    let encoding = config.encoding()?;
+    let server_address = config.service.server.clone();
 
     info!("starting websocket client: server='{}'", server_address);
-    let interpreter: SyncAddress<_> = match Interpreter::launch(script_path, redis_address) {
+    let interpreter: SyncAddress<_> = match Interpreter::launch(script_path,
+                                                                config.service.redis.clone()) {
         Ok(interpreter) => interpreter,
         Err(e) => {
             error!("failed to create interpreter: error='{:?}'", e);
@@ -101,9 +164,9 @@ fn start_client(arguments: ArgMatches) -> Result<(), Error> {
     };
 
     // Start the webserver, it needs the address of the interpreter.
-    start_webserver(bind_address, interpreter.clone())?;
+    start_webserver(config.service.bind.clone(), interpreter.clone())?;
 
-    Client::launch(server_address, interpreter.clone())?;
+    Client::launch(server_address, interpreter.clone(), encoding)?;
 
     system.run();
     Ok(())
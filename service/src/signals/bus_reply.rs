@@ -0,0 +1,58 @@
+use actix::{Context, Handler, ResponseType};
+use common::schemas::Reply;
+use failure::{Error, Fail, ResultExt};
+use rlua::{Function, Value as LuaValue};
+use serde_json::to_string_pretty;
+
+use error::ErrorKind;
+use interpreter::{Interpreter, json_to_lua};
+
+/// The `BusReply` signal is sent from the client to the interpreter when a reply is received for
+/// a request that was sent with `bus:request`.
+pub struct BusReply {
+    pub message: Reply,
+}
+
+impl ResponseType for BusReply {
+    type Item = ();
+    type Error = ();
+}
+
+impl Interpreter {
+    fn handle_bus_reply(&mut self, reply: BusReply) -> Result<(), Error> {
+        let parsed = reply.message;
+        trace!("received reply: message=\n{}", to_string_pretty(&parsed)?);
+
+        // The id of a request from Lua is the registry key of its callback.
+        let function: Function = self.lua.named_registry_value(&parsed.request_id).context(
+            ErrorKind::MissingReplyHandlerRegistryValue)?;
+        self.lua.set_named_registry_value(&parsed.request_id, LuaValue::Nil).context(
+            ErrorKind::MissingReplyHandlerRegistryValue)?;
+
+        let data = if parsed.data.is_null() {
+            LuaValue::Nil
+        } else {
+            LuaValue::Table(json_to_lua(&self.lua, parsed.data).context(
+                ErrorKind::ParseReplyMessage)?)
+        };
+
+        debug!("calling reply handler");
+        if let Err(e) = function.call::<_, ()>((data, parsed.error)) {
+            error!("failure running reply handler: \n\n{}\n", e);
+            return Err(Error::from(e.context(ErrorKind::FailedReplyHandler)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<BusReply> for Interpreter {
+    type Result = ();
+
+    fn handle(&mut self, reply: BusReply, _: &mut Context<Self>) {
+        info!("received bus reply signal from client");
+        if let Err(e) = self.handle_bus_reply(reply) {
+            error!("processing bus reply: error='{}'", e);
+        }
+    }
+}
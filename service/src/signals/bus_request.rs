@@ -0,0 +1,78 @@
+use actix::{Context, Handler, ResponseType};
+use common::schemas::{Message, Reply, Request as RequestSchema};
+use failure::{Error, Fail, ResultExt};
+use rlua::{Function, Value as LuaValue};
+use serde_json::{Value, to_string_pretty};
+
+use error::ErrorKind;
+use interpreter::{Bus, Interpreter, json_to_lua, lua_to_json};
+use signals::SendMessage;
+
+/// The `BusRequest` signal is sent from the client to the interpreter when another client sends
+/// a request to this client type through the event bus.
+pub struct BusRequest {
+    pub message: RequestSchema,
+}
+
+impl ResponseType for BusRequest {
+    type Item = ();
+    type Error = ();
+}
+
+impl Interpreter {
+    fn call_request_handler(&mut self, request: RequestSchema) -> Result<Value, Error> {
+        let globals = self.lua.globals();
+        let bus: Bus = globals.get::<_, Bus>("bus").context(ErrorKind::MissingBusUserData)?;
+        let key = bus.request_handlers.get(&request.request_type).ok_or(
+            Error::from(ErrorKind::MissingRequestHandlerRegistryValue))?;
+        let function: Function = self.lua.named_registry_value(key).context(
+            ErrorKind::MissingRequestHandlerRegistryValue)?;
+
+        debug!("calling request handler");
+        let data = json_to_lua(&self.lua, request.data).context(ErrorKind::ParseRequestMessage)?;
+        let args = (request.request_type, data, request.key);
+        match function.call::<_, LuaValue>(args) {
+            Ok(LuaValue::Nil) => Ok(Value::Null),
+            Ok(LuaValue::Table(t)) => Ok(lua_to_json(&self.lua, t).context(
+                ErrorKind::ParseRequestHandlerResult)?),
+            Ok(_) => Err(Error::from(ErrorKind::RequestHandlerInvalidReturnType)),
+            Err(e) => {
+                error!("failure running request handler: \n\n{}\n", e);
+                Err(Error::from(e.context(ErrorKind::FailedRequestHandler)))
+            },
+        }
+    }
+
+    fn handle_bus_request(&mut self, request: BusRequest) -> Result<(), Error> {
+        let parsed = request.message;
+        trace!("received request: message=\n{}", to_string_pretty(&parsed)?);
+        let request_id = parsed.request_id.clone();
+
+        let reply = match self.call_request_handler(parsed) {
+            Ok(data) => Reply { request_id: request_id, data: data, error: None },
+            Err(e) => {
+                warn!("replying with error: request_id='{}' error='{}'", request_id, e);
+                Reply { request_id: request_id, data: Value::Null, error: Some(format!("{}", e)) }
+            },
+        };
+
+        if let Some(ref client) = self.client {
+            info!("sending reply: request_id='{}'", reply.request_id);
+            client.send(SendMessage(Message::Reply(reply)));
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::ClientNotLinkedToInterpreter))
+        }
+    }
+}
+
+impl Handler<BusRequest> for Interpreter {
+    type Result = ();
+
+    fn handle(&mut self, request: BusRequest, _: &mut Context<Self>) {
+        info!("received bus request signal from client");
+        if let Err(e) = self.handle_bus_request(request) {
+            error!("processing bus request: error='{}'", e);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use actix::{Context, Handler, ResponseType};
+use common::schemas::{Cancel as CancelSchema, EventId, Message};
+use failure::Error;
+
+use error::ErrorKind;
+use interpreter::Interpreter;
+use signals::SendMessage;
+
+/// The `Cancel` signal is sent from the interpreter to the client when a scheduled event should
+/// be cancelled.
+pub struct Cancel {
+    pub event_id: EventId,
+}
+
+impl ResponseType for Cancel {
+    type Item = ();
+    type Error = ();
+}
+
+impl Interpreter {
+    fn send_cancel(&mut self, cancel: Cancel) -> Result<(), Error> {
+        if let Some(ref client) = self.client {
+            info!("sending cancel message: event_id='{}'", cancel.event_id);
+            client.send(SendMessage(Message::Cancel(CancelSchema {
+                event_id: cancel.event_id,
+                message_id: None,
+            })));
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::ClientNotLinkedToInterpreter))
+        }
+    }
+}
+
+impl Handler<Cancel> for Interpreter {
+    type Result = ();
+
+    fn handle(&mut self, cancel: Cancel, _: &mut Context<Self>) {
+        info!("received cancel signal from interpreter");
+        if let Err(e) = self.send_cancel(cancel) {
+            error!("unable to cancel event: error='{}'", e);
+        }
+    }
+}
@@ -1,24 +1,18 @@
-use std::collections::hash_map::Entry;
-
 use actix::{Context, Handler, ResponseType};
-use common::schemas::{
-    ConsistencyKey,
-    ConsistencyValue,
-    Event as EventSchema,
-};
+use common::schemas::{Ack, Event as EventSchema, Message};
 use failure::{Error, Fail, ResultExt};
 use redis::Commands;
-use rlua::Function;
-use serde_json::{from_str, to_string_pretty};
+use rlua::{Function, Value as LuaValue};
+use serde_json::to_string_pretty;
 
 use error::ErrorKind;
-use interpreter::{TIMESTAMP_KEY, Bus, Interpreter, json_to_lua};
+use interpreter::{CURRENT_EVENT_KEY, TIMESTAMP_KEY, Bus, Interpreter, json_to_lua};
 use signals::SendMessage;
 
 /// The `Event` signal is sent from the client to the interpreter when a new event is received from
 /// the event bus.
 pub struct Event {
-    pub message: String,
+    pub message: EventSchema,
 }
 
 impl ResponseType for Event {
@@ -27,41 +21,6 @@ impl ResponseType for Event {
 }
 
 impl Interpreter {
-    pub fn increment_consistency_if_required(&mut self, key: ConsistencyKey,
-                                             value: ConsistencyValue) -> Result<(), Error> {
-        // We don't want to increment if the value was implicit, this can happen from receipts.
-        if let ConsistencyValue::Implicit = value {
-            debug!("ignoring implicit consistency increment");
-            return Ok(());
-        }
-
-        // Increment the consistency value if the consistency value in this event is higher than
-        // what we have locally.
-        debug!("checking for consistency increment");
-        match self.consistency.entry(key) {
-            Entry::Occupied(mut entry) => {
-                let is_higher = {
-                    let current = entry.get();
-                    debug!("current consistency value for key: current='{}' \
-                           received_value='{}'", current, value);
-                    value > *current
-                };
-                debug!("higher consistency value: is_higher='{}'", is_higher);
-
-                if is_higher {
-                    debug!("replacing value with new consistency value: value='{}'", value);
-                    entry.insert(value);
-                }
-            },
-            Entry::Vacant(entry) => {
-                debug!("new consistency key, inserting new value: value='{}'", value);
-                entry.insert(value.clone());
-                ()
-            },
-        }
-        Ok(())
-    }
-
     pub fn save_timestamp_for_query(&mut self, event: &EventSchema) -> Result<(), Error> {
         let key = String::from(TIMESTAMP_KEY);
         let value = event.timestamp_raw.ok_or(Error::from(ErrorKind::NoTimestampProvided))?;
@@ -70,12 +29,11 @@ impl Interpreter {
             ErrorKind::RedisPersist).map_err(Error::from)
     }
 
-    fn respond_with_acknowledgement(&self, mut acknowledgement: EventSchema) -> Result<(), Error> {
+    fn respond_with_acknowledgement(&self, acknowledgement: Ack) -> Result<(), Error> {
         // Respond with an acknowledgement.
-        acknowledgement.message_type = Some(String::from("ack"));
         if let Some(ref client) = self.client {
-            info!("responding with acknowledgement");
-            client.send(SendMessage(acknowledgement));
+            info!("responding with acknowledgement: event_id='{}'", acknowledgement.event_id);
+            client.send(SendMessage(Message::Ack(acknowledgement)));
             Ok(())
         } else {
             Err(Error::from(ErrorKind::ClientNotLinkedToInterpreter))
@@ -83,17 +41,16 @@ impl Interpreter {
     }
 
     fn handle_event(&mut self, event: Event) -> Result<(), Error> {
-        let parsed: EventSchema = from_str(&event.message).context(ErrorKind::ParseEventMessage)?;
-        debug!("received event: message=\n{}", to_string_pretty(&parsed)?);
+        let parsed = event.message;
+        trace!("received event: message=\n{}", to_string_pretty(&parsed)?);
         // We'll send this if handler succeeds.
-        let acknowledgement = parsed.clone();
+        let acknowledgement = Ack { event_id: parsed.id.clone() };
 
         debug!("saving timestamp for query");
         self.save_timestamp_for_query(&parsed)?;
 
         debug!("checking consistency updates from event");
-        self.increment_consistency_if_required(parsed.consistency.key.clone(),
-                                               parsed.consistency.value)?;
+        self.consistency.observe(parsed.consistency.key.clone(), parsed.consistency.value);
 
         let globals = self.lua.globals();
         let bus: Bus = globals.get::<_, Bus>("bus").context(ErrorKind::MissingBusUserData)?;
@@ -106,8 +63,16 @@ impl Interpreter {
                 let data = json_to_lua(&self.lua, parsed.data).context(
                     ErrorKind::ParseEventMessage)?;
                 let args = (parsed.event_type, parsed.consistency.key, parsed.correlation_id,
-                            data, parsed.timestamp_raw);
-                if let Err(e) = function.call::<_, ()>(args) {
+                            data, parsed.timestamp_raw, parsed.metadata, parsed.id.clone(),
+                            parsed.causation_id);
+
+                self.lua.set_named_registry_value(CURRENT_EVENT_KEY, parsed.id).context(
+                    ErrorKind::SetCurrentEvent)?;
+                let result = function.call::<_, ()>(args);
+                self.lua.set_named_registry_value(CURRENT_EVENT_KEY, LuaValue::Nil).context(
+                    ErrorKind::SetCurrentEvent)?;
+
+                if let Err(e) = result {
                     error!("failure running event hander: \n\n{}\n", e);
                     return Err(Error::from(e.context(ErrorKind::FailedEventHandler)));
                 }
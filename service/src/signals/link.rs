@@ -2,7 +2,7 @@ use std::process::exit;
 
 use actix::{AsyncContext, Context, Handler, SyncAddress, ResponseType};
 use chrono::{DateTime, NaiveDateTime, Utc};
-use common::schemas::{Register, Query};
+use common::schemas::{Message, Register, Query};
 use failure::{Error, ResultExt};
 use redis::Commands;
 use rlua::Table;
@@ -52,11 +52,11 @@ impl Interpreter {
         let as_datetime = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(value, 0), Utc);
         debug!("querying for timestamp: value='{}' corresponding_date='{}'", value, as_datetime);
 
-        client.send(SendMessage(Query {
+        client.send(SendMessage(Message::Query(Query {
             event_types,
             since: as_datetime.to_rfc3339(),
-            message_type: String::from("query"),
-        }));
+            message_id: None,
+        })));
 
         Ok(())
     }
@@ -71,11 +71,14 @@ impl Interpreter {
         let register = Register {
             client_type,
             event_types,
-            message_type: String::from("register"),
+            predicates: bus.predicates,
+            delivery_mode: bus.delivery_mode,
+            message_id: None,
         };
 
-        info!("sending register message to server: message=\n{}", to_string_pretty(&register)?);
-        client.send(SendMessage(register));
+        info!("sending register message to server");
+        trace!("sending register message to server: message=\n{}", to_string_pretty(&register)?);
+        client.send(SendMessage(Message::Register(register)));
         Ok(())
     }
 
@@ -1,3 +1,6 @@
+mod bus_reply;
+mod bus_request;
+mod cancel;
 mod event;
 mod link;
 mod new_event;
@@ -6,7 +9,11 @@ mod receipt;
 mod registration;
 mod request;
 mod send_message;
+mod send_request;
 
+pub use self::bus_reply::BusReply;
+pub use self::bus_request::BusRequest;
+pub use self::cancel::Cancel;
 pub use self::event::Event;
 pub use self::link::Link;
 pub use self::new_event::NewEvent;
@@ -15,3 +22,4 @@ pub use self::receipt::Receipt;
 pub use self::registration::Registration;
 pub use self::request::Request;
 pub use self::send_message::SendMessage;
+pub use self::send_request::SendRequest;
@@ -1,11 +1,13 @@
-use std::collections::hash_map::Entry;
+use std::time::Duration;
 
-use actix::{Context, Handler, ResponseType};
-use common::hash_json;
+use actix::{AsyncContext, Context, Handler, ResponseType};
 use common::schemas::{
+    Command,
     Consistency,
     ConsistencyKey,
-    ConsistencyValue,
+    EventId,
+    Message,
+    Metadata,
     NewEvent as NewEventSchema,
     NewEvents,
 };
@@ -17,14 +19,29 @@ use error::ErrorKind;
 use interpreter::Interpreter;
 use signals::SendMessage;
 
+/// Generated correlation ids are kept below 2^53 so that they are integers when passed to Lua and
+/// are exact for JSON clients that read numbers as doubles.
+const CORRELATION_ID_LIMIT: u64 = 1 << 53;
+
 /// The `NewEvent` signal is sent from the interpreter to the client when a new event needs to
 /// be sent to the event bus.
 pub struct NewEvent {
+    pub causation_id: Option<EventId>,
     pub consistency_key: ConsistencyKey,
     pub data: Value,
     pub event_type: String,
+    pub idempotency_key: Option<String>,
     pub implicit: bool,
-    pub correlation_id: Option<u32>,
+    pub correlation_id: Option<u64>,
+    pub deliver_at: Option<String>,
+    pub delay: Option<u64>,
+    pub metadata: Metadata,
+    pub schema_version: Option<u32>,
+    /// This field contains how many milliseconds to wait before sending the event to the event
+    /// bus, such as when retrying an event that was rate limited.
+    pub send_after_ms: Option<u64>,
+    /// This field contains the client type that the event is a command for, if it is one.
+    pub target: Option<String>,
 }
 
 impl ResponseType for NewEvent {
@@ -33,76 +50,54 @@ impl ResponseType for NewEvent {
 }
 
 impl Interpreter {
-    pub fn send_new_event(&mut self, consistency_key: ConsistencyKey, data: Value,
-                          event_type: String, implicit: bool,
-                          correlation_id: Option<u32>) -> Result<(), Error> {
-        let correlation_id = if let Some(correlation_id) = correlation_id {
+    pub fn send_new_event(&mut self, new_event: NewEvent) -> Result<(), Error> {
+        let correlation_id = if let Some(correlation_id) = new_event.correlation_id {
             debug!("using existing correlation id: id='{}'", correlation_id);
             correlation_id
         } else {
             debug!("generating new correlation id");
-            self.rng.borrow_mut().gen::<u32>()
+            self.rng.borrow_mut().gen_range(0, CORRELATION_ID_LIMIT)
         };
 
         debug!("checking consistency for send");
-        let consistency_value = if implicit {
-            debug!("implicit consistency");
-            ConsistencyValue::Implicit
-        } else {
-            match self.consistency.entry(consistency_key.clone()) {
-                Entry::Occupied(mut entry) => {
-                    // We increment the consistency when we send events. As far as I can tell,
-                    // there isn't a reason this will break.
-                    //
-                    // As long as we never send an event with a consistency key higher than
-                    // expected, this should be fine.
-                    let next_value = {
-                        match entry.get() {
-                            &ConsistencyValue::Explicit(v) => {
-                                debug!("existing consistency key: existing_value='{}' \
-                                       sending_value='{}'", v, v + 1);
-                                v + 1
-                            },
-                            _ => return Err(Error::from(ErrorKind::ImplicitConsistencyInMap)),
-                        }
-                    };
-
-                    let value = ConsistencyValue::Explicit(next_value);
-                    debug!("using and updating consistency value: value='{}'", next_value);
-                    entry.insert(value.clone());
-                    value
-                },
-                Entry::Vacant(entry) => {
-                    let initial = ConsistencyValue::Explicit(0);
-                    debug!("new consistency key: value='{}'", initial);
-                    entry.insert(initial.clone());
-                    initial
-                }
-            }
-        };
+        let consistency_key = new_event.consistency_key;
+        let consistency_value = self.consistency.next(consistency_key.clone(),
+                                                      new_event.implicit)?;
 
         let consistency = Consistency {
             key: consistency_key,
             value: consistency_value
         };
 
+        let message_id = self.receipt_lookup.next_message_id();
         let event = NewEventSchema {
+            causation_id: new_event.causation_id,
             consistency: consistency,
             correlation_id: correlation_id,
-            data: data.clone(),
-            event_type: event_type,
+            data: new_event.data,
+            deliver_at: new_event.deliver_at,
+            delay: new_event.delay,
+            event_type: new_event.event_type,
+            metadata: new_event.metadata,
+            schema_version: new_event.schema_version,
+            message_id: Some(message_id.clone()),
+            expected_versions: Vec::new(),
+            idempotency_key: new_event.idempotency_key,
         };
 
-        let message = NewEvents {
-            events: vec![ event.clone() ],
-            message_type: "new".to_owned(),
+        let message = match new_event.target {
+            Some(client_type) => Message::Command(Command {
+                client_type: client_type,
+                command: event.clone(),
+            }),
+            None => Message::NewEvents(NewEvents {
+                events: vec![ event.clone() ],
+                atomic: false,
+            }),
         };
 
-        if let Some(_) = self.receipt_lookup.insert(hash_json(&data)?, event) {
-            info!("replaced event in receipt lookup - hash collision?");
-        } else {
-            info!("added event to receipt lookup");
-        }
+        self.receipt_lookup.track(message_id.clone(), event);
+        info!("added event to receipt lookup: message_id='{}'", message_id);
 
         if let Some(ref client) = self.client {
             debug!("sending send message signal");
@@ -117,15 +112,19 @@ impl Interpreter {
 impl Handler<NewEvent> for Interpreter {
     type Result = ();
 
-    fn handle(&mut self, new_event: NewEvent, _: &mut Context<Self>) {
+    fn handle(&mut self, new_event: NewEvent, ctx: &mut Context<Self>) {
         info!("received new event signal from interpreter");
-        if let Err(e) = self.send_new_event(
-            new_event.consistency_key,
-            new_event.data,
-            new_event.event_type,
-            new_event.implicit,
-            new_event.correlation_id,
-        ) {
+        if let Some(send_after_ms) = new_event.send_after_ms {
+            debug!("waiting to send new event: send_after_ms='{}'", send_after_ms);
+            ctx.run_later(Duration::from_millis(send_after_ms), move |interpreter, _| {
+                if let Err(e) = interpreter.send_new_event(new_event) {
+                    error!("unable to send new event: error='{}'", e);
+                }
+            });
+            return;
+        }
+
+        if let Err(e) = self.send_new_event(new_event) {
             error!("unable to send new event: error='{}'", e);
         }
     }
@@ -2,7 +2,7 @@ use actix::{Context, Handler, ResponseType};
 use common::schemas::{NewEvent, Receipts};
 use failure::{Error, ResultExt};
 use rlua::Function;
-use serde_json::{from_str, to_string_pretty};
+use serde_json::to_string_pretty;
 
 use error::ErrorKind;
 use interpreter::{Bus, Interpreter, json_to_lua};
@@ -10,7 +10,7 @@ use interpreter::{Bus, Interpreter, json_to_lua};
 /// The `Receipt` signal is sent from the client to the interpreter when a new receipt is received
 /// from the event bus.
 pub struct Receipt {
-    pub message: String,
+    pub message: Receipts,
 }
 
 impl ResponseType for Receipt {
@@ -20,8 +20,8 @@ impl ResponseType for Receipt {
 
 impl Interpreter {
     fn handle_receipt(&mut self, receipt: Receipt) -> Result<(), Error> {
-        let parsed: Receipts = from_str(&receipt.message).context(ErrorKind::ParseReceiptMessage)?;
-        debug!("received receipt: message=\n{}", to_string_pretty(&parsed)?);
+        let parsed = receipt.message;
+        trace!("received receipt: message=\n{}", to_string_pretty(&parsed)?);
 
         let bus: Bus = {
             let globals = self.lua.globals();
@@ -29,18 +29,31 @@ impl Interpreter {
         };
 
         for receipt in parsed.receipts {
-            let event: NewEvent = match self.receipt_lookup.get(&receipt.checksum) {
-                Some(event) => event.clone(),
+            let pending = match receipt.message_id {
+                Some(ref message_id) => self.receipt_lookup.resolve(message_id),
+                None => None,
+            };
+            let event: NewEvent = match pending {
+                Some(event) => event,
                 None => {
-                    error!("receipt not found in hashmap: hash='{}'", receipt.checksum);
+                    error!("receipt not found in receipt lookup: message_id='{:?}'",
+                           receipt.message_id);
                     continue;
                 },
             };
             debug!("matched event in receipt: message=\n{}", to_string_pretty(&event)?);
 
             debug!("checking consistency updates from receipt");
-            self.increment_consistency_if_required(event.consistency.key.clone(),
-                                                   event.consistency.value)?;
+            if receipt.status == "success" {
+                let consistency = receipt.consistency.clone()
+                    .unwrap_or(event.consistency.clone());
+                self.consistency.observe(consistency.key, consistency.value);
+            } else {
+                // The event bus did not use the value that was sent, so the next event for the
+                // key is sent with it again.
+                self.consistency.reject(event.consistency.key.clone(),
+                                        event.consistency.value.clone());
+            }
 
             match bus.receipt_handlers.get(&event.event_type.clone()) {
                 Some(key) => {
@@ -51,7 +64,8 @@ impl Interpreter {
                     let data = json_to_lua(&self.lua, event.data).context(
                         ErrorKind::ParseReceiptMessage)?;
                     let args = (receipt.status, event.event_type, event.consistency.key,
-                                event.correlation_id, data);
+                                event.correlation_id, data, receipt.event_id,
+                                receipt.retry_after_ms);
                     if let Err(e) = function.call::<_, ()>(args) {
                         error!("failure running receipt handler: \n\n{}\n", e);
                     }
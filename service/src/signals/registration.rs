@@ -1,11 +1,12 @@
 use actix::{Context, Handler, ResponseType};
+use common::schemas::Registration as RegistrationSchema;
 
 use interpreter::Interpreter;
 
 /// The `Registration` signal is sent from the client to the interpreter when registration
 /// confirmation is received from the event bus.
 pub struct Registration {
-    pub message: String,
+    pub message: RegistrationSchema,
 }
 
 impl ResponseType for Registration {
@@ -16,7 +17,11 @@ impl ResponseType for Registration {
 impl Handler<Registration> for Interpreter {
     type Result = ();
 
-    fn handle(&mut self, _message: Registration, _: &mut Context<Self>) {
+    fn handle(&mut self, message: Registration, _: &mut Context<Self>) {
         info!("received registration signal from client");
+        if let Some(error) = message.message.error {
+            error!("registration refused by event bus: client_type='{}' error='{}'",
+                   message.message.client_type, error);
+        }
     }
 }
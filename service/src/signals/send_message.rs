@@ -1,42 +1,35 @@
 use actix::{Context, Handler, ResponseType};
+use common::schemas::Message;
 use failure::{Error, ResultExt};
-use serde::Serialize;
-use serde_json::{to_string, to_string_pretty};
-use websocket::OwnedMessage;
+use serde_json::to_string_pretty;
 
 use client::Client;
 use error::ErrorKind;
 
 /// The `SendMessage` signal is sent from the interpreter to the client when a new message needs to
 /// be sent to the event bus.
-pub struct SendMessage<T: 'static + Serialize + Send>(pub T);
+pub struct SendMessage(pub Message);
 
-impl<T> ResponseType for SendMessage<T>
-    where T: Serialize + Send
-{
+impl ResponseType for SendMessage {
     type Item = ();
     type Error = ();
 }
 
 impl Client {
-    pub fn send_message<T: Serialize>(&mut self, message: T) -> Result<(), Error> {
-        let serialized = to_string(&message).context(
-            ErrorKind::SerializeJsonForSending)?;
-        let pretty_serialized = to_string_pretty(&message).context(
-            ErrorKind::SerializeJsonForSending)?;
+    pub fn send_message(&mut self, message: Message) -> Result<(), Error> {
+        trace!("sending message: message_type='{}' message=\n{}", message.message_type(),
+               to_string_pretty(&message).context(ErrorKind::SerializeJsonForSending)?);
 
-        info!("sending message: message=\n{}", pretty_serialized);
-        self.framed.send(OwnedMessage::Text(serialized));
+        let frame = self.encoding.encode(&message)?;
+        self.framed.send(frame);
         Ok(())
     }
 }
 
-impl<T> Handler<SendMessage<T>> for Client
-    where T: Serialize + Send
-{
+impl Handler<SendMessage> for Client {
     type Result = ();
 
-    fn handle(&mut self, message: SendMessage<T>, _: &mut Context<Self>) {
+    fn handle(&mut self, message: SendMessage, _: &mut Context<Self>) {
         info!("received send message signal from interpreter");
         if let Err(e) = self.send_message(message.0) {
             error!("unable to send to message on websockets: error='{}'", e);
@@ -0,0 +1,42 @@
+use actix::{Context, Handler, ResponseType};
+use common::schemas::{Message, Request as RequestSchema};
+use failure::Error;
+
+use error::ErrorKind;
+use interpreter::Interpreter;
+use signals::SendMessage;
+
+/// The `SendRequest` signal is sent from the interpreter to the client when a request needs to be
+/// sent to another client type.
+pub struct SendRequest {
+    pub message: RequestSchema,
+}
+
+impl ResponseType for SendRequest {
+    type Item = ();
+    type Error = ();
+}
+
+impl Interpreter {
+    fn send_request(&mut self, request: SendRequest) -> Result<(), Error> {
+        if let Some(ref client) = self.client {
+            info!("sending request message: request_id='{}' client_type='{}'",
+                  request.message.request_id, request.message.client_type);
+            client.send(SendMessage(Message::Request(request.message)));
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::ClientNotLinkedToInterpreter))
+        }
+    }
+}
+
+impl Handler<SendRequest> for Interpreter {
+    type Result = ();
+
+    fn handle(&mut self, request: SendRequest, _: &mut Context<Self>) {
+        info!("received send request signal from interpreter");
+        if let Err(e) = self.send_request(request) {
+            error!("unable to send request: error='{}'", e);
+        }
+    }
+}